@@ -0,0 +1,18 @@
+fn main() {
+    #[cfg(feature = "ffi")]
+    generate_header();
+}
+
+/// Writes the header of the C API into the output directory, `include/teestatus.h` is
+/// the committed copy.
+#[cfg(feature = "ffi")]
+fn generate_header() {
+    let dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    let out = std::env::var("OUT_DIR").unwrap();
+    println!("cargo:rerun-if-changed=src/ffi.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+
+    cbindgen::generate(&dir)
+        .expect("can't generate the C header")
+        .write_to_file(format!("{}/teestatus.h", out));
+}
@@ -1,5 +1,4 @@
 use std::borrow::Cow;
-use std::net::UdpSocket;
 use std::time::Duration;
 use teestatus::*;
 
@@ -31,20 +30,28 @@ fn main() {
 
     let timeout = 250;
 
-    let sock = UdpSocket::bind("0.0.0.0:0").expect("can't bind socket");
-    sock.set_write_timeout(Some(Duration::from_millis(timeout)))
+    let socks = DualStackSocket::bind().expect("can't bind sockets");
+    socks
+        .set_write_timeout(Some(Duration::from_millis(timeout)))
         .unwrap();
-    sock.set_read_timeout(Some(Duration::from_millis(timeout)))
+    socks
+        .set_read_timeout(Some(Duration::from_millis(timeout)))
         .unwrap();
+    println!("Usable address families: {:?}", usable_families());
 
-    let mut servers = master3.get_server_list(&sock).unwrap();
-    println!("Loaded {}", servers.len());
-    servers.extend(&master4.get_server_list(&sock).unwrap());
-    println!("Loaded {}", servers.len());
-    servers.extend(&master2.get_server_list(&sock).unwrap());
-    println!("Loaded {}", servers.len());
-    servers.extend(&master1.get_server_list(&sock).unwrap());
-    println!("Loaded {}", servers.len());
+    let mut scan = Scan::new();
+    let mut servers = std::collections::HashSet::new();
+
+    for master in &[master3, master4, master2, master1] {
+        let list = socks.get_server_list(master).unwrap();
+        servers.extend(&list);
+        println!("Loaded {}", servers.len());
+
+        scan.masters.push(MasterList {
+            master: format!("{}:{}", master.hostname, master.port),
+            servers: list.iter().map(|&x| x.into()).collect(),
+        });
+    }
 
     let mut server_info_buffers = Vec::with_capacity(servers.len());
 
@@ -52,35 +59,38 @@ fn main() {
         server_info_buffers.push(ServerInfo::create_buffers());
     }
 
-    let mut server_infos: Vec<ServerInfo> = vec![];
-
     let mut buffer_iter = server_info_buffers.iter_mut();
 
-    for (ip, port) in servers.iter() {
-        let addr = format!("{}:{}", ip.to_string(), port);
-        if let Ok(_) = sock.connect(addr.clone()) {
-            match ServerInfo::new(&sock, buffer_iter.next().unwrap()) {
-                Ok(info) => {
-                    println!("Loaded server '{}'", info.name);
-                    println!(
-                        "Server has {} connected players ({}/{})",
-                        info.players.len(),
-                        info.client_count,
-                        info.max_client_count
-                    );
-                    server_infos.push(info);
-                }
-                Err(e) => {
-                    println!("Error loading server: {}", addr);
-                    println!("Error: {:?}", e);
-                }
+    for &(ip, port) in servers.iter() {
+        let addr = (ip, port).into();
+        match socks.query(addr, buffer_iter.next().unwrap()) {
+            Ok(entry) => {
+                let info = &entry.info;
+                println!("Loaded server '{}'", info.name);
+                println!(
+                    "Server has {} connected players ({}/{})",
+                    info.players.len(),
+                    info.client_count,
+                    info.max_client_count
+                );
+                scan.servers.push(entry.into_owned());
+            }
+            Err(e) => {
+                println!("Error loading server: {}", addr);
+                println!("Error: {:?}", e);
             }
         }
     }
 
     println!(
         "Loaded {} servers out of {}.",
-        server_infos.len(),
+        scan.servers.len(),
         servers.len()
     );
+
+    // Pass a path to keep the scan, e.g `cargo run --example info -- scan.bin`.
+    if let Some(path) = std::env::args().nth(1) {
+        scan.save(&path).unwrap();
+        println!("Saved scan to {}", path);
+    }
 }
@@ -0,0 +1,153 @@
+/*
+ * Exercises the C API against a stand-in server forked from this program.
+ *
+ * Built and run from the repository root by CI:
+ *   cargo rustc --lib --features ffi --crate-type cdylib,staticlib
+ *   cc ffi/test.c -Iinclude -Ltarget/debug -lteestatus -o target/ffi-test
+ *   LD_LIBRARY_PATH=target/debug target/ffi-test
+ */
+#include <arpa/inet.h>
+#include <netinet/in.h>
+#include <signal.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/socket.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+#include "teestatus.h"
+
+#define CHECK(cond)                                                   \
+  do {                                                                \
+    if (!(cond)) {                                                    \
+      fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, \
+              #cond);                                                 \
+      return 1;                                                       \
+    }                                                                 \
+  } while (0)
+
+static size_t read_sample(const char *path, unsigned char *buf, size_t size) {
+  FILE *f = fopen(path, "rb");
+  size_t len;
+  if (!f) {
+    perror(path);
+    _exit(1);
+  }
+  len = fread(buf, 1, size, f);
+  fclose(f);
+  return len;
+}
+
+/* Rewrites the token of a sample reply to answer the request. */
+static size_t answer(const unsigned char *sample, size_t sample_len,
+                     const unsigned char *request, unsigned char *reply) {
+  int token = request[14] | ((request[2] << 8 | request[3]) << 8);
+  size_t end = 14 + strlen((const char *)sample + 14);
+  size_t len = 14;
+
+  memcpy(reply, sample, 14);
+  len += sprintf((char *)reply + 14, "%d", token);
+  memcpy(reply + len, sample + end, sample_len - end);
+  return len + sample_len - end;
+}
+
+/* Answers info requests with the samples, and list requests like a master with a
+ * single server. */
+static void stand_in(int sock) {
+  unsigned char main_sample[1400], more_sample[1400];
+  size_t main_len = read_sample("src/samples/server_info.data", main_sample, 1400);
+  size_t more_len = read_sample("src/samples/server_info_more.data", more_sample, 1400);
+  unsigned char request[1400], reply[1400];
+  struct sockaddr_in from;
+  socklen_t from_len;
+  ssize_t len;
+
+  for (;;) {
+    from_len = sizeof(from);
+    len = recvfrom(sock, request, sizeof(request), 0, (struct sockaddr *)&from, &from_len);
+    if (len < 14)
+      continue;
+
+    memset(reply, 0xff, 10);
+    if (!memcmp(request + 10, "gie3", 4) && len >= 15) {
+      size_t n = answer(main_sample, main_len, request, reply);
+      sendto(sock, reply, n, 0, (struct sockaddr *)&from, from_len);
+      n = answer(more_sample, more_len, request, reply);
+      sendto(sock, reply, n, 0, (struct sockaddr *)&from, from_len);
+    } else if (!memcmp(request + 10, "cou2", 4)) {
+      memcpy(reply + 10, "siz2\x00\x01", 6);
+      sendto(sock, reply, 16, 0, (struct sockaddr *)&from, from_len);
+    } else if (!memcmp(request + 10, "req2", 4)) {
+      static const unsigned char entry[18] = {0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff,
+                                              10, 0, 0, 1, 0x20, 0x6f};
+      memcpy(reply + 10, "lis2", 4);
+      memcpy(reply + 14, entry, sizeof(entry));
+      sendto(sock, reply, 14 + sizeof(entry), 0, (struct sockaddr *)&from, from_len);
+    }
+  }
+}
+
+static int run(const char *address) {
+  TeestatusServerInfo *info = NULL;
+  TeestatusServerList *list = NULL;
+  TeestatusError error;
+
+  error = teestatus_query_info(address, 2000, &info);
+  if (error != TEESTATUS_ERROR_OK)
+    fprintf(stderr, "query failed: %s\n", teestatus_error_message(error));
+  CHECK(error == TEESTATUS_ERROR_OK);
+  CHECK(strcmp(info->map, "Multeasymap") == 0);
+  CHECK(strcmp(info->game_type, "DDraceNetwork") == 0);
+  CHECK(info->num_players == 63);
+  CHECK(info->has_map_checksum);
+  printf("%s: %s (%zu players)\n", address, info->name, info->num_players);
+  teestatus_server_info_free(info);
+
+  error = teestatus_get_server_list(address, 500, &list);
+  CHECK(error == TEESTATUS_ERROR_OK);
+  CHECK(list->len == 1);
+  CHECK(strcmp(list->addresses[0], "10.0.0.1:8303") == 0);
+  teestatus_server_list_free(list);
+
+  info = (TeestatusServerInfo *)1;
+  CHECK(teestatus_query_info("not an address", 100, &info) == TEESTATUS_ERROR_INVALID_ARGUMENT);
+  CHECK(info == NULL);
+  CHECK(teestatus_query_info(address, 100, NULL) == TEESTATUS_ERROR_INVALID_ARGUMENT);
+  teestatus_server_info_free(NULL);
+
+  return 0;
+}
+
+int main(void) {
+  struct sockaddr_in addr;
+  socklen_t addr_len = sizeof(addr);
+  char address[64];
+  int sock, result, status;
+  pid_t child;
+
+  sock = socket(AF_INET, SOCK_DGRAM, 0);
+  memset(&addr, 0, sizeof(addr));
+  addr.sin_family = AF_INET;
+  addr.sin_addr.s_addr = htonl(INADDR_LOOPBACK);
+  if (sock < 0 || bind(sock, (struct sockaddr *)&addr, sizeof(addr)) < 0 ||
+      getsockname(sock, (struct sockaddr *)&addr, &addr_len) < 0) {
+    perror("stand-in socket");
+    return 1;
+  }
+  snprintf(address, sizeof(address), "127.0.0.1:%d", ntohs(addr.sin_port));
+
+  child = fork();
+  if (child == 0) {
+    stand_in(sock);
+    _exit(0);
+  }
+  close(sock);
+
+  result = run(address);
+  kill(child, SIGTERM);
+  waitpid(child, &status, 0);
+
+  if (result == 0)
+    printf("ok\n");
+  return result;
+}
@@ -0,0 +1,128 @@
+#ifndef TEESTATUS_H
+#define TEESTATUS_H
+
+/* Generated by cbindgen from src/ffi.rs, do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * The error codes, mirroring [RequestError].
+ */
+typedef enum TeestatusError {
+  TEESTATUS_ERROR_OK = 0,
+  TEESTATUS_ERROR_DECODE_ERROR,
+  TEESTATUS_ERROR_PARSE_ERROR,
+  TEESTATUS_ERROR_IO_ERROR,
+  TEESTATUS_ERROR_MISSING,
+  TEESTATUS_ERROR_TIMEOUT,
+  TEESTATUS_ERROR_INVALID_DATA,
+  TEESTATUS_ERROR_AUTH_FAILED,
+  TEESTATUS_ERROR_DISCONNECTED,
+  TEESTATUS_ERROR_REFUSED,
+  TEESTATUS_ERROR_FULL,
+  TEESTATUS_ERROR_BANNED,
+  TEESTATUS_ERROR_TOKEN_ERROR,
+  /**
+   * A null pointer or an address that doesn't resolve.
+   */
+  TEESTATUS_ERROR_INVALID_ARGUMENT,
+} TeestatusError;
+
+typedef struct TeestatusPlayer {
+  char *name;
+  char *clan;
+  int32_t country;
+  int32_t score;
+  bool is_spectator;
+} TeestatusPlayer;
+
+typedef struct TeestatusServerInfo {
+  char *version;
+  char *name;
+  char *map;
+  char *game_type;
+  bool password;
+  int32_t flags;
+  int32_t player_count;
+  int32_t max_player_count;
+  int32_t client_count;
+  int32_t max_client_count;
+  /**
+   * Whether the server sent the map checksum and size.
+   */
+  bool has_map_checksum;
+  int32_t map_crc;
+  int32_t map_size;
+  uint32_t ping_ms;
+  struct TeestatusPlayer *players;
+  size_t num_players;
+} TeestatusServerInfo;
+
+/**
+ * The servers of a master list, as `ip:port` strings.
+ */
+typedef struct TeestatusServerList {
+  char **addresses;
+  size_t len;
+} TeestatusServerList;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Queries the info of the server at `address`, an `ip:port` or `host:port` string,
+ * waiting up to `timeout_ms`.
+ *
+ * # Safety
+ * `address` must be a nul terminated string and `out` a valid pointer. On success the
+ * info is written to `out` and must be freed with [teestatus_server_info_free].
+ */
+enum TeestatusError teestatus_query_info(const char *address,
+                                         uint32_t timeout_ms,
+                                         struct TeestatusServerInfo **out);
+
+/**
+ * Frees an info returned by [teestatus_query_info], null is ignored.
+ *
+ * # Safety
+ * `info` must come from [teestatus_query_info] and not be freed already.
+ */
+void teestatus_server_info_free(struct TeestatusServerInfo *info);
+
+/**
+ * Requests the server list of the master at `address`, an `ip:port` or `host:port`
+ * string, waiting up to `timeout_ms` after the last datagram.
+ *
+ * # Safety
+ * `address` must be a nul terminated string and `out` a valid pointer. On success the
+ * list is written to `out` and must be freed with [teestatus_server_list_free].
+ */
+enum TeestatusError teestatus_get_server_list(const char *address,
+                                              uint32_t timeout_ms,
+                                              struct TeestatusServerList **out);
+
+/**
+ * Frees a list returned by [teestatus_get_server_list], null is ignored.
+ *
+ * # Safety
+ * `list` must come from [teestatus_get_server_list] and not be freed already.
+ */
+void teestatus_server_list_free(struct TeestatusServerList *list);
+
+/**
+ * A static description of the error, not to be freed.
+ *
+ * Takes the code as an int, codes that aren't a [TeestatusError] are unknown errors.
+ */
+const char *teestatus_error_message(int error);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* TEESTATUS_H */
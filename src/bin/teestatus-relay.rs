@@ -0,0 +1,52 @@
+//! Answers the info requests of a server from a cache, forwarding the game traffic.
+//!
+//! Usage: `teestatus-relay <listen address> <server address> [--refresh <secs>] [--max-clients <n>] [--info-per-second <n>] [--no-forward]`
+
+use std::net::{SocketAddr, UdpSocket};
+use std::process::exit;
+use std::time::Duration;
+use teestatus::*;
+
+const USAGE: &str =
+    "usage: teestatus-relay <listen address> <server address> [--refresh <secs>] [--max-clients <n>] [--info-per-second <n>] [--no-forward]";
+
+fn parse_args() -> Option<(SocketAddr, InfoRelay)> {
+    let mut args = std::env::args().skip(1);
+    let listen = args.next()?.parse().ok()?;
+    let mut relay = InfoRelay::new(args.next()?.parse().ok()?);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--refresh" => relay.refresh = Duration::from_secs(args.next()?.parse().ok()?),
+            "--max-clients" => relay.max_clients = args.next()?.parse().ok()?,
+            "--info-per-second" => relay.max_info_per_second = args.next()?.parse().ok()?,
+            "--no-forward" => relay.forward = false,
+            _ => return None,
+        }
+    }
+
+    Some((listen, relay))
+}
+
+fn main() {
+    let (listen, relay) = match parse_args() {
+        Some(x) => x,
+        None => {
+            eprintln!("{}", USAGE);
+            exit(2);
+        }
+    };
+
+    let sock = match UdpSocket::bind(listen) {
+        Ok(sock) => sock,
+        Err(e) => {
+            eprintln!("can't bind {}: {}", listen, e);
+            exit(1);
+        }
+    };
+
+    if let Err(e) = relay.run(&sock) {
+        eprintln!("relay stopped: {}", e);
+        exit(1);
+    }
+}
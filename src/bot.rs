@@ -0,0 +1,532 @@
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::errors::*;
+use crate::net::*;
+use crate::protocol::*;
+use crate::query::*;
+use crate::session::*;
+
+/// The ints in an input, all left to 0 since spectators don't move.
+const INPUT_SIZE: usize = 10;
+
+/// Something that happened in the game, as seen by a [SpectatorBot].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GameEvent {
+    /// The bot entered the game on this map, sent again after every map change.
+    Entered { map: String },
+    /// A player said something, to everyone or to their team only.
+    Chat {
+        client_id: i32,
+        team: bool,
+        message: String,
+    },
+    /// A chat message sent by the server itself.
+    ServerMessage { message: String },
+    /// A message shown in the middle of the screen.
+    Broadcast { message: String },
+    /// The message of the day, sent when entering the game.
+    Motd { message: String },
+    /// A player killed another, or themselves if both ids are the same.
+    Kill {
+        killer: i32,
+        victim: i32,
+        weapon: i32,
+        special: i32,
+    },
+    /// A player joined the server.
+    Join { name: String },
+    /// A player left the server.
+    Leave { name: String },
+    /// The state of the world at a tick, only sent if [SpectatorBot::snapshots] is set.
+    Snapshot { tick: i32, snapshot: Snapshot },
+    /// The server closed the connection.
+    Disconnected { reason: Option<String> },
+}
+
+/// Parses the server messages announcing players joining or leaving.
+///
+/// Like `'nameless tee' entered and joined the game` or `'nameless tee' has left the
+/// game (timeout)`.
+fn parse_join_leave(message: &str) -> Option<GameEvent> {
+    let message = message.strip_prefix('\'')?;
+
+    if let Some(i) = message.rfind("' entered and joined the ") {
+        return Some(GameEvent::Join {
+            name: message[..i].to_owned(),
+        });
+    }
+
+    if let Some(i) = message.rfind("' has left the game") {
+        return Some(GameEvent::Leave {
+            name: message[..i].to_owned(),
+        });
+    }
+
+    None
+}
+
+/// A headless client joining a server as spectator, to follow what happens in it.
+#[derive(Debug, Clone)]
+pub struct SpectatorBot {
+    /// The server to join.
+    pub address: SocketAddr,
+    /// The name shown to the other players.
+    pub name: String,
+    pub clan: String,
+    /// The server password, if it has one.
+    pub password: Option<String>,
+    /// Whether to send the snapshots as events, several times a second.
+    pub snapshots: bool,
+}
+
+impl SpectatorBot {
+    /// Creates a bot for the server at the given address, named `teestatus`.
+    pub fn new(address: SocketAddr) -> SpectatorBot {
+        SpectatorBot {
+            address,
+            name: "teestatus".to_owned(),
+            clan: String::new(),
+            password: None,
+            snapshots: false,
+        }
+    }
+
+    /// Joins the server and sends what happens in it to `events`, until the server
+    /// closes the connection or the receiving end is dropped.
+    ///
+    /// Fails with [RequestError::Full] without connecting if the server has no free slot.
+    /// Waits for the socket read timeout, or [DEFAULT_TIMEOUT] if it has none, for the
+    /// server to answer.
+    pub fn run(&self, sock: &UdpSocket, events: &Sender<GameEvent>) -> Result<()> {
+        let timeout = sock.read_timeout()?.unwrap_or(DEFAULT_TIMEOUT);
+        let mut query = InfoQuery::new(self.address, timeout);
+        drive(sock, &mut query)?;
+
+        let info = query.finish()?.info;
+        if info.client_count >= info.max_client_count {
+            return Err(RequestError::Full);
+        }
+
+        let session = ClientSession::connect(sock, self.address)?;
+        let mut client = BotClient {
+            bot: self,
+            session,
+            map: String::new(),
+            snapshots: SnapshotStorage::new(),
+        };
+
+        let mut info = pack_system_message(SystemMessage::Info);
+        info.add_string(GAME_VERSION)
+            .add_string(self.password.as_deref().unwrap_or(""));
+        client.session.send(info.as_bytes(), true)?;
+
+        loop {
+            client.session.pump(sock, Duration::from_secs(1))?;
+
+            while let Some(event) = client.session.poll_event() {
+                let event = match event {
+                    SessionEvent::Chunk { data, .. } => match client.handle_message(&data) {
+                        Ok(event) => event,
+                        Err(e) => {
+                            log::debug!("ignoring malformed message: {}", e);
+                            None
+                        }
+                    },
+                    SessionEvent::Closed { reason } => {
+                        log::debug!("bot connection closed: {:?}", reason);
+                        // Nothing more will come, whether someone listens or not.
+                        let _ = events.send(GameEvent::Disconnected { reason });
+                        return Ok(());
+                    }
+                    SessionEvent::Connected => None,
+                };
+
+                if let Some(event) = event {
+                    if events.send(event).is_err() {
+                        client.session.close(None);
+                        return client.session.pump(sock, Duration::from_secs(0));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Runs the bot on its own thread and socket, returning the events it sends.
+    ///
+    /// The thread stops once the receiver is dropped and the next event arrives.
+    pub fn spawn(self) -> Result<(Receiver<GameEvent>, JoinHandle<Result<()>>)> {
+        let sock = UdpSocket::bind(AddressFamily::of(&self.address).unspecified())?;
+        let (sender, receiver) = mpsc::channel();
+        let handle = thread::spawn(move || self.run(&sock, &sender));
+        Ok((receiver, handle))
+    }
+}
+
+/// The state of a running bot.
+struct BotClient<'a> {
+    bot: &'a SpectatorBot,
+    session: ClientSession,
+    map: String,
+    snapshots: SnapshotStorage,
+}
+
+impl BotClient<'_> {
+    fn handle_message(&mut self, data: &[u8]) -> Result<Option<GameEvent>> {
+        let (id, system, mut unpacker) = unpack_message(data)?;
+
+        if system {
+            match SystemMessage::from_id(id) {
+                Some(SystemMessage::MapChange) => {
+                    // Reloading the map, we start over from here without reconnecting.
+                    self.map = unpacker.get_string()?.to_owned();
+                    // The ticks start over with the map.
+                    self.snapshots = SnapshotStorage::new();
+                    self.send(pack_system_message(SystemMessage::Ready))?;
+                }
+                Some(SystemMessage::ConReady) => {
+                    let mut info = pack_game_message(GameMessage::ClStartInfo);
+                    info.add_string(&self.bot.name)
+                        .add_string(&self.bot.clan)
+                        // Country
+                        .add_int(-1)
+                        .add_string("default")
+                        // Use custom color, body and feet colors.
+                        .add_int(0)
+                        .add_int(0)
+                        .add_int(0);
+                    self.send(info)?;
+                }
+                Some(SystemMessage::Ping) => {
+                    self.send(pack_system_message(SystemMessage::PingReply))?;
+                }
+                Some(message) if self.bot.snapshots => {
+                    return self.handle_snapshot(message, &mut unpacker);
+                }
+                _ => {}
+            }
+            return Ok(None);
+        }
+
+        let event = match GameMessage::from_id(id) {
+            Some(GameMessage::SvReadyToEnter) => {
+                self.send(pack_system_message(SystemMessage::EnterGame))?;
+                let mut team = pack_game_message(GameMessage::ClSetTeam);
+                team.add_int(TEAM_SPECTATORS);
+                self.send(team)?;
+                GameEvent::Entered {
+                    map: self.map.clone(),
+                }
+            }
+            Some(GameMessage::SvChat) => {
+                let team = unpacker.get_int()? != 0;
+                let client_id = unpacker.get_int()?;
+                let message = unpacker.get_string()?.to_owned();
+
+                if client_id < 0 {
+                    parse_join_leave(&message).unwrap_or(GameEvent::ServerMessage { message })
+                } else {
+                    GameEvent::Chat {
+                        client_id,
+                        team,
+                        message,
+                    }
+                }
+            }
+            Some(GameMessage::SvBroadcast) => GameEvent::Broadcast {
+                message: unpacker.get_string()?.to_owned(),
+            },
+            Some(GameMessage::SvMotd) => GameEvent::Motd {
+                message: unpacker.get_string()?.to_owned(),
+            },
+            Some(GameMessage::SvKillMsg) => GameEvent::Kill {
+                killer: unpacker.get_int()?,
+                victim: unpacker.get_int()?,
+                weapon: unpacker.get_int()?,
+                special: unpacker.get_int()?,
+            },
+            _ => return Ok(None),
+        };
+
+        Ok(Some(event))
+    }
+
+    fn handle_snapshot(
+        &mut self,
+        message: SystemMessage,
+        unpacker: &mut Unpacker<'_>,
+    ) -> Result<Option<GameEvent>> {
+        let result = self.snapshots.handle_message(message, unpacker);
+        let event = match result {
+            Ok(Some((tick, snapshot))) => Some(GameEvent::Snapshot {
+                tick,
+                snapshot: snapshot.clone(),
+            }),
+            Ok(None) => return Ok(None),
+            Err(e) => {
+                log::debug!("dropping snapshot: {}", e);
+                None
+            }
+        };
+
+        // The server sends deltas against the snapshots acked in the inputs, and a
+        // full one after an error.
+        let mut input = pack_system_message(SystemMessage::Input);
+        let ack_tick = self.snapshots.ack_tick();
+        input.add_int(ack_tick).add_int(ack_tick + 1);
+        input.add_int((INPUT_SIZE * 4) as i32);
+        for _ in 0..INPUT_SIZE {
+            input.add_int(0);
+        }
+        self.session.send(input.as_bytes(), false)?;
+
+        Ok(event)
+    }
+
+    fn send(&mut self, packer: Packer) -> Result<()> {
+        self.session.send(packer.as_bytes(), true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::InfoExtensions;
+    use crate::query::testing::request_token;
+    use crate::server::*;
+    use crate::session::testing::*;
+    use pretty_assertions::assert_eq;
+    use std::borrow::Cow;
+
+    /// Answers an info request with a server having the given number of clients.
+    fn answer_info(sock: &UdpSocket, clients: i32, max_clients: i32) {
+        let mut request = [0; 64];
+        let (_, from) = sock.recv_from(&mut request).unwrap();
+
+        let info = ServerInfo {
+            version: Cow::Borrowed("0.6.4"),
+            token: 0,
+            name: Cow::Borrowed("Stand-in"),
+            map: Cow::Borrowed("dm1"),
+            password: false,
+            flags: ServerFlags::default(),
+            game_type: Cow::Borrowed("DM"),
+            player_count: clients,
+            max_player_count: max_clients,
+            client_count: clients,
+            max_client_count: max_clients,
+            map_crc: Some(4660),
+            map_size: Some(5805),
+            players: (0..clients)
+                .map(|i| Player {
+                    name: Cow::Owned(format!("tee {}", i)),
+                    clan: Cow::Borrowed(""),
+                    country: -1,
+                    score: 0,
+                    is_spectator: false,
+                    reserved: Cow::Borrowed(""),
+                })
+                .collect(),
+            buffers: Vec::new(),
+            extensions: InfoExtensions::default(),
+        };
+        for packet in info.to_packets(request_token(&request)) {
+            sock.send_to(&packet, from).unwrap();
+        }
+    }
+
+    fn chat(team: i32, client_id: i32, message: &str) -> Packer {
+        let mut packer = pack_game_message(GameMessage::SvChat);
+        packer.add_int(team).add_int(client_id).add_string(message);
+        packer
+    }
+
+    #[test]
+    fn streams_events() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap();
+
+        let stand_in = std::thread::spawn(move || {
+            answer_info(&server, 2, 16);
+            let mut server = StandIn::accept(server);
+            server.get_ready("dm1");
+
+            let info = server.expect_game(GameMessage::ClStartInfo);
+            let mut unpacker = Unpacker::new(&info);
+            assert_eq!(unpacker.get_string().unwrap(), "overlay");
+            assert_eq!(unpacker.get_string().unwrap(), "esports");
+
+            let mut motd = pack_game_message(GameMessage::SvMotd);
+            motd.add_string("Welcome!");
+            server.send(&motd);
+            server.send(&pack_game_message(GameMessage::SvReadyToEnter));
+            server.expect_system(SystemMessage::EnterGame);
+            let team = server.expect_game(GameMessage::ClSetTeam);
+            assert_eq!(Unpacker::new(&team).get_int().unwrap(), TEAM_SPECTATORS);
+
+            server.send(&chat(-1, -1, "'tee' entered and joined the game"));
+            server.send(&chat(0, 3, "gl hf"));
+            server.send(&chat(1, 3, "go mid"));
+            let mut kill = pack_game_message(GameMessage::SvKillMsg);
+            kill.add_int(3).add_int(5).add_int(2).add_int(0);
+            server.send(&kill);
+
+            server.send(&pack_system_message(SystemMessage::Ping));
+            server.expect_system(SystemMessage::PingReply);
+
+            // A map change goes through the ready sequence again.
+            server.change_map("ctf1");
+            server.expect_game(GameMessage::ClStartInfo);
+            server.send(&pack_game_message(GameMessage::SvReadyToEnter));
+            server.expect_system(SystemMessage::EnterGame);
+            server.expect_game(GameMessage::ClSetTeam);
+
+            let mut broadcast = pack_game_message(GameMessage::SvBroadcast);
+            broadcast.add_string("Final round");
+            server.send(&broadcast);
+            server.send(&chat(-1, -1, "'tee' has left the game"));
+            server.send(&chat(-1, -1, "Server will shut down"));
+            server.close("Server shutdown");
+        });
+
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sock.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let mut bot = SpectatorBot::new(address);
+        bot.name = "overlay".to_owned();
+        bot.clan = "esports".to_owned();
+
+        let (sender, receiver) = mpsc::channel();
+        bot.run(&sock, &sender).unwrap();
+        stand_in.join().unwrap();
+
+        let message = |x: &str| x.to_owned();
+        assert_eq!(
+            receiver.try_iter().collect::<Vec<_>>(),
+            vec![
+                GameEvent::Motd {
+                    message: message("Welcome!")
+                },
+                GameEvent::Entered {
+                    map: message("dm1")
+                },
+                GameEvent::Join {
+                    name: message("tee")
+                },
+                GameEvent::Chat {
+                    client_id: 3,
+                    team: false,
+                    message: message("gl hf")
+                },
+                GameEvent::Chat {
+                    client_id: 3,
+                    team: true,
+                    message: message("go mid")
+                },
+                GameEvent::Kill {
+                    killer: 3,
+                    victim: 5,
+                    weapon: 2,
+                    special: 0
+                },
+                GameEvent::Entered {
+                    map: message("ctf1")
+                },
+                GameEvent::Broadcast {
+                    message: message("Final round")
+                },
+                GameEvent::Leave {
+                    name: message("tee")
+                },
+                GameEvent::ServerMessage {
+                    message: message("Server will shut down")
+                },
+                GameEvent::Disconnected {
+                    reason: Some(message("Server shutdown"))
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn streams_snapshots() {
+        let mut snapshot = Snapshot::new();
+        snapshot.insert(PlayerInfo::TYPE_ID, 0, vec![0, 0, TEAM_RED, 12, 40]);
+        let expected = snapshot.clone();
+
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap();
+
+        let stand_in = std::thread::spawn(move || {
+            answer_info(&server, 1, 16);
+            let mut server = StandIn::accept(server);
+            server.get_ready("dm1");
+            server.expect_game(GameMessage::ClStartInfo);
+            server.send(&pack_game_message(GameMessage::SvReadyToEnter));
+            server.expect_system(SystemMessage::EnterGame);
+            server.expect_game(GameMessage::ClSetTeam);
+
+            let data = pack_ints(&Snapshot::new().create_delta(&snapshot));
+            let mut snap = pack_system_message(SystemMessage::SnapSingle);
+            snap.add_int(50)
+                .add_int(51)
+                .add_int(snapshot.crc())
+                .add_int(data.len() as i32)
+                .add_raw(&data);
+            server.send(&snap);
+            server.close("Server shutdown");
+        });
+
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sock.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let mut bot = SpectatorBot::new(address);
+        bot.snapshots = true;
+
+        let (sender, receiver) = mpsc::channel();
+        bot.run(&sock, &sender).unwrap();
+        stand_in.join().unwrap();
+
+        let snapshots: Vec<_> = receiver
+            .try_iter()
+            .filter_map(|x| match x {
+                GameEvent::Snapshot { tick, snapshot } => Some((tick, snapshot)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(snapshots, vec![(50, expected)]);
+    }
+
+    #[test]
+    fn checks_for_a_free_slot() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap();
+        let stand_in = std::thread::spawn(move || answer_info(&server, 4, 4));
+
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sock.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let (sender, _receiver) = mpsc::channel();
+        let result = SpectatorBot::new(address).run(&sock, &sender);
+        stand_in.join().unwrap();
+
+        assert!(matches!(result, Err(RequestError::Full)));
+    }
+
+    #[test]
+    fn parses_join_and_leave() {
+        assert_eq!(
+            parse_join_leave("'nameless tee' entered and joined the game"),
+            Some(GameEvent::Join {
+                name: "nameless tee".to_owned()
+            })
+        );
+        assert_eq!(
+            parse_join_leave("'it's me' has left the game (timeout)"),
+            Some(GameEvent::Leave {
+                name: "it's me".to_owned()
+            })
+        );
+        assert_eq!(parse_join_leave("'tee' joined the spectators"), None);
+        assert_eq!(parse_join_leave("Server will shut down"), None);
+    }
+}
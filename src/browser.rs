@@ -0,0 +1,477 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::net::{SocketAddr, UdpSocket};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use crate::errors::*;
+use crate::friends::*;
+use crate::query::*;
+use crate::server::*;
+use crate::util::*;
+
+/// A scanned server, along with how and when it was reached.
+#[derive(Debug, Clone)]
+pub struct ServerEntry<'a> {
+    pub address: SocketAddr,
+    pub ping: Duration,
+    pub queried_at: SystemTime,
+    pub info: ServerInfo<'a>,
+}
+
+impl<'a> ServerEntry<'a> {
+    /// Connects the socket to the given address and requests the server info,
+    /// measuring the round trip time.
+    ///
+    /// See also [ServerInfo::new()]
+    pub fn query(
+        sock: &UdpSocket,
+        address: SocketAddr,
+        buffers: &'a mut [Vec<u8>],
+    ) -> Result<ServerEntry<'a>> {
+        sock.connect(address)?;
+
+        let timeout = sock.read_timeout()?.unwrap_or(DEFAULT_TIMEOUT);
+        let mut query = InfoQuery::new(address, timeout);
+        drive(sock, &mut query)?;
+
+        Ok(ServerEntry {
+            address,
+            ping: query.ping().unwrap_or_default(),
+            queried_at: query.queried_at().unwrap_or_else(SystemTime::now),
+            info: query.into_info(buffers)?,
+        })
+    }
+
+    /// Copies the borrowed data, detaching the entry from the receive buffers.
+    pub fn into_owned(self) -> ServerEntry<'static> {
+        ServerEntry {
+            address: self.address,
+            ping: self.ping,
+            queried_at: self.queried_at,
+            info: self.info.into_owned(),
+        }
+    }
+}
+
+/// Server browser filters, like the ones found in the game.
+///
+/// Filters can be written as a string of space separated tokens:
+/// `not_empty not_full no_password friends gametype:ctf,dm !gametype:race
+/// map:ctf_* name:"my server" ping:100 country:276`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Filter {
+    /// Hide servers without clients.
+    pub not_empty: bool,
+    /// Hide servers with no free slots.
+    pub not_full: bool,
+    /// Hide servers requiring a password.
+    pub no_password: bool,
+    /// Show only servers whose gametype contains one of these.
+    pub game_types: Vec<String>,
+    /// Hide servers whose gametype contains one of these.
+    pub excluded_game_types: Vec<String>,
+    /// Glob pattern the map name must match.
+    pub map: Option<String>,
+    /// Text the server name must contain.
+    pub name: Option<String>,
+    /// Hide servers with a higher ping.
+    pub max_ping: Option<Duration>,
+    /// Show only servers with one of [Filter::friends] online.
+    pub has_friends: bool,
+    /// Show only servers with a player from this country.
+    pub country: Option<i32>,
+    /// The friends looked for by [Filter::has_friends], not part of the string form.
+    pub friends: FriendList,
+}
+
+fn contains_ignore_case(haystack: &str, needle: &str) -> bool {
+    haystack.to_lowercase().contains(&needle.to_lowercase())
+}
+
+impl Filter {
+    /// Whether the server passes all the filters.
+    pub fn matches(&self, entry: &ServerEntry) -> bool {
+        let info = &entry.info;
+
+        if self.not_empty && info.client_count == 0 {
+            return false;
+        }
+
+        if self.not_full && info.client_count >= info.max_client_count {
+            return false;
+        }
+
+        if self.no_password && info.flags.contains(ServerFlags::PASSWORD) {
+            return false;
+        }
+
+        if !self.game_types.is_empty()
+            && !self
+                .game_types
+                .iter()
+                .any(|x| contains_ignore_case(&info.game_type, x))
+        {
+            return false;
+        }
+
+        if self
+            .excluded_game_types
+            .iter()
+            .any(|x| contains_ignore_case(&info.game_type, x))
+        {
+            return false;
+        }
+
+        if let Some(map) = &self.map {
+            if !glob_match(map, &info.map) {
+                return false;
+            }
+        }
+
+        if let Some(name) = &self.name {
+            if !contains_ignore_case(&info.name, name) {
+                return false;
+            }
+        }
+
+        if let Some(max_ping) = self.max_ping {
+            if entry.ping > max_ping {
+                return false;
+            }
+        }
+
+        if self.has_friends && !info.players.iter().any(|p| self.friends.is_friend(p)) {
+            return false;
+        }
+
+        if let Some(country) = self.country {
+            if !info.players.iter().any(|p| p.country == country) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Returns the entries passing the filters.
+    pub fn apply<'e, 'a>(
+        &'e self,
+        entries: &'e [ServerEntry<'a>],
+    ) -> impl Iterator<Item = &'e ServerEntry<'a>> + 'e {
+        entries.iter().filter(move |x| self.matches(x))
+    }
+}
+
+/// Known filter keys, used to tell unknown keys apart from misused ones.
+const FILTER_KEYS: &[&str] = &[
+    "not_empty",
+    "not_full",
+    "no_password",
+    "friends",
+    "gametype",
+    "!gametype",
+    "map",
+    "name",
+    "ping",
+    "country",
+];
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_owned())
+        .collect()
+}
+
+impl FromStr for Filter {
+    type Err = FilterError;
+
+    fn from_str(s: &str) -> Result<Filter, FilterError> {
+        let mut filter = Filter::default();
+
+        for token in split_args(s).ok_or(FilterError::UnterminatedQuote)? {
+            let (key, value) = match token.find(':') {
+                Some(i) => (&token[..i], Some(&token[i + 1..])),
+                None => (&token[..], None),
+            };
+
+            let invalid = || FilterError::InvalidValue {
+                key: key.to_owned(),
+                value: value.unwrap_or_default().to_owned(),
+            };
+
+            match (key, value) {
+                ("not_empty", None) => filter.not_empty = true,
+                ("not_full", None) => filter.not_full = true,
+                ("no_password", None) => filter.no_password = true,
+                ("friends", None) => filter.has_friends = true,
+                ("gametype", Some(value)) => filter.game_types.extend(split_list(value)),
+                ("!gametype", Some(value)) => filter.excluded_game_types.extend(split_list(value)),
+                ("map", Some(value)) => filter.map = Some(value.to_owned()),
+                ("name", Some(value)) => filter.name = Some(value.to_owned()),
+                ("ping", Some(value)) => {
+                    let ms = value.parse().map_err(|_| invalid())?;
+                    filter.max_ping = Some(Duration::from_millis(ms));
+                }
+                ("country", Some(value)) => {
+                    filter.country = Some(value.parse().map_err(|_| invalid())?);
+                }
+                _ if FILTER_KEYS.contains(&key) => return Err(invalid()),
+                _ => return Err(FilterError::UnknownKey(key.to_owned())),
+            }
+        }
+
+        Ok(filter)
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut tokens = Vec::new();
+
+        if self.not_empty {
+            tokens.push("not_empty".to_owned());
+        }
+        if self.not_full {
+            tokens.push("not_full".to_owned());
+        }
+        if self.no_password {
+            tokens.push("no_password".to_owned());
+        }
+        if self.has_friends {
+            tokens.push("friends".to_owned());
+        }
+        if !self.game_types.is_empty() {
+            tokens.push(quote_arg(&format!(
+                "gametype:{}",
+                self.game_types.join(",")
+            )));
+        }
+        if !self.excluded_game_types.is_empty() {
+            tokens.push(quote_arg(&format!(
+                "!gametype:{}",
+                self.excluded_game_types.join(",")
+            )));
+        }
+        if let Some(map) = &self.map {
+            tokens.push(quote_arg(&format!("map:{}", map)));
+        }
+        if let Some(name) = &self.name {
+            tokens.push(quote_arg(&format!("name:{}", name)));
+        }
+        if let Some(ping) = self.max_ping {
+            tokens.push(format!("ping:{}", ping.as_millis()));
+        }
+        if let Some(country) = self.country {
+            tokens.push(format!("country:{}", country));
+        }
+
+        write!(f, "{}", tokens.join(" "))
+    }
+}
+
+/// A field to sort servers by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    /// Number of connected clients.
+    Players,
+    Ping,
+    Name,
+    Map,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    Ascending,
+    Descending,
+}
+
+/// A multi key sort order, earlier keys take precedence.
+///
+/// Written as a comma separated list of keys with an optional order:
+/// `players:desc,ping,name:asc`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Sort {
+    pub keys: Vec<(SortKey, SortOrder)>,
+}
+
+impl Sort {
+    /// Compares two entries according to the sort keys.
+    pub fn compare(&self, a: &ServerEntry, b: &ServerEntry) -> Ordering {
+        for (key, order) in &self.keys {
+            let ordering = match key {
+                SortKey::Players => a.info.client_count.cmp(&b.info.client_count),
+                SortKey::Ping => a.ping.cmp(&b.ping),
+                SortKey::Name => a.info.name.to_lowercase().cmp(&b.info.name.to_lowercase()),
+                SortKey::Map => a.info.map.to_lowercase().cmp(&b.info.map.to_lowercase()),
+            };
+
+            let ordering = match order {
+                SortOrder::Ascending => ordering,
+                SortOrder::Descending => ordering.reverse(),
+            };
+
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+
+        Ordering::Equal
+    }
+
+    /// Sorts the entries in place, keeping the original order of equal entries.
+    pub fn sort(&self, entries: &mut [ServerEntry]) {
+        entries.sort_by(|a, b| self.compare(a, b));
+    }
+}
+
+impl FromStr for Sort {
+    type Err = FilterError;
+
+    fn from_str(s: &str) -> Result<Sort, FilterError> {
+        let mut keys = Vec::new();
+
+        for part in s.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+            let (name, order) = match part.find(':') {
+                Some(i) => (&part[..i], Some(&part[i + 1..])),
+                None => (part, None),
+            };
+
+            let key = match name {
+                "players" => SortKey::Players,
+                "ping" => SortKey::Ping,
+                "name" => SortKey::Name,
+                "map" => SortKey::Map,
+                _ => return Err(FilterError::UnknownKey(name.to_owned())),
+            };
+
+            let order = match order {
+                None | Some("asc") => SortOrder::Ascending,
+                Some("desc") => SortOrder::Descending,
+                Some(value) => {
+                    return Err(FilterError::InvalidValue {
+                        key: name.to_owned(),
+                        value: value.to_owned(),
+                    })
+                }
+            };
+
+            keys.push((key, order));
+        }
+
+        Ok(Sort { keys })
+    }
+}
+
+impl fmt::Display for Sort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let keys: Vec<String> = self
+            .keys
+            .iter()
+            .map(|(key, order)| {
+                let name = match key {
+                    SortKey::Players => "players",
+                    SortKey::Ping => "ping",
+                    SortKey::Name => "name",
+                    SortKey::Map => "map",
+                };
+                match order {
+                    SortOrder::Ascending => name.to_owned(),
+                    SortOrder::Descending => format!("{}:desc", name),
+                }
+            })
+            .collect();
+
+        write!(f, "{}", keys.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn entry(data: &[u8], ping: u64) -> ServerEntry<'_> {
+        ServerEntry {
+            address: "127.0.0.1:8303".parse().unwrap(),
+            ping: Duration::from_millis(ping),
+            queried_at: SystemTime::now(),
+            info: ServerInfo::parse_main(data).unwrap(),
+        }
+    }
+
+    #[test]
+    fn parses_filters() {
+        let filter: Filter =
+            "not_empty no_password gametype:ctf,dm !gametype:race map:ctf_* name:\"my server\" ping:100 country:276"
+                .parse()
+                .unwrap();
+
+        assert!(filter.not_empty);
+        assert!(!filter.not_full);
+        assert!(filter.no_password);
+        assert_eq!(filter.game_types, vec!["ctf", "dm"]);
+        assert_eq!(filter.excluded_game_types, vec!["race"]);
+        assert_eq!(filter.map.as_deref(), Some("ctf_*"));
+        assert_eq!(filter.name.as_deref(), Some("my server"));
+        assert_eq!(filter.max_ping, Some(Duration::from_millis(100)));
+        assert_eq!(filter.country, Some(276));
+
+        assert_eq!(filter.to_string().parse::<Filter>().unwrap(), filter);
+
+        assert_eq!(
+            "foo".parse::<Filter>(),
+            Err(FilterError::UnknownKey("foo".to_owned()))
+        );
+        assert!("ping:fast".parse::<Filter>().is_err());
+        assert!("not_full:yes".parse::<Filter>().is_err());
+        assert_eq!(
+            "name:\"abc".parse::<Filter>(),
+            Err(FilterError::UnterminatedQuote)
+        );
+    }
+
+    #[test]
+    fn filters_servers() {
+        let data = include_bytes!("samples/server_info.data");
+        let server = entry(data, 50);
+
+        let matches = |s: &str| s.parse::<Filter>().unwrap().matches(&server);
+
+        assert!(matches(""));
+        assert!(matches(
+            "not_empty no_password gametype:ddrace map:multeasy*"
+        ));
+        assert!(matches("name:novice ping:50 country:250"));
+        assert!(!matches("not_full"));
+        assert!(!matches("!gametype:ddrace"));
+        assert!(!matches("gametype:ctf"));
+        assert!(!matches("map:ctf_*"));
+        assert!(!matches("ping:10"));
+        assert!(!matches("country:1234"));
+
+        let mut filter: Filter = "friends".parse().unwrap();
+        assert!(!filter.matches(&server));
+        filter.friends.add(Friend::new("Dino Bleu", None));
+        assert!(filter.matches(&server));
+    }
+
+    #[test]
+    fn sorts_servers() {
+        let data = include_bytes!("samples/server_info.data");
+        let mut entries = vec![entry(data, 80), entry(data, 20), entry(data, 50)];
+        entries[1].info.client_count = 10;
+
+        let sort: Sort = "players:desc,ping".parse().unwrap();
+        assert_eq!(sort.to_string(), "players:desc,ping");
+        sort.sort(&mut entries);
+
+        let pings: Vec<_> = entries.iter().map(|x| x.ping.as_millis()).collect();
+        assert_eq!(pings, vec![50, 80, 20]);
+
+        assert!("players:up".parse::<Sort>().is_err());
+        assert!("size".parse::<Sort>().is_err());
+    }
+}
@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::errors::*;
+use crate::query::*;
+use crate::server::*;
+
+#[derive(Debug, Default)]
+struct Slot {
+    info: Option<(Instant, ServerInfo<'static>)>,
+    /// Whether a query is running.
+    pending: bool,
+    /// Counts the queries done, so waiters know theirs finished.
+    generation: u64,
+    /// The error of the last query, if it failed.
+    error: Option<RequestError>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    ttl: Duration,
+    stale: Duration,
+    timeout: Duration,
+    slots: Mutex<HashMap<SocketAddr, Slot>>,
+    done: Condvar,
+}
+
+impl Inner {
+    fn lock(&self) -> MutexGuard<'_, HashMap<SocketAddr, Slot>> {
+        // A panic while holding the lock leaves nothing half updated.
+        self.slots.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Queries the server and stores the result, waking up the waiters.
+    fn refresh(&self, address: SocketAddr) {
+        let result = query_info(address, self.timeout);
+
+        let mut slots = self.lock();
+        let slot = slots.entry(address).or_default();
+        slot.pending = false;
+        slot.generation += 1;
+        match result {
+            Ok(info) => {
+                slot.info = Some((Instant::now(), info));
+                slot.error = None;
+            }
+            Err(e) => {
+                log::debug!("refreshing {} failed: {}", address, e);
+                slot.error = Some(e);
+            }
+        }
+        self.done.notify_all();
+    }
+}
+
+/// A cache of server infos, shared by cloning it.
+///
+/// Concurrent requests for the same server share a single query. Once a value is older
+/// than the TTL, it can still be served during the stale window while a query refreshes
+/// it in the background.
+#[derive(Debug, Clone)]
+pub struct InfoCache {
+    inner: Arc<Inner>,
+}
+
+impl InfoCache {
+    /// Creates a cache keeping infos for `ttl`, without stale window.
+    pub fn new(ttl: Duration) -> InfoCache {
+        InfoCache::with_options(ttl, Duration::from_secs(0), DEFAULT_TIMEOUT)
+    }
+
+    /// Creates a cache keeping infos for `ttl`, serving them for `stale` longer while
+    /// refreshing them, and waiting up to `timeout` for the servers.
+    pub fn with_options(ttl: Duration, stale: Duration, timeout: Duration) -> InfoCache {
+        InfoCache {
+            inner: Arc::new(Inner {
+                ttl,
+                stale,
+                timeout,
+                slots: Mutex::new(HashMap::new()),
+                done: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Returns the info of the server, querying it if the cached one is too old.
+    ///
+    /// Fails if the query fails, waiters of the same query get the same error.
+    pub fn get(&self, address: SocketAddr) -> Result<ServerInfo<'static>> {
+        let inner = &*self.inner;
+        let mut slots = inner.lock();
+        let mut waiting_for = None;
+
+        loop {
+            let slot = slots.entry(address).or_default();
+
+            // The query we waited on finished, its result is returned whatever its age.
+            if let Some(generation) = waiting_for {
+                if slot.generation > generation {
+                    match (&slot.error, &slot.info) {
+                        (Some(e), _) => return Err(e.duplicate()),
+                        (None, Some((_, info))) => return Ok(info.clone()),
+                        (None, None) => {}
+                    }
+                }
+            }
+
+            if let Some((fetched, info)) = &slot.info {
+                let age = fetched.elapsed();
+                if age < inner.ttl {
+                    return Ok(info.clone());
+                }
+
+                if age < inner.ttl + inner.stale {
+                    let info = info.clone();
+                    if !slot.pending {
+                        slot.pending = true;
+                        let cache = self.inner.clone();
+                        thread::spawn(move || cache.refresh(address));
+                    }
+                    return Ok(info);
+                }
+            }
+
+            if slot.pending {
+                waiting_for = Some(slot.generation);
+                slots = inner.done.wait(slots).unwrap_or_else(|e| e.into_inner());
+                continue;
+            }
+
+            slot.pending = true;
+            waiting_for = Some(slot.generation);
+            drop(slots);
+            inner.refresh(address);
+            slots = inner.lock();
+        }
+    }
+
+    /// Forgets the info of a server, the next request queries it again.
+    pub fn invalidate(&self, address: SocketAddr) {
+        if let Some(slot) = self.inner.lock().get_mut(&address) {
+            slot.info = None;
+        }
+    }
+
+    /// Forgets every info.
+    pub fn clear(&self) {
+        for slot in self.inner.lock().values_mut() {
+            slot.info = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::testing::*;
+    use pretty_assertions::assert_eq;
+    use std::net::UdpSocket;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Answers info requests after `delay`, counting them.
+    fn responder(delay: Duration, answer_requests: bool) -> (SocketAddr, Arc<AtomicUsize>) {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap();
+        let count = Arc::new(AtomicUsize::new(0));
+
+        let counter = count.clone();
+        thread::spawn(move || {
+            serve_samples_with(&server, |_, _, _| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                if answer_requests {
+                    thread::sleep(delay);
+                }
+                answer_requests
+            })
+        });
+
+        (address, count)
+    }
+
+    #[test]
+    fn coalesces_requests() {
+        let (address, count) = responder(Duration::from_millis(100), true);
+        let cache = InfoCache::new(Duration::from_secs(60));
+
+        let handles: Vec<_> = (0..5)
+            .map(|_| {
+                let cache = cache.clone();
+                thread::spawn(move || cache.get(address).unwrap())
+            })
+            .collect();
+        for handle in handles {
+            let info = handle.join().unwrap();
+            assert_eq!(info.map, "Multeasymap");
+            assert_eq!(info.players.len(), 63);
+        }
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        cache.get(address).unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        cache.invalidate(address);
+        cache.get(address).unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn refreshes_expired_infos() {
+        let (address, count) = responder(Duration::from_millis(0), true);
+        let cache = InfoCache::new(Duration::from_millis(50));
+
+        cache.get(address).unwrap();
+        thread::sleep(Duration::from_millis(80));
+        cache.get(address).unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn queries_every_time_without_ttl() {
+        let (address, count) = responder(Duration::from_millis(50), true);
+        let cache = InfoCache::new(Duration::from_secs(0));
+
+        let handles: Vec<_> = (0..3)
+            .map(|_| {
+                let cache = cache.clone();
+                thread::spawn(move || cache.get(address).unwrap())
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap().map, "Multeasymap");
+        }
+        // At most one query each, waiters share the one in flight.
+        assert!(count.load(Ordering::SeqCst) <= 3);
+
+        let before = count.load(Ordering::SeqCst);
+        cache.get(address).unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), before + 1);
+    }
+
+    #[test]
+    fn serves_stale_infos_while_refreshing() {
+        let (address, count) = responder(Duration::from_millis(200), true);
+        let cache = InfoCache::with_options(
+            Duration::from_millis(10),
+            Duration::from_secs(60),
+            DEFAULT_TIMEOUT,
+        );
+
+        cache.get(address).unwrap();
+        thread::sleep(Duration::from_millis(20));
+
+        // Answered at once, while the refresh waits for the server.
+        let start = Instant::now();
+        cache.get(address).unwrap();
+        cache.get(address).unwrap();
+        assert!(start.elapsed() < Duration::from_millis(150));
+
+        thread::sleep(Duration::from_millis(400));
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+        cache.get(address).unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn shares_errors() {
+        let (address, count) = responder(Duration::from_millis(0), false);
+        let cache = InfoCache::with_options(
+            Duration::from_secs(60),
+            Duration::from_secs(0),
+            Duration::from_millis(100),
+        );
+
+        let handles: Vec<_> = (0..3)
+            .map(|_| {
+                let cache = cache.clone();
+                thread::spawn(move || cache.get(address))
+            })
+            .collect();
+        for handle in handles {
+            assert!(matches!(handle.join().unwrap(), Err(RequestError::Timeout)));
+        }
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,179 @@
+use std::net::SocketAddr;
+use std::path::Path;
+
+use crate::browser::*;
+use crate::errors::*;
+use crate::friends::*;
+use crate::net::*;
+use crate::util::*;
+
+/// A favorite server, which may be reachable through several addresses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Favorite {
+    pub addresses: Vec<SocketAddr>,
+}
+
+/// Favorites and friends read from a teeworlds or DDNet client config,
+/// like `settings.cfg` or `settings_ddnet.cfg`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientConfig {
+    pub favorites: Vec<Favorite>,
+    pub friends: FriendList,
+}
+
+/// Parses a favorite address, either plain (`1.2.3.4:8303`, `[::1]:8303`)
+/// or in the DDNet url form (`tw-0.6+udp://1.2.3.4:8303`).
+fn parse_address(address: &str) -> Option<SocketAddr> {
+    let address = match address.find("://") {
+        Some(i) => &address[i + 3..],
+        None => address,
+    };
+
+    address.trim_end_matches('/').parse().ok()
+}
+
+impl ClientConfig {
+    /// Parses the content of a client config, ignoring unrelated commands.
+    pub fn parse(config: &str) -> ClientConfig {
+        let mut parsed = ClientConfig::default();
+
+        for command in config.lines().flat_map(split_commands) {
+            let args = match split_args(command) {
+                Some(args) => args,
+                None => {
+                    log::warn!("unterminated quote in config command: {}", command);
+                    continue;
+                }
+            };
+
+            if let Some(friend) = parse_add_friend(&args) {
+                parsed.friends.add(friend);
+                continue;
+            }
+
+            if let [cmd, value] = args.as_slice() {
+                if cmd == "cl_friends_ignore_clan" {
+                    parsed.friends.ignore_clan = matches!(value.parse::<i32>(), Ok(x) if x != 0);
+                    continue;
+                }
+            }
+
+            if let [cmd, addresses, ..] = args.as_slice() {
+                if cmd != "add_favorite" {
+                    continue;
+                }
+
+                let addresses: Vec<SocketAddr> = addresses
+                    .split(',')
+                    .filter_map(|x| {
+                        let address = parse_address(x.trim());
+                        if address.is_none() {
+                            log::warn!("skipping invalid favorite address '{}'", x);
+                        }
+                        address
+                    })
+                    .collect();
+
+                if !addresses.is_empty() {
+                    let favorite = Favorite { addresses };
+                    if !parsed.favorites.contains(&favorite) {
+                        parsed.favorites.push(favorite);
+                    }
+                }
+            }
+        }
+
+        parsed
+    }
+
+    /// Reads and parses a client config file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<ClientConfig> {
+        let config = std::fs::read(path)?;
+        Ok(ClientConfig::parse(&String::from_utf8_lossy(&config)))
+    }
+
+    /// Requests the info of every favorite, using one set of buffers for each.
+    ///
+    /// Each favorite is queried on its first address of a family the socket can reach.
+    /// The results are in the same order as [ClientConfig::favorites]. Fails if there are
+    /// fewer sets of buffers than favorites.
+    ///
+    /// See also [ServerInfo::create_buffers()](crate::ServerInfo::create_buffers)
+    pub fn query_favorites<'a>(
+        &self,
+        socks: &DualStackSocket,
+        buffers: &'a mut [Vec<Vec<u8>>],
+    ) -> Result<Vec<Result<ServerEntry<'a>>>> {
+        if buffers.len() < self.favorites.len() {
+            return Err(RequestError::InvalidData("fewer buffers than favorites"));
+        }
+
+        let families = socks.families();
+
+        Ok(self
+            .favorites
+            .iter()
+            .zip(buffers.iter_mut())
+            .map(|(favorite, buffers)| {
+                let address = favorite
+                    .addresses
+                    .iter()
+                    .find(|x| families.contains(&AddressFamily::of(x)))
+                    .ok_or(RequestError::Missing)?;
+                socks.query(*address, buffers)
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn parses_config() {
+        let config = r#"
+player_name "nameless tee"
+add_favorite 1.2.3.4:8303
+add_favorite "tw-0.6+udp://5.6.7.8:8304,tw-0.6+udp://[2001:db8::1]:8304" allow_ping
+add_favorite "not an address"; add_friend "Dino Bleu" "Helper"
+# add_favorite 9.9.9.9:8303
+add_favorite 1.2.3.4:8303
+cl_friends_ignore_clan 1
+"#;
+
+        let parsed = ClientConfig::parse(config);
+        assert_eq!(
+            parsed.favorites,
+            vec![
+                Favorite {
+                    addresses: vec!["1.2.3.4:8303".parse().unwrap()]
+                },
+                Favorite {
+                    addresses: vec![
+                        "5.6.7.8:8304".parse().unwrap(),
+                        "[2001:db8::1]:8304".parse().unwrap()
+                    ]
+                },
+            ]
+        );
+        assert_eq!(
+            parsed.friends.friends,
+            vec![Friend::new("Dino Bleu", Some("Helper"))]
+        );
+        assert!(parsed.friends.ignore_clan);
+    }
+
+    #[test]
+    fn needs_buffers_for_every_favorite() {
+        let config =
+            ClientConfig::parse("add_favorite 127.0.0.1:8303\nadd_favorite 127.0.0.1:8304");
+        let socks = DualStackSocket::bind().unwrap();
+        let mut buffers = vec![crate::ServerInfo::create_buffers()];
+        assert!(matches!(
+            config.query_favorites(&socks, &mut buffers),
+            Err(RequestError::InvalidData(_))
+        ));
+    }
+}
@@ -0,0 +1,512 @@
+/// A player as written in the log lines, like `'0:nameless tee'`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogPlayer<'a> {
+    pub client_id: i32,
+    pub name: &'a str,
+}
+
+impl<'a> LogPlayer<'a> {
+    /// Parses `id:name`, the quotes already removed.
+    fn parse(player: &'a str) -> Option<LogPlayer<'a>> {
+        let (client_id, name) = player.split_once(':')?;
+        Some(LogPlayer {
+            client_id: client_id.parse().ok()?,
+            name,
+        })
+    }
+}
+
+/// Something that happened on the server, read from its console output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogEvent<'a> {
+    /// A client finished connecting.
+    Enter {
+        client_id: i32,
+        address: Option<&'a str>,
+    },
+    /// A player joined a team, when entering the game or switching teams later.
+    TeamJoin { player: LogPlayer<'a>, team: i32 },
+    /// A player left the game.
+    Leave { player: LogPlayer<'a> },
+    /// A client was dropped, by leaving, timing out or being kicked.
+    Drop {
+        client_id: i32,
+        address: Option<&'a str>,
+        reason: &'a str,
+    },
+    /// A public chat message.
+    Chat {
+        player: LogPlayer<'a>,
+        team: i32,
+        message: &'a str,
+    },
+    /// A chat message only sent to a team.
+    TeamChat {
+        player: LogPlayer<'a>,
+        team: i32,
+        message: &'a str,
+    },
+    /// A chat message sent by the server itself.
+    ServerChat { message: &'a str },
+    /// A player died, the killer is the victim on suicides.
+    Kill {
+        killer: LogPlayer<'a>,
+        victim: LogPlayer<'a>,
+        weapon: i32,
+        special: i32,
+    },
+    /// A map was loaded.
+    MapChange { map: &'a str, crc: Option<u32> },
+    /// A vote was called.
+    Vote {
+        player: LogPlayer<'a>,
+        /// What the vote is about, like `option`, `kick` or `spectate`.
+        kind: &'a str,
+        description: &'a str,
+        reason: &'a str,
+        command: &'a str,
+        forced: bool,
+    },
+    /// A client logged into the remote console.
+    RconAuth {
+        client_id: i32,
+        /// The authentication level, like `admin` or `moderator`.
+        level: &'a str,
+        /// The key used, on DDNet servers.
+        key: Option<&'a str>,
+    },
+    /// A line we don't know about.
+    Other,
+}
+
+/// A line of console output, as sent over econ or written to the log files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogLine<'a> {
+    /// The timestamp, as written by the server.
+    pub timestamp: Option<&'a str>,
+    /// The part of the server logging, like `game`, `chat` or `server`.
+    pub system: &'a str,
+    pub message: &'a str,
+}
+
+/// Returns the address of `addr=`, which DDNet wraps in `<{...}>`.
+fn address(message: &str) -> Option<&str> {
+    let address = field(message, "addr", None)?;
+    Some(
+        address
+            .strip_prefix("<{")
+            .and_then(|x| x.strip_suffix("}>"))
+            .unwrap_or(address),
+    )
+}
+
+/// Returns the value of `key=value`, which may be quoted.
+///
+/// Quoted values end at the last `'` followed by the next key, so names
+/// containing quotes are kept whole.
+fn field<'a>(message: &'a str, key: &str, next: Option<&str>) -> Option<&'a str> {
+    let start = message.find(&format!("{}=", key))? + key.len() + 1;
+    let rest = &message[start..];
+
+    match (rest.strip_prefix('\''), next) {
+        (Some(rest), Some(next)) => Some(&rest[..rest.rfind(&format!("' {}=", next))?]),
+        (Some(rest), None) => Some(&rest[..rest.rfind('\'')?]),
+        (None, _) => Some(rest.split_whitespace().next().unwrap_or("")),
+    }
+}
+
+/// Splits off the last ` key=value` of a message, returning the rest and the value.
+///
+/// Looking from the end keeps names containing `key=` from hiding the real field.
+fn trailing_field<'a>(message: &'a str, key: &str) -> Option<(&'a str, &'a str)> {
+    let start = message.rfind(&format!(" {}=", key))?;
+    Some((&message[..start], &message[start + key.len() + 2..]))
+}
+
+/// Removes the quotes around a value, if any.
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('\'')
+        .and_then(|x| x.strip_suffix('\''))
+        .unwrap_or(value)
+}
+
+impl<'a> LogLine<'a> {
+    /// Splits a line into its timestamp, system and message.
+    ///
+    /// Supports the vanilla `[5f8d1a2b][game]: msg`, the older DDNet
+    /// `[2020-10-15 12:34:56][game]: msg` and the newer DDNet
+    /// `2020-10-15 12:34:56 I game: msg` formats, with or without timestamp.
+    pub fn parse(line: &'a str) -> Option<LogLine<'a>> {
+        let line = line.trim_end_matches(['\n', '\r']);
+
+        if let Some(rest) = line.strip_prefix('[') {
+            let (first, rest) = rest.split_once(']')?;
+
+            if let Some(rest) = rest.strip_prefix(": ") {
+                return Some(LogLine {
+                    timestamp: None,
+                    system: first,
+                    message: rest,
+                });
+            }
+
+            let (system, message) = rest.strip_prefix('[')?.split_once("]: ")?;
+            return Some(LogLine {
+                timestamp: Some(first),
+                system,
+                message,
+            });
+        }
+
+        // DDNet: date, time, a level letter, then the system.
+        let mut parts = line.splitn(4, ' ');
+        let date = parts.next()?;
+        let time = parts.next()?;
+        let level = parts.next()?;
+        let rest = parts.next()?;
+
+        if level.len() != 1 || !date.contains('-') || !time.contains(':') {
+            return None;
+        }
+
+        let (system, message) = rest.split_once(": ")?;
+        Some(LogLine {
+            timestamp: Some(&line[..date.len() + 1 + time.len()]),
+            system,
+            message,
+        })
+    }
+
+    /// Reads the event the line is about.
+    pub fn event(&self) -> LogEvent<'a> {
+        self.parse_event().unwrap_or(LogEvent::Other)
+    }
+
+    fn parse_event(&self) -> Option<LogEvent<'a>> {
+        let message = self.message;
+
+        match self.system {
+            "chat" | "teamchat" => {
+                if let Some(message) = message.strip_prefix("*** ") {
+                    return Some(LogEvent::ServerChat { message });
+                }
+
+                let (client_id, rest) = message.split_once(':')?;
+                let (team, rest) = rest.split_once(':')?;
+                let (name, message) = rest.split_once(": ")?;
+                let client_id = client_id.parse().ok()?;
+                let team = team.parse().ok()?;
+
+                if client_id < 0 {
+                    return Some(LogEvent::ServerChat { message });
+                }
+
+                let player = LogPlayer { client_id, name };
+                if self.system == "chat" {
+                    Some(LogEvent::Chat {
+                        player,
+                        team,
+                        message,
+                    })
+                } else {
+                    Some(LogEvent::TeamChat {
+                        player,
+                        team,
+                        message,
+                    })
+                }
+            }
+            "game" => {
+                if message.starts_with("kill ") {
+                    let (rest, special) = trailing_field(message, "special")?;
+                    let (rest, weapon) = trailing_field(rest, "weapon")?;
+                    let (rest, victim) = trailing_field(rest, "victim")?;
+                    Some(LogEvent::Kill {
+                        killer: LogPlayer::parse(field(rest, "killer", None)?)?,
+                        victim: LogPlayer::parse(unquote(victim))?,
+                        weapon: weapon.parse().ok()?,
+                        special: special.parse().ok()?,
+                    })
+                } else if message.starts_with("team_join ") {
+                    // Vanilla writes `m_Team=` when switching teams.
+                    let (rest, team) = trailing_field(message, "team")
+                        .or_else(|| trailing_field(message, "m_Team"))?;
+                    Some(LogEvent::TeamJoin {
+                        player: LogPlayer::parse(field(rest, "player", None)?)?,
+                        team: team.parse().ok()?,
+                    })
+                } else if message.starts_with("leave ") {
+                    Some(LogEvent::Leave {
+                        player: LogPlayer::parse(field(message, "player", None)?)?,
+                    })
+                } else if let Some(rest) = message.strip_prefix('\'') {
+                    let (player, rest) = rest.split_once("' voted ")?;
+                    let (kind, rest) = rest.split_once(" '")?;
+                    let i = rest.rfind("' reason='")?;
+                    Some(LogEvent::Vote {
+                        player: LogPlayer::parse(player)?,
+                        kind,
+                        description: &rest[..i],
+                        reason: field(rest, "reason", Some("cmd"))?,
+                        command: field(rest, "cmd", Some("force"))?,
+                        forced: field(rest, "force", None)? != "0",
+                    })
+                } else {
+                    None
+                }
+            }
+            "server" => {
+                if let Some(rest) = message.strip_prefix("player has entered the game. ") {
+                    // Vanilla writes the id in hex, DDNet in decimal along with `sixup=`.
+                    let ddnet = rest.contains(" sixup=") || rest.contains("addr=<{");
+                    let client_id = field(rest, "ClientID", None)?;
+                    Some(LogEvent::Enter {
+                        client_id: i32::from_str_radix(client_id, if ddnet { 10 } else { 16 })
+                            .ok()?,
+                        address: address(rest),
+                    })
+                } else if let Some(rest) = message.strip_prefix("client dropped. ") {
+                    Some(LogEvent::Drop {
+                        client_id: field(rest, "cid", None)?.parse().ok()?,
+                        address: address(rest),
+                        reason: field(rest, "reason", None)?,
+                    })
+                } else if message.starts_with("ClientID=") && message.contains(" authed") {
+                    let client_id = field(message, "ClientID", None)?.parse().ok()?;
+                    let level = message[message.rfind('(')? + 1..].trim_end_matches(')');
+                    let key = field(message, "key", None);
+                    Some(LogEvent::RconAuth {
+                        client_id,
+                        level,
+                        key,
+                    })
+                } else if let Some(rest) = message.strip_prefix("maps/") {
+                    let (map, rest) = rest.split_once(".map ")?;
+                    let crc = match rest.strip_prefix("crc is ") {
+                        Some(crc) => Some(u32::from_str_radix(crc.trim(), 16).ok()?),
+                        None => None,
+                    };
+                    crc.map(|crc| LogEvent::MapChange {
+                        map,
+                        crc: Some(crc),
+                    })
+                } else {
+                    None
+                }
+            }
+            "datafile" => {
+                // Loaded maps before the crc is known, DDNet logs `loading done. datafile='...'`.
+                let file = field(message.strip_prefix("loading done. ")?, "datafile", None)?;
+                let map = file.strip_prefix("maps/")?.strip_suffix(".map")?;
+                Some(LogEvent::MapChange { map, crc: None })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Parses a line and reads its event in one go.
+///
+/// Lines that aren't console output are [LogEvent::Other].
+pub fn parse_log_event(line: &str) -> LogEvent<'_> {
+    LogLine::parse(line).map_or(LogEvent::Other, |x| x.event())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn player(client_id: i32, name: &str) -> LogPlayer<'_> {
+        LogPlayer { client_id, name }
+    }
+
+    #[test]
+    fn splits_lines() {
+        let expected = |timestamp| LogLine {
+            timestamp,
+            system: "game",
+            message: "start round type='DM' teamplay='0'",
+        };
+
+        assert_eq!(
+            LogLine::parse("[5f8d1a2b][game]: start round type='DM' teamplay='0'"),
+            Some(expected(Some("5f8d1a2b")))
+        );
+        assert_eq!(
+            LogLine::parse("[2020-10-15 12:34:56][game]: start round type='DM' teamplay='0'\r\n"),
+            Some(expected(Some("2020-10-15 12:34:56")))
+        );
+        assert_eq!(
+            LogLine::parse("2020-10-15 12:34:56 I game: start round type='DM' teamplay='0'"),
+            Some(expected(Some("2020-10-15 12:34:56")))
+        );
+        assert_eq!(
+            LogLine::parse("[game]: start round type='DM' teamplay='0'"),
+            Some(expected(None))
+        );
+        assert_eq!(LogLine::parse("Enter password:"), None);
+    }
+
+    #[test]
+    fn parses_events() {
+        assert_eq!(
+            parse_log_event("[chat]: 0:-2:nameless tee: hi: there"),
+            LogEvent::Chat {
+                player: player(0, "nameless tee"),
+                team: -2,
+                message: "hi: there"
+            }
+        );
+        assert_eq!(
+            parse_log_event("2020-10-15 12:34:56 I teamchat: 3:1:brainless tee: go"),
+            LogEvent::TeamChat {
+                player: player(3, "brainless tee"),
+                team: 1,
+                message: "go"
+            }
+        );
+        assert_eq!(
+            parse_log_event("[chat]: *** 'nameless tee' entered and joined the game"),
+            LogEvent::ServerChat {
+                message: "'nameless tee' entered and joined the game"
+            }
+        );
+        assert_eq!(
+            parse_log_event("[game]: kill killer='0:it's me' victim='1:you' weapon=5 special=0"),
+            LogEvent::Kill {
+                killer: player(0, "it's me"),
+                victim: player(1, "you"),
+                weapon: 5,
+                special: 0
+            }
+        );
+        assert_eq!(
+            parse_log_event(
+                "[game]: kill killer='0:x weapon=3' victim='1:y weapon=1 special=1' weapon=5 special=0"
+            ),
+            LogEvent::Kill {
+                killer: player(0, "x weapon=3"),
+                victim: player(1, "y weapon=1 special=1"),
+                weapon: 5,
+                special: 0
+            }
+        );
+        assert_eq!(
+            parse_log_event("[game]: team_join player='2:tee' team=0"),
+            LogEvent::TeamJoin {
+                player: player(2, "tee"),
+                team: 0
+            }
+        );
+        assert_eq!(
+            parse_log_event("[game]: team_join player='2:x team=3' team=0"),
+            LogEvent::TeamJoin {
+                player: player(2, "x team=3"),
+                team: 0
+            }
+        );
+        assert_eq!(
+            parse_log_event("[game]: team_join player='2:tee' m_Team=-1"),
+            LogEvent::TeamJoin {
+                player: player(2, "tee"),
+                team: -1
+            }
+        );
+        assert_eq!(
+            parse_log_event("[game]: leave player='2:tee'"),
+            LogEvent::Leave {
+                player: player(2, "tee")
+            }
+        );
+        assert_eq!(
+            parse_log_event(
+                "[5f8d1a2b][server]: player has entered the game. ClientID=a addr=1.2.3.4:5678"
+            ),
+            LogEvent::Enter {
+                client_id: 10,
+                address: Some("1.2.3.4:5678")
+            }
+        );
+        assert_eq!(
+            parse_log_event(
+                "2020-10-15 12:34:56 I server: player has entered the game. ClientID=12 \
+                 addr=<{1.2.3.4:5678}> sixup=0"
+            ),
+            LogEvent::Enter {
+                client_id: 12,
+                address: Some("1.2.3.4:5678")
+            }
+        );
+        assert_eq!(
+            parse_log_event("[server]: client dropped. cid=10 addr=1.2.3.4:5678 reason='Timeout'"),
+            LogEvent::Drop {
+                client_id: 10,
+                address: Some("1.2.3.4:5678"),
+                reason: "Timeout"
+            }
+        );
+        assert_eq!(
+            parse_log_event(
+                "2020-10-15 12:34:56 I server: client dropped. cid=10 addr=<{1.2.3.4:5678}> \
+                 reason='Timeout'"
+            ),
+            LogEvent::Drop {
+                client_id: 10,
+                address: Some("1.2.3.4:5678"),
+                reason: "Timeout"
+            }
+        );
+        assert_eq!(
+            parse_log_event(
+                "[game]: '0:tee' voted kick '1:griefer' reason='blocking' cmd='kick 1' force=0"
+            ),
+            LogEvent::Vote {
+                player: player(0, "tee"),
+                kind: "kick",
+                description: "1:griefer",
+                reason: "blocking",
+                command: "kick 1",
+                forced: false
+            }
+        );
+        assert_eq!(
+            parse_log_event("[server]: ClientID=0 authed (admin)"),
+            LogEvent::RconAuth {
+                client_id: 0,
+                level: "admin",
+                key: None
+            }
+        );
+        assert_eq!(
+            parse_log_event(
+                "[server]: ClientID=0 addr=1.2.3.4:5678 authed with key=mod1 (moderator)"
+            ),
+            LogEvent::RconAuth {
+                client_id: 0,
+                level: "moderator",
+                key: Some("mod1")
+            }
+        );
+        assert_eq!(
+            parse_log_event("[server]: maps/ctf5.map crc is 8b1a3c07"),
+            LogEvent::MapChange {
+                map: "ctf5",
+                crc: Some(0x8b1a3c07)
+            }
+        );
+        assert_eq!(
+            parse_log_event("[datafile]: loading done. datafile='maps/Multeasymap.map'"),
+            LogEvent::MapChange {
+                map: "Multeasymap",
+                crc: None
+            }
+        );
+        assert_eq!(
+            parse_log_event("[server]: maps/ctf5.map sha256 is 0123"),
+            LogEvent::Other
+        );
+        assert_eq!(parse_log_event("[game]: kill killer='x"), LogEvent::Other);
+    }
+}
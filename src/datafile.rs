@@ -0,0 +1,341 @@
+//! Reader for the datafile format used by teeworlds for maps.
+//!
+//! A datafile holds items, small lists of ints grouped by type, and data blocks, like
+//! strings and images, which items point to by index. Version 4 compresses the data
+//! blocks with zlib, version 3 doesn't.
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use flate2::read::ZlibDecoder;
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+use crate::errors::*;
+
+/// The size of the header, including the magic and version.
+const HEADER_SIZE: usize = 36;
+
+/// The most memory reserved up front for a data block, the sizes come from the file.
+const MAX_PREALLOCATION: usize = 16 * 1024 * 1024;
+
+/// An item, identified by its type and id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DatafileItem {
+    pub type_id: u16,
+    pub id: u16,
+    pub data: Vec<i32>,
+}
+
+/// A parsed datafile, the data blocks are decompressed on access.
+#[derive(Debug, Clone)]
+pub struct Datafile {
+    version: i32,
+    items: Vec<DatafileItem>,
+    data: Vec<Vec<u8>>,
+    /// The uncompressed sizes, only in version 4.
+    data_sizes: Option<Vec<usize>>,
+}
+
+fn read_count<R: Read>(r: &mut R) -> Result<usize> {
+    let value = r.read_i32::<LittleEndian>()?;
+    if value < 0 {
+        return Err(RequestError::InvalidData("negative datafile count"));
+    }
+    Ok(value as usize)
+}
+
+fn read_counts<R: Read>(r: &mut R, len: usize) -> Result<Vec<usize>> {
+    (0..len).map(|_| read_count(r)).collect()
+}
+
+impl Datafile {
+    /// Parses a datafile of version 3 or 4.
+    pub fn parse(data: &[u8]) -> Result<Datafile> {
+        if data.len() < HEADER_SIZE {
+            return Err(RequestError::InvalidData("datafile too short"));
+        }
+        // Files written on big endian machines have it reversed.
+        if &data[..4] != b"DATA" && &data[..4] != b"ATAD" {
+            return Err(RequestError::InvalidData("not a datafile"));
+        }
+
+        let mut r = Cursor::new(&data[4..]);
+        let version = r.read_i32::<LittleEndian>()?;
+        if version != 3 && version != 4 {
+            return Err(RequestError::InvalidData("unsupported datafile version"));
+        }
+
+        // Size and swap length, not needed to read it.
+        r.read_i32::<LittleEndian>()?;
+        r.read_i32::<LittleEndian>()?;
+        let num_item_types = read_count(&mut r)?;
+        let num_items = read_count(&mut r)?;
+        let num_data = read_count(&mut r)?;
+        let items_size = read_count(&mut r)?;
+        let data_size = read_count(&mut r)?;
+
+        // The item types tell where each type starts, the items already carry their type.
+        let tables_size = (num_item_types * 3 + num_items + num_data * (version as usize - 2)) * 4;
+        let items_start = HEADER_SIZE + tables_size;
+        let data_start = items_start + items_size;
+        if data.len() < data_start + data_size {
+            return Err(RequestError::InvalidData("datafile truncated"));
+        }
+
+        let mut r = Cursor::new(&data[HEADER_SIZE..items_start]);
+        read_counts(&mut r, num_item_types * 3)?;
+        let item_offsets = read_counts(&mut r, num_items)?;
+        let data_offsets = read_counts(&mut r, num_data)?;
+        let data_sizes = if version == 4 {
+            Some(read_counts(&mut r, num_data)?)
+        } else {
+            None
+        };
+
+        let items_data = &data[items_start..data_start];
+        let items = item_offsets
+            .iter()
+            .map(|&offset| {
+                let mut r = Cursor::new(
+                    items_data
+                        .get(offset..)
+                        .ok_or(RequestError::InvalidData("item out of bounds"))?,
+                );
+                let key = r.read_i32::<LittleEndian>()?;
+                let size = read_count(&mut r)?;
+                let data = (0..size / 4)
+                    .map(|_| r.read_i32::<LittleEndian>())
+                    .collect::<std::io::Result<_>>()
+                    .map_err(|_| RequestError::InvalidData("item out of bounds"))?;
+
+                Ok(DatafileItem {
+                    type_id: (key >> 16) as u16,
+                    id: key as u16,
+                    data,
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        let blocks = &data[data_start..data_start + data_size];
+        let data = data_offsets
+            .iter()
+            .enumerate()
+            .map(|(i, &start)| {
+                let end = data_offsets.get(i + 1).copied().unwrap_or(data_size);
+                blocks
+                    .get(start..end)
+                    .map(|x| x.to_vec())
+                    .ok_or(RequestError::InvalidData("data block out of bounds"))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Datafile {
+            version,
+            items,
+            data,
+            data_sizes,
+        })
+    }
+
+    /// Reads and parses a datafile.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Datafile> {
+        Datafile::parse(&std::fs::read(path)?)
+    }
+
+    pub fn version(&self) -> i32 {
+        self.version
+    }
+
+    pub fn items(&self) -> &[DatafileItem] {
+        &self.items
+    }
+
+    /// The items of the given type.
+    pub fn items_of_type(&self, type_id: u16) -> impl Iterator<Item = &DatafileItem> {
+        self.items.iter().filter(move |x| x.type_id == type_id)
+    }
+
+    /// The item with the given type and id.
+    pub fn find_item(&self, type_id: u16, id: u16) -> Option<&DatafileItem> {
+        self.items
+            .iter()
+            .find(|x| x.type_id == type_id && x.id == id)
+    }
+
+    /// The number of data blocks.
+    pub fn num_data(&self) -> usize {
+        self.data.len()
+    }
+
+    /// The data block at the given index, decompressed.
+    pub fn data(&self, index: usize) -> Result<Vec<u8>> {
+        let block = self.data.get(index).ok_or(RequestError::Missing)?;
+
+        let size = match &self.data_sizes {
+            Some(sizes) => sizes[index],
+            None => return Ok(block.clone()),
+        };
+
+        // Reading past the announced size checks the zlib checksum.
+        let mut data = Vec::with_capacity(size.min(MAX_PREALLOCATION));
+        ZlibDecoder::new(&block[..])
+            .take(size as u64 + 1)
+            .read_to_end(&mut data)
+            .map_err(|_| RequestError::InvalidData("corrupted data block"))?;
+        if data.len() != size {
+            return Err(RequestError::InvalidData("data block size mismatch"));
+        }
+        Ok(data)
+    }
+
+    /// The zero terminated string in the data block at the given index.
+    ///
+    /// Items refer to missing strings with a negative index, which gives `None`.
+    pub fn string(&self, index: i32) -> Result<Option<String>> {
+        if index < 0 {
+            return Ok(None);
+        }
+
+        let data = self.data(index as usize)?;
+        let end = data.iter().position(|&x| x == 0).unwrap_or(data.len());
+        Ok(Some(String::from_utf8_lossy(&data[..end]).into_owned()))
+    }
+}
+
+/// Builds datafiles for the tests, compressing the data like version 4.
+#[cfg(test)]
+pub(crate) fn build_datafile(items: &[DatafileItem], data: &[&[u8]]) -> Vec<u8> {
+    use byteorder::WriteBytesExt;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    let mut items = items.to_vec();
+    items.sort_by_key(|x| (x.type_id, x.id));
+
+    let mut types: Vec<(u16, usize, usize)> = Vec::new();
+    for (i, item) in items.iter().enumerate() {
+        match types.last_mut() {
+            Some((type_id, _, num)) if *type_id == item.type_id => *num += 1,
+            _ => types.push((item.type_id, i, 1)),
+        }
+    }
+
+    let mut items_data = Vec::new();
+    let mut item_offsets = Vec::new();
+    for item in &items {
+        item_offsets.push(items_data.len() as i32);
+        items_data
+            .write_i32::<LittleEndian>(((item.type_id as i32) << 16) | item.id as i32)
+            .unwrap();
+        items_data
+            .write_i32::<LittleEndian>(item.data.len() as i32 * 4)
+            .unwrap();
+        for &x in &item.data {
+            items_data.write_i32::<LittleEndian>(x).unwrap();
+        }
+    }
+
+    let mut blocks = Vec::new();
+    let mut data_offsets = Vec::new();
+    for block in data {
+        data_offsets.push(blocks.len() as i32);
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(block).unwrap();
+        blocks.extend(encoder.finish().unwrap());
+    }
+
+    let mut file = b"DATA".to_vec();
+    let mut ints = vec![4, 0, 0];
+    ints.extend(&[
+        types.len() as i32,
+        items.len() as i32,
+        data.len() as i32,
+        items_data.len() as i32,
+        blocks.len() as i32,
+    ]);
+    for (type_id, start, num) in types {
+        ints.extend(&[type_id as i32, start as i32, num as i32]);
+    }
+    ints.extend(item_offsets);
+    ints.extend(data_offsets);
+    ints.extend(data.iter().map(|x| x.len() as i32));
+    for x in ints {
+        file.write_i32::<LittleEndian>(x).unwrap();
+    }
+    file.extend(items_data);
+    file.extend(blocks);
+    file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn reads_items_and_data() {
+        let items = vec![
+            DatafileItem {
+                type_id: 5,
+                id: 1,
+                data: vec![3, 4],
+            },
+            DatafileItem {
+                type_id: 0,
+                id: 0,
+                data: vec![1],
+            },
+            DatafileItem {
+                type_id: 5,
+                id: 0,
+                data: vec![-1],
+            },
+        ];
+        let big: Vec<u8> = (0..5000u32).map(|x| (x % 7) as u8).collect();
+        let file = build_datafile(&items, &[b"nameless tee\0", &big]);
+
+        let datafile = Datafile::parse(&file).unwrap();
+        assert_eq!(datafile.version(), 4);
+        assert_eq!(datafile.items().len(), 3);
+        assert_eq!(datafile.items_of_type(5).count(), 2);
+        assert_eq!(datafile.find_item(5, 1).unwrap().data, vec![3, 4]);
+        assert_eq!(datafile.find_item(0, 0).unwrap().data, vec![1]);
+        assert_eq!(datafile.find_item(1, 0), None);
+
+        assert_eq!(datafile.num_data(), 2);
+        assert_eq!(datafile.string(0).unwrap().unwrap(), "nameless tee");
+        assert_eq!(datafile.string(-1).unwrap(), None);
+        assert_eq!(datafile.data(1).unwrap(), big);
+        assert!(datafile.data(2).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        let file = build_datafile(&[], &[b"data"]);
+        assert!(Datafile::parse(&file).is_ok());
+
+        assert!(Datafile::parse(b"DATA").is_err());
+        assert!(Datafile::parse(&file[..file.len() - 1]).is_err());
+
+        let mut wrong = file.clone();
+        wrong[..4].copy_from_slice(b"PNG ");
+        assert!(Datafile::parse(&wrong).is_err());
+
+        let mut wrong = file.clone();
+        wrong[4] = 5;
+        assert!(Datafile::parse(&wrong).is_err());
+
+        // A lying uncompressed size doesn't reserve it all up front.
+        let mut wrong = file.clone();
+        wrong[HEADER_SIZE + 4..HEADER_SIZE + 8].copy_from_slice(&i32::MAX.to_le_bytes());
+        let datafile = Datafile::parse(&wrong).unwrap();
+        assert!(datafile.data(0).is_err());
+
+        // A corrupted data block only fails when read.
+        let mut wrong = file;
+        let last = wrong.len() - 3;
+        wrong[last] ^= 0xff;
+        let datafile = Datafile::parse(&wrong).unwrap();
+        assert!(datafile.data(0).is_err());
+    }
+}
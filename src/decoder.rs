@@ -0,0 +1,227 @@
+//! Decoders for the data mods put in the info fields.
+//!
+//! Some mods encode extra data in the version, the name or the reserved player strings.
+//! An [InfoDecoder] registered in an [InfoDecoders] reads it from the infos it's given,
+//! attaching what it returns to [ServerInfo::extensions].
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::server::*;
+
+/// The format of an info response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InfoFormat {
+    /// The vanilla `inf3` packet.
+    Vanilla,
+    /// The DDNet `iext` packets.
+    Extended,
+}
+
+/// The servers a decoder applies to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DecoderTarget {
+    /// Every info received in this format.
+    Format(InfoFormat),
+    /// Every info with exactly this game type.
+    GameType(String),
+}
+
+impl DecoderTarget {
+    fn matches(&self, info: &ServerInfo<'_>) -> bool {
+        match self {
+            DecoderTarget::Format(format) => info.format() == *format,
+            DecoderTarget::GameType(game_type) => info.game_type == game_type.as_str(),
+        }
+    }
+}
+
+/// Reads mod specific data from the raw info fields.
+pub trait InfoDecoder: Send + Sync + 'static {
+    /// The data decoded, found with [ServerInfo::extension].
+    type Output: Any + Send + Sync;
+
+    /// Decodes the data, or returns `None` if the info doesn't carry it.
+    fn decode(&self, info: &ServerInfo<'_>) -> Option<Self::Output>;
+}
+
+impl<F, T> InfoDecoder for F
+where
+    F: Fn(&ServerInfo<'_>) -> Option<T> + Send + Sync + 'static,
+    T: Any + Send + Sync,
+{
+    type Output = T;
+
+    fn decode(&self, info: &ServerInfo<'_>) -> Option<T> {
+        self(info)
+    }
+}
+
+/// The data decoded from an info, one value for each type.
+#[derive(Clone, Default)]
+pub struct InfoExtensions {
+    values: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl InfoExtensions {
+    pub fn get<T: Any>(&self) -> Option<&T> {
+        self.values
+            .get(&TypeId::of::<T>())
+            .and_then(|x| x.downcast_ref())
+    }
+
+    /// Adds a value, replacing the one of the same type.
+    pub fn insert<T: Any + Send + Sync>(&mut self, value: T) {
+        self.values.insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+}
+
+impl fmt::Debug for InfoExtensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InfoExtensions")
+            .field("len", &self.values.len())
+            .finish()
+    }
+}
+
+type ErasedDecoder = Box<dyn Fn(&ServerInfo<'_>, &mut InfoExtensions) + Send + Sync>;
+
+/// A set of decoders, given to the queries with
+/// [InfoQuery::with_decoders](crate::InfoQuery::with_decoders) or run on an info with
+/// [ServerInfo::decode_extensions].
+#[derive(Default)]
+pub struct InfoDecoders {
+    decoders: Vec<(DecoderTarget, ErasedDecoder)>,
+}
+
+impl InfoDecoders {
+    pub fn new() -> InfoDecoders {
+        InfoDecoders::default()
+    }
+
+    /// Adds a decoder for the infos matching the target.
+    ///
+    /// Decoders run in the order they were registered, a later one replaces the data of
+    /// the same type decoded by an earlier one.
+    pub fn register<D: InfoDecoder>(&mut self, target: DecoderTarget, decoder: D) {
+        let erased: ErasedDecoder = Box::new(move |info, extensions| {
+            if let Some(value) = decoder.decode(info) {
+                extensions.insert(value);
+            }
+        });
+        self.decoders.push((target, erased));
+    }
+
+    /// Runs the decoders matching the info.
+    pub fn decode(&self, info: &ServerInfo<'_>) -> InfoExtensions {
+        let mut extensions = InfoExtensions::default();
+        for (target, decoder) in &self.decoders {
+            if target.matches(info) {
+                decoder(info, &mut extensions);
+            }
+        }
+        extensions
+    }
+
+    pub fn len(&self) -> usize {
+        self.decoders.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.decoders.is_empty()
+    }
+}
+
+impl fmt::Debug for InfoDecoders {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let targets: Vec<&DecoderTarget> = self.decoders.iter().map(|(x, _)| x).collect();
+        f.debug_struct("InfoDecoders")
+            .field("targets", &targets)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::borrow::Cow;
+
+    /// The levels a made up mod puts in the reserved player strings.
+    #[derive(Debug, PartialEq)]
+    struct Levels(Vec<u32>);
+
+    struct LevelDecoder;
+
+    impl InfoDecoder for LevelDecoder {
+        type Output = Levels;
+
+        fn decode(&self, info: &ServerInfo<'_>) -> Option<Levels> {
+            let levels = info
+                .players
+                .iter()
+                .map(|x| x.reserved.strip_prefix("lvl:")?.parse().ok())
+                .collect::<Option<_>>()?;
+            Some(Levels(levels))
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Motto(String);
+
+    #[test]
+    fn decodes_extensions() {
+        let mut decoders = InfoDecoders::new();
+        decoders.register(DecoderTarget::GameType("LevelMod".to_owned()), LevelDecoder);
+        decoders.register(
+            DecoderTarget::GameType("LevelMod".to_owned()),
+            |info: &ServerInfo<'_>| {
+                let (_, motto) = info.name.split_once(" | ")?;
+                Some(Motto(motto.to_owned()))
+            },
+        );
+        assert_eq!(decoders.len(), 2);
+
+        let mut info = ServerInfo::parse_main(include_bytes!("samples/server_info.data")).unwrap();
+        info.decode_extensions(&decoders);
+        assert!(info.extension::<Levels>().is_none());
+
+        info.game_type = Cow::Borrowed("LevelMod");
+        info.name = Cow::Borrowed("Level up | no pain no gain");
+        for (i, player) in info.players.iter_mut().enumerate() {
+            player.reserved = Cow::Owned(format!("lvl:{}", i));
+        }
+        info.decode_extensions(&decoders);
+
+        let levels = info.extension::<Levels>().unwrap();
+        assert_eq!(levels.0.len(), info.players.len());
+        assert_eq!(levels.0[3], 3);
+        assert_eq!(
+            info.extension::<Motto>(),
+            Some(&Motto("no pain no gain".to_owned()))
+        );
+
+        // Extensions survive copying the info.
+        let owned = info.into_owned();
+        assert_eq!(owned.extensions.len(), 2);
+
+        // Other decoders don't see them.
+        let mut info = owned.clone();
+        info.decode_extensions(&InfoDecoders::new());
+        assert!(info.extensions.is_empty());
+    }
+}
@@ -0,0 +1,505 @@
+//! Reader for the demos recorded by the client and server.
+//!
+//! A demo starts with a header, the timeline markers and a copy of the map, followed by
+//! chunks: tick markers, snapshots, snapshot deltas and messages. The chunk data is
+//! packed as ints, then compressed with [Huffman](crate::protocol::Huffman).
+
+use byteorder::{BigEndian, LittleEndian, ReadBytesExt};
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+use crate::errors::*;
+use crate::map::*;
+use crate::protocol::*;
+use crate::server::*;
+
+const MAGIC: &[u8; 7] = b"TWDEMO\0";
+
+/// The most timeline markers a demo holds.
+pub const MAX_TIMELINE_MARKERS: usize = 64;
+
+/// The extension added by DDNet demos to carry the map sha256.
+const SHA256_EXTENSION: [u8; 16] = [
+    0x6b, 0xe6, 0xda, 0x4a, 0xce, 0xbd, 0x38, 0x0c, 0x9b, 0x5b, 0x12, 0x89, 0xc8, 0x42, 0xd7, 0x80,
+];
+
+const CHUNK_TICK_MARKER: u8 = 0x80;
+const CHUNK_KEYFRAME: u8 = 0x40;
+/// The tick is given as a difference with the previous one, since version 5.
+const CHUNK_TICK_COMPRESSED: u8 = 0x20;
+const CHUNK_TICK_MASK: u8 = 0x1f;
+/// The mask of the tick difference before version 5.
+const CHUNK_TICK_MASK_LEGACY: u8 = 0x3f;
+
+const CHUNK_SNAPSHOT: u8 = 1;
+const CHUNK_MESSAGE: u8 = 2;
+const CHUNK_DELTA: u8 = 3;
+
+/// The fixed part of a demo.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DemoHeader {
+    pub version: u8,
+    /// The network version of the game that recorded it, like `0.6 626fce9a778df4d4`.
+    pub net_version: String,
+    pub map_name: String,
+    pub map_size: u32,
+    pub map_crc: u32,
+    /// Who recorded it, `client` or `server`.
+    pub kind: String,
+    /// In seconds.
+    pub length: i32,
+    /// When it was recorded, like `2021-05-09_16-02-17`.
+    pub timestamp: String,
+}
+
+impl DemoHeader {
+    pub fn map_checksum(&self) -> MapChecksum {
+        MapChecksum {
+            crc: self.map_crc,
+            size: self.map_size,
+        }
+    }
+
+    /// Whether the demo was recorded on the map the server is running.
+    pub fn matches(&self, info: &ServerInfo) -> bool {
+        self.map_name == info.map && self.map_checksum().matches(info)
+    }
+}
+
+/// Reads a fixed size, zero padded string.
+fn read_string<R: Read>(r: &mut R, len: usize) -> Result<String> {
+    let mut buf = vec![0; len];
+    r.read_exact(&mut buf)?;
+    let end = buf.iter().position(|&x| x == 0).unwrap_or(len);
+    Ok(String::from_utf8_lossy(&buf[..end]).into_owned())
+}
+
+/// A demo file, the chunks are read through [Demo::chunks].
+#[derive(Debug, Clone)]
+pub struct Demo {
+    header: DemoHeader,
+    timeline_markers: Vec<i32>,
+    map_sha256: Option<[u8; 32]>,
+    data: Vec<u8>,
+    map_start: usize,
+    chunks_start: usize,
+}
+
+impl Demo {
+    /// Parses the header of a demo, versions 3 to 6 are supported.
+    pub fn parse(data: Vec<u8>) -> Result<Demo> {
+        let mut r = Cursor::new(&data[..]);
+
+        let mut magic = [0; 7];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(RequestError::InvalidData("not a demo"));
+        }
+
+        let version = r.read_u8()?;
+        if !(3..=6).contains(&version) {
+            return Err(RequestError::InvalidData("unsupported demo version"));
+        }
+
+        let header = DemoHeader {
+            version,
+            net_version: read_string(&mut r, 64)?,
+            map_name: read_string(&mut r, 64)?,
+            map_size: r.read_u32::<BigEndian>()?,
+            map_crc: r.read_u32::<BigEndian>()?,
+            kind: read_string(&mut r, 8)?,
+            length: r.read_i32::<BigEndian>()?,
+            timestamp: read_string(&mut r, 20)?,
+        };
+
+        let mut timeline_markers = Vec::new();
+        if version >= 4 {
+            let num = r
+                .read_i32::<BigEndian>()?
+                .clamp(0, MAX_TIMELINE_MARKERS as i32) as usize;
+            for i in 0..MAX_TIMELINE_MARKERS {
+                let tick = r.read_i32::<BigEndian>()?;
+                if i < num {
+                    timeline_markers.push(tick);
+                }
+            }
+        }
+
+        let mut map_sha256 = None;
+        if version >= 6 {
+            let start = r.position();
+            let mut extension = [0; 16];
+            let mut sha256 = [0; 32];
+            if r.read_exact(&mut extension).is_ok()
+                && extension == SHA256_EXTENSION
+                && r.read_exact(&mut sha256).is_ok()
+            {
+                map_sha256 = Some(sha256);
+            } else {
+                r.set_position(start);
+            }
+        }
+
+        let map_start = r.position() as usize;
+        let chunks_start = map_start + header.map_size as usize;
+        if chunks_start > data.len() {
+            return Err(RequestError::InvalidData("demo truncated"));
+        }
+
+        Ok(Demo {
+            header,
+            timeline_markers,
+            map_sha256,
+            data,
+            map_start,
+            chunks_start,
+        })
+    }
+
+    /// Reads a demo file and parses its header.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Demo> {
+        Demo::parse(std::fs::read(path)?)
+    }
+
+    pub fn header(&self) -> &DemoHeader {
+        &self.header
+    }
+
+    /// The ticks the player marked while recording.
+    pub fn timeline_markers(&self) -> &[i32] {
+        &self.timeline_markers
+    }
+
+    /// The sha256 of the map, only in DDNet demos.
+    pub fn map_sha256(&self) -> Option<&[u8; 32]> {
+        self.map_sha256.as_ref()
+    }
+
+    /// The map the demo was recorded on.
+    pub fn map(&self) -> &[u8] {
+        &self.data[self.map_start..self.chunks_start]
+    }
+
+    /// The chunks of the demo, with the deltas applied to give the full snapshots.
+    pub fn chunks(&self) -> DemoChunks<'_> {
+        DemoChunks {
+            data: &self.data[self.chunks_start..],
+            version: self.header.version,
+            tick: 0,
+            keyframe: false,
+            snapshot: Snapshot::new(),
+            failed: false,
+        }
+    }
+}
+
+/// What a demo recorded at a tick.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DemoChunk {
+    /// The state of the game, keyframes are the ones a player can seek to.
+    Snapshot {
+        tick: i32,
+        keyframe: bool,
+        snapshot: Snapshot,
+    },
+    /// A game or system message, as sent by the server.
+    Message { tick: i32, data: Vec<u8> },
+}
+
+/// Iterator over the chunks of a [Demo], stops after the first error.
+#[derive(Debug)]
+pub struct DemoChunks<'a> {
+    data: &'a [u8],
+    version: u8,
+    tick: i32,
+    keyframe: bool,
+    snapshot: Snapshot,
+    failed: bool,
+}
+
+/// Parses a snapshot as stored in memory: its size and number of items, the offset of
+/// each item, then the items with their key.
+fn parse_snapshot(ints: &[i32]) -> Result<Snapshot> {
+    let malformed = || RequestError::InvalidData("malformed demo snapshot");
+
+    let (data_size, num_items) = match ints {
+        &[data_size, num_items, ..] if data_size >= 0 && num_items >= 0 => {
+            (data_size as usize / 4, num_items as usize)
+        }
+        _ => return Err(malformed()),
+    };
+    let offsets = ints.get(2..2 + num_items).ok_or_else(malformed)?;
+    let items = ints
+        .get(2 + num_items..2 + num_items + data_size)
+        .ok_or_else(malformed)?;
+
+    let mut snapshot = Snapshot::new();
+    for (i, &offset) in offsets.iter().enumerate() {
+        let start = offset as usize / 4;
+        let end = offsets.get(i + 1).map_or(items.len(), |&x| x as usize / 4);
+        let (&key, data) = items
+            .get(start..end)
+            .and_then(|x| x.split_first())
+            .ok_or_else(malformed)?;
+        snapshot.insert((key >> 16) as u16, key as u16, data.to_vec());
+    }
+
+    Ok(snapshot)
+}
+
+impl DemoChunks<'_> {
+    fn read_u8(&mut self) -> Result<u8> {
+        let (&byte, rest) = self
+            .data
+            .split_first()
+            .ok_or(RequestError::InvalidData("demo chunk truncated"))?;
+        self.data = rest;
+        Ok(byte)
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&[u8]> {
+        if len > self.data.len() {
+            return Err(RequestError::InvalidData("demo chunk truncated"));
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn next_chunk(&mut self) -> Result<Option<DemoChunk>> {
+        while !self.data.is_empty() {
+            let chunk = self.read_u8()?;
+
+            if chunk & CHUNK_TICK_MARKER != 0 {
+                self.keyframe = chunk & CHUNK_KEYFRAME != 0;
+                let (compressed, delta) = if self.version >= 5 {
+                    (chunk & CHUNK_TICK_COMPRESSED != 0, chunk & CHUNK_TICK_MASK)
+                } else {
+                    let delta = chunk & CHUNK_TICK_MASK_LEGACY;
+                    (delta != 0, delta)
+                };
+
+                self.tick = if compressed {
+                    self.tick + delta as i32
+                } else {
+                    Cursor::new(self.read_bytes(4)?).read_i32::<BigEndian>()?
+                };
+                continue;
+            }
+
+            let kind = (chunk & 0x60) >> 5;
+            let size = match chunk & 0x1f {
+                30 => self.read_u8()? as usize,
+                31 => Cursor::new(self.read_bytes(2)?).read_u16::<LittleEndian>()? as usize,
+                size => size as usize,
+            };
+            let data = decompress(self.read_bytes(size)?)?;
+            let ints = unpack_ints(&data)?;
+
+            let chunk = match kind {
+                CHUNK_SNAPSHOT | CHUNK_DELTA => {
+                    self.snapshot = if kind == CHUNK_SNAPSHOT {
+                        parse_snapshot(&ints)?
+                    } else {
+                        self.snapshot.apply_delta(&ints)?
+                    };
+                    DemoChunk::Snapshot {
+                        tick: self.tick,
+                        keyframe: self.keyframe,
+                        snapshot: self.snapshot.clone(),
+                    }
+                }
+                CHUNK_MESSAGE => {
+                    // Messages are stored padded to ints.
+                    let data = ints.iter().flat_map(|x| x.to_le_bytes()).collect();
+                    DemoChunk::Message {
+                        tick: self.tick,
+                        data,
+                    }
+                }
+                _ => return Err(RequestError::InvalidData("unknown demo chunk type")),
+            };
+            return Ok(Some(chunk));
+        }
+
+        Ok(None)
+    }
+}
+
+impl Iterator for DemoChunks<'_> {
+    type Item = Result<DemoChunk>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let result = self.next_chunk();
+        self.failed = result.is_err();
+        result.transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn write_string(buf: &mut Vec<u8>, value: &str, len: usize) {
+        let mut bytes = value.as_bytes().to_vec();
+        bytes.resize(len, 0);
+        buf.extend(bytes);
+    }
+
+    fn header(version: u8, map: &[u8]) -> Vec<u8> {
+        let checksum = MapChecksum::of(map);
+        let mut demo = MAGIC.to_vec();
+        demo.push(version);
+        write_string(&mut demo, GAME_VERSION, 64);
+        write_string(&mut demo, "Multeasymap", 64);
+        demo.extend(&checksum.size.to_be_bytes());
+        demo.extend(&checksum.crc.to_be_bytes());
+        write_string(&mut demo, "server", 8);
+        demo.extend(&95i32.to_be_bytes());
+        write_string(&mut demo, "2021-05-09_16-02-17", 20);
+
+        demo.extend(&2i32.to_be_bytes());
+        for i in 0..MAX_TIMELINE_MARKERS as i32 {
+            demo.extend(&(i * 50 + 100).to_be_bytes());
+        }
+        demo
+    }
+
+    fn chunk(demo: &mut Vec<u8>, kind: u8, ints: &[i32]) {
+        let data = compress(&pack_ints(ints));
+        match data.len() {
+            len if len < 30 => demo.push(kind << 5 | len as u8),
+            len if len < 256 => demo.extend(&[kind << 5 | 30, len as u8]),
+            len => {
+                demo.push(kind << 5 | 31);
+                demo.extend(&(len as u16).to_le_bytes());
+            }
+        }
+        demo.extend(data);
+    }
+
+    fn sample_snapshot() -> Snapshot {
+        let mut snapshot = Snapshot::new();
+        snapshot.insert(PlayerInfo::TYPE_ID, 0, vec![1, 0, TEAM_RED, 3, 20]);
+        snapshot.insert(Flag::TYPE_ID, 1, vec![64, 64, TEAM_BLUE]);
+        snapshot
+    }
+
+    /// Lays out a snapshot like the recorder does.
+    fn snapshot_ints(snapshot: &Snapshot) -> Vec<i32> {
+        let mut offsets = Vec::new();
+        let mut items = Vec::new();
+        for (type_id, id, data) in snapshot.items() {
+            offsets.push(items.len() as i32 * 4);
+            items.push(((type_id as i32) << 16) | id as i32);
+            items.extend_from_slice(data);
+        }
+
+        let mut ints = vec![items.len() as i32 * 4, offsets.len() as i32];
+        ints.extend(offsets);
+        ints.extend(items);
+        ints
+    }
+
+    #[test]
+    fn reads_demos() {
+        let map: Vec<u8> = (0..100).collect();
+        let mut demo = header(5, &map);
+        demo.extend(&map);
+
+        let first = sample_snapshot();
+        let mut second = first.clone();
+        second.insert(Flag::TYPE_ID, 1, vec![80, 64, TEAM_BLUE]);
+
+        // An absolute tick, then a difference.
+        demo.push(CHUNK_TICK_MARKER | CHUNK_KEYFRAME);
+        demo.extend(&1000i32.to_be_bytes());
+        chunk(&mut demo, CHUNK_SNAPSHOT, &snapshot_ints(&first));
+        demo.push(CHUNK_TICK_MARKER | CHUNK_TICK_COMPRESSED | 2);
+        chunk(&mut demo, CHUNK_DELTA, &first.create_delta(&second));
+        chunk(
+            &mut demo,
+            CHUNK_MESSAGE,
+            &[i32::from_le_bytes([0x07, 0x00, 0x01, 0x00])],
+        );
+
+        let demo = Demo::parse(demo).unwrap();
+        let header = demo.header();
+        assert_eq!(header.version, 5);
+        assert_eq!(header.net_version, GAME_VERSION);
+        assert_eq!(header.map_name, "Multeasymap");
+        assert_eq!(header.map_checksum(), MapChecksum::of(&map));
+        assert_eq!(header.kind, "server");
+        assert_eq!(header.length, 95);
+        assert_eq!(header.timestamp, "2021-05-09_16-02-17");
+        assert_eq!(demo.timeline_markers(), &[100, 150]);
+        assert_eq!(demo.map_sha256(), None);
+        assert_eq!(demo.map(), &map[..]);
+
+        let chunks: Vec<_> = demo.chunks().collect::<Result<_>>().unwrap();
+        assert_eq!(
+            chunks,
+            vec![
+                DemoChunk::Snapshot {
+                    tick: 1000,
+                    keyframe: true,
+                    snapshot: first,
+                },
+                DemoChunk::Snapshot {
+                    tick: 1002,
+                    keyframe: false,
+                    snapshot: second,
+                },
+                DemoChunk::Message {
+                    tick: 1002,
+                    data: vec![0x07, 0x00, 0x01, 0x00],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn matches_server_maps() {
+        let info = ServerInfo::parse_main(include_bytes!("samples/server_info.data")).unwrap();
+        let mut demo = header(6, &[]);
+        // The sha256 extension of DDNet.
+        demo.extend(&SHA256_EXTENSION);
+        demo.extend(&[0xab; 32]);
+        // Claim the map of the sample, without embedding it.
+        let size_at = 7 + 1 + 64 + 64;
+        demo[size_at..size_at + 4].copy_from_slice(&0u32.to_be_bytes());
+        demo[size_at + 4..size_at + 8]
+            .copy_from_slice(&(info.map_crc.unwrap() as u32).to_be_bytes());
+
+        let demo = Demo::parse(demo).unwrap();
+        assert_eq!(demo.map_sha256(), Some(&[0xab; 32]));
+        let mut header = demo.header().clone();
+        assert!(!header.matches(&info));
+        header.map_size = info.map_size.unwrap() as u32;
+        assert!(header.matches(&info));
+    }
+
+    #[test]
+    fn rejects_invalid_demos() {
+        assert!(Demo::parse(b"TWDEMO\0".to_vec()).is_err());
+        assert!(Demo::parse(b"TWDEMX\0\x05".to_vec()).is_err());
+        assert!(Demo::parse(header(7, &[])).is_err());
+        // The map is missing.
+        assert!(Demo::parse(header(5, &[1, 2, 3])).is_err());
+
+        // A legacy tick marker, then a truncated chunk.
+        let mut demo = header(4, &[]);
+        demo.push(CHUNK_TICK_MARKER | 5);
+        demo.push(CHUNK_SNAPSHOT << 5 | 20);
+        demo.push(0);
+        let demo = Demo::parse(demo).unwrap();
+        let mut chunks = demo.chunks();
+        assert!(chunks.next().unwrap().is_err());
+        assert!(chunks.next().is_none());
+    }
+}
@@ -0,0 +1,247 @@
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use crate::errors::*;
+use crate::query::*;
+
+/// How often an idle connection sends an empty line by default.
+pub const DEFAULT_KEEPALIVE: Duration = Duration::from_secs(30);
+
+const PASSWORD_PROMPT: &str = "Enter password:";
+const AUTH_SUCCESS: &str = "Authentication successful";
+
+/// A client for the external console of a server, enabled with `ec_port` and `ec_password`.
+///
+/// Econ is a line based TCP protocol: the server asks for the password, then every
+/// line sent is executed as a command and every console line is sent back.
+#[derive(Debug)]
+pub struct Econ {
+    reader: BufReader<TcpStream>,
+    pending: Vec<u8>,
+    keepalive: Option<Duration>,
+    last_sent: Instant,
+}
+
+impl Econ {
+    /// Connects to the econ port of a server and authenticates.
+    ///
+    /// Waits [DEFAULT_TIMEOUT] for each step of the authentication.
+    pub fn connect<A: ToSocketAddrs>(address: A, password: &str) -> Result<Econ> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_read_timeout(Some(DEFAULT_TIMEOUT))?;
+        Econ::from_stream(stream, password)
+    }
+
+    /// Authenticates on an already connected stream.
+    ///
+    /// Waits for the stream read timeout, or [DEFAULT_TIMEOUT] if it has none,
+    /// for each step of the authentication.
+    pub fn from_stream(stream: TcpStream, password: &str) -> Result<Econ> {
+        let timeout = stream.read_timeout()?.unwrap_or(DEFAULT_TIMEOUT);
+        let mut econ = Econ {
+            reader: BufReader::new(stream),
+            pending: Vec::new(),
+            keepalive: Some(DEFAULT_KEEPALIVE),
+            last_sent: Instant::now(),
+        };
+
+        loop {
+            let line = econ
+                .read_line_timeout(Some(timeout))?
+                .ok_or(RequestError::Timeout)?;
+            if line.starts_with(PASSWORD_PROMPT) {
+                break;
+            }
+            log::debug!("econ before prompt: {}", line);
+        }
+
+        econ.send(password)?;
+
+        let line = econ
+            .read_line_timeout(Some(timeout))?
+            .ok_or(RequestError::Timeout)?;
+        if !line.starts_with(AUTH_SUCCESS) {
+            // Wrong password, or banned after too many tries.
+            return Err(RequestError::AuthFailed(line));
+        }
+
+        log::debug!("econ authenticated: {}", line);
+        Ok(econ)
+    }
+
+    /// Sets how often an empty line is sent while no command is,
+    /// so idle connections aren't dropped along the way. `None` disables it.
+    pub fn set_keepalive(&mut self, interval: Option<Duration>) {
+        self.keepalive = interval;
+    }
+
+    /// Sends a command to execute.
+    ///
+    /// Several commands can be given separated by `;`, like in the console.
+    pub fn send(&mut self, command: &str) -> Result<()> {
+        if command.contains(['\n', '\r']) {
+            return Err(RequestError::InvalidData("command contains a line break"));
+        }
+
+        let stream = self.reader.get_mut();
+        stream.write_all(command.as_bytes())?;
+        stream.write_all(b"\n")?;
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+
+    /// Reads a console line.
+    ///
+    /// Waits until the next keepalive is due, sending it and returning `None` if nothing
+    /// arrived by then. Without keepalive it waits for a line forever.
+    pub fn read_line(&mut self) -> Result<Option<String>> {
+        let now = Instant::now();
+        let wait = self.keepalive.map(|x| {
+            (self.last_sent + x)
+                .saturating_duration_since(now)
+                .max(Duration::from_millis(1))
+        });
+
+        let line = self.read_line_timeout(wait)?;
+
+        if line.is_none() && matches!(self.keepalive, Some(x) if self.last_sent.elapsed() >= x) {
+            log::debug!("econ sending keepalive");
+            self.send("")?;
+        }
+
+        Ok(line)
+    }
+
+    /// Iterates over the console lines, until the server closes the connection.
+    pub fn lines(&mut self) -> EconLines<'_> {
+        EconLines { econ: self }
+    }
+
+    fn read_line_timeout(&mut self, timeout: Option<Duration>) -> Result<Option<String>> {
+        self.reader.get_ref().set_read_timeout(timeout)?;
+
+        // Partial lines stay in `pending` across timeouts.
+        match self.reader.read_until(b'\n', &mut self.pending) {
+            Ok(_) if self.pending.is_empty() => Err(RequestError::Disconnected),
+            Ok(_) => {
+                let line = String::from_utf8_lossy(&self.pending)
+                    .trim_end_matches(['\n', '\r'])
+                    .to_owned();
+                self.pending.clear();
+                Ok(Some(line))
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+                Ok(None)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Iterator over the console lines of an [Econ] connection.
+///
+/// Keepalives are sent while waiting.
+#[derive(Debug)]
+pub struct EconLines<'a> {
+    econ: &'a mut Econ,
+}
+
+impl<'a> Iterator for EconLines<'a> {
+    type Item = Result<String>;
+
+    fn next(&mut self) -> Option<Result<String>> {
+        loop {
+            match self.econ.read_line() {
+                Ok(Some(line)) => return Some(Ok(line)),
+                Ok(None) => {}
+                Err(RequestError::Disconnected) => return None,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::net::TcpListener;
+
+    fn serve<F: FnOnce(BufReader<TcpStream>) + Send + 'static>(
+        handler: F,
+    ) -> (std::net::SocketAddr, std::thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let handle = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            handler(BufReader::new(stream));
+        });
+        (address, handle)
+    }
+
+    fn read(client: &mut BufReader<TcpStream>) -> String {
+        let mut line = String::new();
+        client.read_line(&mut line).unwrap();
+        line
+    }
+
+    #[test]
+    fn authenticates_and_streams() {
+        let (address, server) = serve(|mut client| {
+            client.get_mut().write_all(b"Enter password:\r\n").unwrap();
+            assert_eq!(read(&mut client), "secret\n");
+            client
+                .get_mut()
+                .write_all(b"Authentication successful. External console access granted.\r\n")
+                .unwrap();
+
+            assert_eq!(read(&mut client), "status\n");
+            client
+                .get_mut()
+                .write_all(b"[server]: id=0 addr=1.2.3.4:5678 name='nameless tee'\r\n[ser")
+                .unwrap();
+            std::thread::sleep(Duration::from_millis(100));
+            client.get_mut().write_all(b"ver]: done\r\n").unwrap();
+
+            // Keepalive.
+            assert_eq!(read(&mut client), "\n");
+        });
+
+        let mut econ = Econ::connect(address, "secret").unwrap();
+        econ.set_keepalive(Some(Duration::from_millis(200)));
+        assert!(econ.send("say\nshutdown").is_err());
+        econ.send("status").unwrap();
+
+        let lines: Vec<_> = econ.lines().collect::<Result<_>>().unwrap();
+        server.join().unwrap();
+
+        assert_eq!(
+            lines,
+            vec![
+                "[server]: id=0 addr=1.2.3.4:5678 name='nameless tee'",
+                "[server]: done"
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_wrong_password() {
+        let (address, server) = serve(|mut client| {
+            client.get_mut().write_all(b"Enter password:\r\n").unwrap();
+            read(&mut client);
+            client
+                .get_mut()
+                .write_all(b"Wrong password 1/3.\r\n")
+                .unwrap();
+        });
+
+        let result = Econ::connect(address, "guess");
+        server.join().unwrap();
+
+        match result {
+            Err(RequestError::AuthFailed(reason)) => assert_eq!(reason, "Wrong password 1/3."),
+            x => panic!("unexpected result: {:?}", x),
+        }
+    }
+}
@@ -15,6 +15,27 @@ pub enum RequestError {
     /// Missing data.
     #[error("missing data")]
     Missing,
+    /// No response arrived in time.
+    #[error("timed out")]
+    Timeout,
+    /// Data not in the expected format.
+    #[error("invalid data: {0}")]
+    InvalidData(&'static str),
+    /// The server refused the password, the message is the reason it gave.
+    #[error("authentication failed: {0}")]
+    AuthFailed(String),
+    /// The server closed the connection.
+    #[error("connection closed")]
+    Disconnected,
+    /// The server closed the connection while connecting, the message is the reason it gave.
+    #[error("connection refused: {0}")]
+    Refused(String),
+    /// The server has no free slot.
+    #[error("server is full")]
+    Full,
+    /// The server banned us, the message is the reason it gave.
+    #[error("banned: {0}")]
+    Banned(String),
     /// Token validation error.
     #[error("token received by server is invalid")]
     TokenError {
@@ -25,5 +46,78 @@ pub enum RequestError {
     },
 }
 
+impl RequestError {
+    /// Duplicates the error, to hand one failure to several callers.
+    ///
+    /// An io error keeps its OS error code when it has one, otherwise its kind and message.
+    pub(crate) fn duplicate(&self) -> RequestError {
+        match self {
+            RequestError::DecodeError(e) => RequestError::DecodeError(*e),
+            RequestError::ParseError(e) => RequestError::ParseError(e.clone()),
+            RequestError::IoError(e) => RequestError::IoError(match e.raw_os_error() {
+                Some(code) => std::io::Error::from_raw_os_error(code),
+                None => std::io::Error::new(e.kind(), e.to_string()),
+            }),
+            RequestError::Missing => RequestError::Missing,
+            RequestError::Timeout => RequestError::Timeout,
+            RequestError::InvalidData(x) => RequestError::InvalidData(x),
+            RequestError::AuthFailed(x) => RequestError::AuthFailed(x.clone()),
+            RequestError::Disconnected => RequestError::Disconnected,
+            RequestError::Refused(x) => RequestError::Refused(x.clone()),
+            RequestError::Full => RequestError::Full,
+            RequestError::Banned(x) => RequestError::Banned(x.clone()),
+            RequestError::TokenError {
+                wanted_extra_token,
+                wanted_token,
+                received_extra_token,
+                received_token,
+            } => RequestError::TokenError {
+                wanted_extra_token: *wanted_extra_token,
+                wanted_token: *wanted_token,
+                received_extra_token: *received_extra_token,
+                received_token: *received_token,
+            },
+        }
+    }
+}
+
 /// A type alias to handle Results with RequestError.
 pub type Result<T, V = RequestError> = std::result::Result<T, V>;
+
+/// Errors parsing browser filters and sort orders.
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum FilterError {
+    /// The key is not a known filter or sort key.
+    #[error("unknown key '{0}'")]
+    UnknownKey(String),
+    /// The value given to a key is invalid.
+    #[error("invalid value '{value}' for '{key}'")]
+    InvalidValue { key: String, value: String },
+    /// A quote was left open.
+    #[error("unterminated quote")]
+    UnterminatedQuote,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn duplicates_errors() {
+        let error = RequestError::IoError(std::io::Error::from_raw_os_error(111));
+        match error.duplicate() {
+            RequestError::IoError(e) => assert_eq!(e.raw_os_error(), Some(111)),
+            e => panic!("unexpected error {:?}", e),
+        }
+
+        let error = RequestError::IoError(std::io::ErrorKind::TimedOut.into());
+        match error.duplicate() {
+            RequestError::IoError(e) => assert_eq!(e.kind(), std::io::ErrorKind::TimedOut),
+            e => panic!("unexpected error {:?}", e),
+        }
+
+        let error = RequestError::Banned("spam".to_owned());
+        assert_eq!(error.duplicate().to_string(), error.to_string());
+    }
+}
@@ -0,0 +1,365 @@
+//! C API, built with the `ffi` feature.
+//!
+//! Every function returns a [TeestatusError], the results are written through an out
+//! pointer and owned by the caller, who frees them with the matching `_free` function.
+//! The header is `include/teestatus.h`, CI checks it against the one the build script
+//! generates.
+
+use std::ffi::{CStr, CString};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+use std::time::Duration;
+
+use crate::errors::*;
+use crate::net::*;
+use crate::query::*;
+
+/// The error codes, mirroring [RequestError].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TeestatusError {
+    Ok = 0,
+    DecodeError,
+    ParseError,
+    IoError,
+    Missing,
+    Timeout,
+    InvalidData,
+    AuthFailed,
+    Disconnected,
+    Refused,
+    Full,
+    Banned,
+    TokenError,
+    /// A null pointer or an address that doesn't resolve.
+    InvalidArgument,
+}
+
+impl TeestatusError {
+    const ALL: [TeestatusError; 14] = [
+        TeestatusError::Ok,
+        TeestatusError::DecodeError,
+        TeestatusError::ParseError,
+        TeestatusError::IoError,
+        TeestatusError::Missing,
+        TeestatusError::Timeout,
+        TeestatusError::InvalidData,
+        TeestatusError::AuthFailed,
+        TeestatusError::Disconnected,
+        TeestatusError::Refused,
+        TeestatusError::Full,
+        TeestatusError::Banned,
+        TeestatusError::TokenError,
+        TeestatusError::InvalidArgument,
+    ];
+
+    /// The error with this code, C can pass any int.
+    fn from_code(code: c_int) -> Option<TeestatusError> {
+        TeestatusError::ALL
+            .iter()
+            .copied()
+            .find(|x| *x as c_int == code)
+    }
+}
+
+impl From<&RequestError> for TeestatusError {
+    fn from(e: &RequestError) -> TeestatusError {
+        match e {
+            RequestError::DecodeError(_) => TeestatusError::DecodeError,
+            RequestError::ParseError(_) => TeestatusError::ParseError,
+            RequestError::IoError(_) => TeestatusError::IoError,
+            RequestError::Missing => TeestatusError::Missing,
+            RequestError::Timeout => TeestatusError::Timeout,
+            RequestError::InvalidData(_) => TeestatusError::InvalidData,
+            RequestError::AuthFailed(_) => TeestatusError::AuthFailed,
+            RequestError::Disconnected => TeestatusError::Disconnected,
+            RequestError::Refused(_) => TeestatusError::Refused,
+            RequestError::Full => TeestatusError::Full,
+            RequestError::Banned(_) => TeestatusError::Banned,
+            RequestError::TokenError { .. } => TeestatusError::TokenError,
+        }
+    }
+}
+
+#[repr(C)]
+pub struct TeestatusPlayer {
+    pub name: *mut c_char,
+    pub clan: *mut c_char,
+    pub country: i32,
+    pub score: i32,
+    pub is_spectator: bool,
+}
+
+#[repr(C)]
+pub struct TeestatusServerInfo {
+    pub version: *mut c_char,
+    pub name: *mut c_char,
+    pub map: *mut c_char,
+    pub game_type: *mut c_char,
+    pub password: bool,
+    pub flags: i32,
+    pub player_count: i32,
+    pub max_player_count: i32,
+    pub client_count: i32,
+    pub max_client_count: i32,
+    /// Whether the server sent the map checksum and size.
+    pub has_map_checksum: bool,
+    pub map_crc: i32,
+    pub map_size: i32,
+    pub ping_ms: u32,
+    pub players: *mut TeestatusPlayer,
+    pub num_players: usize,
+}
+
+/// The servers of a master list, as `ip:port` strings.
+#[repr(C)]
+pub struct TeestatusServerList {
+    pub addresses: *mut *mut c_char,
+    pub len: usize,
+}
+
+/// Copies a string for C, servers can't send interior nul bytes.
+fn c_string(s: &str) -> *mut c_char {
+    CString::new(s).unwrap_or_default().into_raw()
+}
+
+unsafe fn free_string(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Hands a vector to C as a pointer and length, freed with [free_vec].
+fn into_raw_vec<T>(items: Vec<T>) -> (*mut T, usize) {
+    let items = items.into_boxed_slice();
+    let len = items.len();
+    (Box::into_raw(items) as *mut T, len)
+}
+
+unsafe fn free_vec<T>(items: *mut T, len: usize) -> Vec<T> {
+    if items.is_null() {
+        return Vec::new();
+    }
+    Box::from_raw(ptr::slice_from_raw_parts_mut(items, len)).into_vec()
+}
+
+unsafe fn resolve(address: *const c_char) -> Option<SocketAddr> {
+    if address.is_null() {
+        return None;
+    }
+    let address = CStr::from_ptr(address).to_str().ok()?;
+    address.to_socket_addrs().ok()?.next()
+}
+
+/// Runs `f`, writing its result through `out` or returning its error.
+unsafe fn with_out<T, F>(out: *mut *mut T, f: F) -> TeestatusError
+where
+    F: FnOnce() -> Result<T, TeestatusError>,
+{
+    if out.is_null() {
+        return TeestatusError::InvalidArgument;
+    }
+    *out = ptr::null_mut();
+
+    match f() {
+        Ok(value) => {
+            *out = Box::into_raw(Box::new(value));
+            TeestatusError::Ok
+        }
+        Err(e) => e,
+    }
+}
+
+fn bind_for(address: &SocketAddr) -> Result<UdpSocket, TeestatusError> {
+    UdpSocket::bind(AddressFamily::of(address).unspecified()).map_err(|_| TeestatusError::IoError)
+}
+
+/// Queries the info of the server at `address`, an `ip:port` or `host:port` string,
+/// waiting up to `timeout_ms`.
+///
+/// # Safety
+/// `address` must be a nul terminated string and `out` a valid pointer. On success the
+/// info is written to `out` and must be freed with [teestatus_server_info_free].
+#[no_mangle]
+pub unsafe extern "C" fn teestatus_query_info(
+    address: *const c_char,
+    timeout_ms: u32,
+    out: *mut *mut TeestatusServerInfo,
+) -> TeestatusError {
+    let address = resolve(address);
+    with_out(out, || {
+        let address = address.ok_or(TeestatusError::InvalidArgument)?;
+        let sock = bind_for(&address)?;
+        let mut query = InfoQuery::new(address, Duration::from_millis(timeout_ms as u64));
+        let entry = drive(&sock, &mut query)
+            .and_then(|_| query.finish())
+            .map_err(|e| TeestatusError::from(&e))?;
+
+        let info = entry.info;
+        let players = info
+            .players
+            .iter()
+            .map(|x| TeestatusPlayer {
+                name: c_string(&x.name),
+                clan: c_string(&x.clan),
+                country: x.country,
+                score: x.score,
+                is_spectator: x.is_spectator,
+            })
+            .collect();
+        let (players, num_players) = into_raw_vec(players);
+
+        Ok(TeestatusServerInfo {
+            version: c_string(&info.version),
+            name: c_string(&info.name),
+            map: c_string(&info.map),
+            game_type: c_string(&info.game_type),
+            password: info.password,
+            flags: info.flags.bits(),
+            player_count: info.player_count,
+            max_player_count: info.max_player_count,
+            client_count: info.client_count,
+            max_client_count: info.max_client_count,
+            has_map_checksum: info.map_crc.is_some(),
+            map_crc: info.map_crc.unwrap_or(0),
+            map_size: info.map_size.unwrap_or(0),
+            ping_ms: entry.ping.as_millis().min(u32::MAX as u128) as u32,
+            players,
+            num_players,
+        })
+    })
+}
+
+/// Frees an info returned by [teestatus_query_info], null is ignored.
+///
+/// # Safety
+/// `info` must come from [teestatus_query_info] and not be freed already.
+#[no_mangle]
+pub unsafe extern "C" fn teestatus_server_info_free(info: *mut TeestatusServerInfo) {
+    if info.is_null() {
+        return;
+    }
+
+    let info = Box::from_raw(info);
+    for s in [info.version, info.name, info.map, info.game_type] {
+        free_string(s);
+    }
+    for player in free_vec(info.players, info.num_players) {
+        free_string(player.name);
+        free_string(player.clan);
+    }
+}
+
+/// Requests the server list of the master at `address`, an `ip:port` or `host:port`
+/// string, waiting up to `timeout_ms` after the last datagram.
+///
+/// # Safety
+/// `address` must be a nul terminated string and `out` a valid pointer. On success the
+/// list is written to `out` and must be freed with [teestatus_server_list_free].
+#[no_mangle]
+pub unsafe extern "C" fn teestatus_get_server_list(
+    address: *const c_char,
+    timeout_ms: u32,
+    out: *mut *mut TeestatusServerList,
+) -> TeestatusError {
+    let address = resolve(address);
+    with_out(out, || {
+        let address = address.ok_or(TeestatusError::InvalidArgument)?;
+        let sock = bind_for(&address)?;
+        let mut query = ListQuery::new(address, Duration::from_millis(timeout_ms as u64));
+        drive(&sock, &mut query).map_err(|e| TeestatusError::from(&e))?;
+
+        let mut servers: Vec<SocketAddr> = query.finish().into_iter().map(Into::into).collect();
+        servers.sort();
+        let addresses = servers.iter().map(|x| c_string(&x.to_string())).collect();
+        let (addresses, len) = into_raw_vec(addresses);
+
+        Ok(TeestatusServerList { addresses, len })
+    })
+}
+
+/// Frees a list returned by [teestatus_get_server_list], null is ignored.
+///
+/// # Safety
+/// `list` must come from [teestatus_get_server_list] and not be freed already.
+#[no_mangle]
+pub unsafe extern "C" fn teestatus_server_list_free(list: *mut TeestatusServerList) {
+    if list.is_null() {
+        return;
+    }
+
+    let list = Box::from_raw(list);
+    for address in free_vec(list.addresses, list.len) {
+        free_string(address);
+    }
+}
+
+/// A static description of the error, not to be freed.
+///
+/// Takes the code as an int, codes that aren't a [TeestatusError] are unknown errors.
+#[no_mangle]
+pub extern "C" fn teestatus_error_message(error: c_int) -> *const c_char {
+    let error = match TeestatusError::from_code(error) {
+        Some(error) => error,
+        None => return b"unknown error\0".as_ptr() as *const c_char,
+    };
+    let message: &'static [u8] = match error {
+        TeestatusError::Ok => b"no error\0",
+        TeestatusError::DecodeError => b"decode error\0",
+        TeestatusError::ParseError => b"parse error\0",
+        TeestatusError::IoError => b"io error\0",
+        TeestatusError::Missing => b"missing data\0",
+        TeestatusError::Timeout => b"timed out\0",
+        TeestatusError::InvalidData => b"invalid data\0",
+        TeestatusError::AuthFailed => b"authentication failed\0",
+        TeestatusError::Disconnected => b"connection closed\0",
+        TeestatusError::Refused => b"connection refused\0",
+        TeestatusError::Full => b"server is full\0",
+        TeestatusError::Banned => b"banned\0",
+        TeestatusError::TokenError => b"token received by server is invalid\0",
+        TeestatusError::InvalidArgument => b"invalid argument\0",
+    };
+    message.as_ptr() as *const c_char
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::testing::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn queries_info() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = CString::new(server.local_addr().unwrap().to_string()).unwrap();
+        std::thread::spawn(move || serve_samples(&server));
+
+        unsafe {
+            let mut info = ptr::null_mut();
+            let error = teestatus_query_info(address.as_ptr(), 2000, &mut info);
+            assert_eq!(error, TeestatusError::Ok);
+
+            let info = &*info;
+            assert_eq!(CStr::from_ptr(info.map).to_str().unwrap(), "Multeasymap");
+            assert_eq!(info.num_players, 63);
+            assert!(info.has_map_checksum);
+            teestatus_server_info_free(info as *const _ as *mut _);
+
+            let mut info = ptr::null_mut();
+            let error = teestatus_query_info(ptr::null(), 2000, &mut info);
+            assert_eq!(error, TeestatusError::InvalidArgument);
+            assert!(info.is_null());
+
+            let message = |code| {
+                CStr::from_ptr(teestatus_error_message(code))
+                    .to_str()
+                    .unwrap()
+            };
+            assert_eq!(message(error as c_int), "invalid argument");
+            assert_eq!(message(TeestatusError::Timeout as c_int), "timed out");
+            assert_eq!(message(42), "unknown error");
+            assert_eq!(message(-1), "unknown error");
+        }
+    }
+}
@@ -0,0 +1,526 @@
+//! Guessing what software a server runs.
+//!
+//! Servers give themselves away through the info requests they answer, the format of
+//! their version string, their game type and what they put in the fields nobody uses.
+//! Each of these only hints at the software, so the guess comes with a confidence.
+
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::errors::*;
+use crate::query::*;
+use crate::server::*;
+use crate::util::*;
+use crate::version::*;
+
+/// Forks and mods recognized by name, in their game type or version string.
+const KNOWN_MODS: &[(&str, &str, bool)] = &[
+    ("f-ddrace", "F-DDrace", true),
+    ("iddrace", "iDDRace", true),
+    ("infclass", "InfClass", false),
+    ("zcatch", "zCatch", false),
+    ("fng", "FNG", false),
+    ("openfng", "openFNG", false),
+    ("ictf", "iCTF", false),
+    ("gctf", "gCTF", false),
+    ("idm", "iDM", false),
+];
+
+/// The game types of vanilla servers.
+const VANILLA_GAME_TYPES: &[&str] = &["DM", "TDM", "CTF"];
+
+/// The game type of DDNet servers.
+const DDNET_GAME_TYPE: &str = "DDraceNetwork";
+
+/// The software a server runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Software {
+    Vanilla06,
+    Vanilla07,
+    /// DDNet, with its version if the server told it.
+    DDNet(Option<Version>),
+    /// A fork or mod, named after its game type or version string.
+    Mod {
+        name: String,
+        ddnet_based: bool,
+    },
+    Unknown,
+}
+
+/// The info requests a server answered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct InfoAnswers {
+    /// Answered the vanilla request with `inf3`.
+    pub vanilla: bool,
+    /// Answered the request with the DDNet extended token with `iext`.
+    pub extended: bool,
+    /// Answered the legacy 64 players request with `dtsf`.
+    pub legacy64: bool,
+}
+
+/// A best guess of the software a server runs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fingerprint {
+    pub software: Software,
+    /// How sure the guess is, from 0 to 1.
+    pub confidence: f32,
+    /// What the guess was based on.
+    pub hints: Vec<&'static str>,
+}
+
+/// Splits a game type or version string in lowercase words to look for mod names.
+fn words(s: &str) -> impl Iterator<Item = String> + '_ {
+    s.split(|c: char| !c.is_alphanumeric() && c != '-')
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_lowercase())
+}
+
+fn known_mod(s: &str) -> Option<(&'static str, bool)> {
+    words(s).find_map(|word| {
+        KNOWN_MODS
+            .iter()
+            .find(|(key, _, _)| *key == word)
+            .map(|&(_, name, ddnet_based)| (name, ddnet_based))
+    })
+}
+
+impl Fingerprint {
+    /// Guesses the software from the requests the server answered and its info, if any.
+    pub fn guess(answers: InfoAnswers, info: Option<&ServerInfo<'_>>) -> Fingerprint {
+        let mut hints = Vec::new();
+        let mut ddnet = 0.0;
+        let mut vanilla = 0.0;
+
+        if answers.extended {
+            hints.push("answers extended info requests");
+            ddnet += 0.35;
+        } else if answers.vanilla {
+            hints.push("answers only vanilla info requests");
+            vanilla += 0.35;
+        }
+        if answers.legacy64 {
+            hints.push("answers legacy 64 players info requests");
+            ddnet += 0.15;
+        }
+
+        let info = match info {
+            Some(info) => info,
+            None => {
+                let software = if ddnet > vanilla {
+                    Software::DDNet(None)
+                } else if vanilla > 0.0 {
+                    Software::Vanilla06
+                } else {
+                    Software::Unknown
+                };
+                let confidence = if ddnet > vanilla { ddnet } else { vanilla };
+                return Fingerprint {
+                    software,
+                    confidence,
+                    hints,
+                };
+            }
+        };
+
+        let version = info.parse_version();
+        let mut oddities = 0.0;
+
+        match &version {
+            Some(v) if v.is_07() => {
+                hints.push("reports a 0.7 version");
+                // 0.7 servers don't answer the 0.6 requests, someone is lying otherwise.
+                let confidence = if answers.vanilla || answers.extended {
+                    0.5
+                } else {
+                    0.9
+                };
+                let software = match known_mod(&info.game_type) {
+                    Some((name, _)) => Software::Mod {
+                        name: name.to_owned(),
+                        ddnet_based: false,
+                    },
+                    None => Software::Vanilla07,
+                };
+                return Fingerprint {
+                    software,
+                    confidence,
+                    hints,
+                };
+            }
+            Some(v) if v.is_ddnet() => {
+                hints.push("reports a DDNet version");
+                ddnet += 0.4;
+            }
+            Some(v) if v.suffix.is_none() => {
+                hints.push("reports a plain vanilla version");
+                vanilla += 0.3;
+            }
+            Some(_) => {
+                hints.push("reports a version with a suffix");
+                oddities += 0.3;
+            }
+            None => {
+                hints.push("reports an unknown version format");
+                oddities += 0.3;
+            }
+        }
+
+        if info.flags.contains(ServerFlags::TIMESCORE) {
+            hints.push("uses finish times as scores");
+            ddnet += 0.1;
+        }
+        if info.flags.bits() & !(ServerFlags::PASSWORD | ServerFlags::TIMESCORE).bits() != 0 {
+            hints.push("sets unknown server flags");
+            oddities += 0.2;
+        }
+        if info.players.iter().any(|x| !x.reserved.is_empty()) {
+            hints.push("fills the reserved player fields");
+            oddities += 0.2;
+        }
+        // Vanilla and DDNet count as players exactly the clients not flagged as
+        // spectators, mods with bots or extra teams often don't.
+        let in_game = info.players.iter().filter(|x| !x.is_spectator).count();
+        if info.players.len() == info.client_count as usize && in_game != info.player_count as usize
+        {
+            hints.push("flags spectators inconsistently with its player count");
+            oddities += 0.2;
+        }
+
+        let game_type: &str = &info.game_type;
+        if game_type == DDNET_GAME_TYPE {
+            hints.push("uses the DDNet game type");
+            ddnet += 0.1;
+        } else if VANILLA_GAME_TYPES.contains(&game_type) {
+            hints.push("uses a vanilla game type");
+            vanilla += 0.2;
+        } else {
+            hints.push("uses a custom game type");
+            oddities += 0.3;
+        }
+
+        let ddnet_based = ddnet > vanilla;
+        let suffix = version.as_ref().and_then(|x| x.suffix).unwrap_or("");
+
+        if let Some((name, based)) = known_mod(game_type).or_else(|| known_mod(suffix)) {
+            hints.push("names a known mod");
+            return Fingerprint {
+                software: Software::Mod {
+                    name: name.to_owned(),
+                    ddnet_based: based || ddnet_based,
+                },
+                confidence: 0.8,
+                hints,
+            };
+        }
+
+        if oddities >= 0.3 {
+            let name = if !suffix.is_empty() {
+                suffix
+            } else {
+                game_type
+            };
+            return Fingerprint {
+                software: Software::Mod {
+                    name: name.to_owned(),
+                    ddnet_based,
+                },
+                confidence: f32::min(oddities, 0.6),
+                hints,
+            };
+        }
+
+        let (software, winner, loser) = if ddnet_based {
+            let version = version.and_then(|x| x.ddnet_version);
+            (Software::DDNet(version), ddnet, vanilla)
+        } else if vanilla > 0.0 {
+            (Software::Vanilla06, vanilla, ddnet)
+        } else {
+            (Software::Unknown, 0.0, 0.0)
+        };
+
+        Fingerprint {
+            software,
+            confidence: f32::min(winner, 1.0) * (1.0 - f32::min(loser + oddities, 1.0) / 2.0),
+            hints,
+        }
+    }
+
+    /// Probes the server with every kind of info request and guesses its software.
+    ///
+    /// Waits for the socket read timeout, or [DEFAULT_TIMEOUT] if it has none, since not
+    /// every server answers every request.
+    pub fn probe(sock: &UdpSocket, address: SocketAddr) -> Result<Fingerprint> {
+        let timeout = sock.read_timeout()?.unwrap_or(DEFAULT_TIMEOUT);
+        let mut query = FingerprintQuery::new(address, timeout);
+        drive(sock, &mut query)?;
+        query.finish()
+    }
+}
+
+/// Builds a request with the vanilla connless header.
+fn vanilla_request(packet_type: PacketType, token: u8) -> Vec<u8> {
+    let mut request = vec![0xff; 10];
+    request.extend_from_slice(packet_type.value());
+    request.push(token);
+    request
+}
+
+/// Fingerprinting request.
+///
+/// Sends the vanilla, extended and legacy 64 players info requests. Completes once all
+/// were answered, otherwise at the timeout.
+#[derive(Debug)]
+pub struct FingerprintQuery {
+    address: SocketAddr,
+    timeout: Duration,
+    token: u8,
+    requests: Vec<Vec<u8>>,
+    deadline: Option<Instant>,
+    answers: InfoAnswers,
+    extended: Vec<Vec<u8>>,
+    vanilla: Option<Vec<u8>>,
+}
+
+impl FingerprintQuery {
+    pub fn new(address: SocketAddr, timeout: Duration) -> FingerprintQuery {
+        let (extended, _, token) = create_packet(PacketType::GetInfo, Some(b"xe"), true);
+        let token = token.expect("token should always have value here.");
+
+        FingerprintQuery {
+            address,
+            timeout,
+            token,
+            requests: vec![
+                vanilla_request(PacketType::GetInfo64Legacy, token),
+                vanilla_request(PacketType::GetInfo, token),
+                extended.to_vec(),
+            ],
+            deadline: None,
+            answers: InfoAnswers::default(),
+            extended: Vec::new(),
+            vanilla: None,
+        }
+    }
+
+    /// The requests answered so far.
+    pub fn answers(&self) -> InfoAnswers {
+        self.answers
+    }
+
+    /// Guesses the software from the answers.
+    ///
+    /// Fails if the server answered nothing.
+    pub fn finish(self) -> Result<Fingerprint> {
+        if self.answers == InfoAnswers::default() {
+            return Err(RequestError::Timeout);
+        }
+
+        let extended = self.extended.split_first().and_then(|(main, more)| {
+            let mut info = ServerInfo::parse_main(main).ok()?;
+            for more in more {
+                info.parse_more(more).ok()?;
+            }
+            Some(info)
+        });
+        let info = extended.or_else(|| ServerInfo::parse_main(self.vanilla.as_ref()?).ok());
+
+        Ok(Fingerprint::guess(self.answers, info.as_ref()))
+    }
+}
+
+impl Query for FingerprintQuery {
+    fn poll_transmit(&mut self, now: Instant) -> Option<Transmit> {
+        // Every request carries the token, the extended one only adds to it.
+        let contents = self.requests.pop()?;
+        if self.deadline.is_none() {
+            self.deadline = Some(now + self.timeout);
+        }
+
+        Some(Transmit {
+            destination: self.address,
+            contents,
+        })
+    }
+
+    fn handle_datagram(&mut self, from: SocketAddr, data: &[u8], _now: Instant) -> Result<()> {
+        if from != self.address {
+            return Err(RequestError::InvalidData("datagram from another address"));
+        }
+
+        if data.len() < 14 {
+            return Err(RequestError::InvalidData("datagram too short"));
+        }
+
+        let packet_type = &data[10..14];
+        let token = if PacketType::InfoExtendedMore == *packet_type {
+            parse_more_header(data)?.0
+        } else {
+            let end = data[14..].iter().position(|&x| x == 0).unwrap_or(0);
+            std::str::from_utf8(&data[14..14 + end])?.parse::<i32>()?
+        };
+
+        if token & 0xff != self.token as i32 {
+            return Err(RequestError::InvalidData("token mismatch"));
+        }
+
+        if PacketType::InfoExtended == *packet_type {
+            self.answers.extended = true;
+            self.extended.insert(0, data.to_vec());
+        } else if PacketType::InfoExtendedMore == *packet_type {
+            self.extended.push(data.to_vec());
+        } else if PacketType::Info == *packet_type {
+            self.answers.vanilla = true;
+            self.vanilla = Some(data.to_vec());
+        } else if PacketType::Info64Legacy == *packet_type {
+            self.answers.legacy64 = true;
+        } else {
+            return Err(RequestError::InvalidData("unexpected packet type"));
+        }
+
+        Ok(())
+    }
+
+    fn poll(&mut self, now: Instant) -> QueryStatus {
+        let answers = self.answers;
+        if answers.vanilla && answers.extended && answers.legacy64 {
+            QueryStatus::Complete
+        } else if matches!(self.deadline, Some(x) if now >= x) {
+            QueryStatus::TimedOut
+        } else {
+            QueryStatus::Pending
+        }
+    }
+
+    fn poll_timeout(&self) -> Option<Instant> {
+        self.deadline
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::testing::sample_info;
+    use crate::relay::*;
+    use pretty_assertions::assert_eq;
+    use std::borrow::Cow;
+
+    fn modified(version: &'static str, game_type: &'static str) -> ServerInfo<'static> {
+        let mut info = sample_info();
+        info.version = Cow::Borrowed(version);
+        info.game_type = Cow::Borrowed(game_type);
+        info.flags = ServerFlags::default();
+        info
+    }
+
+    const ALL: InfoAnswers = InfoAnswers {
+        vanilla: true,
+        extended: true,
+        legacy64: true,
+    };
+
+    #[test]
+    fn guesses_software() {
+        let ddnet = Fingerprint::guess(ALL, Some(&sample_info()));
+        assert_eq!(
+            ddnet.software,
+            Software::DDNet(Some(Version::new(15, 3, 1)))
+        );
+        assert!(ddnet.confidence > 0.8);
+
+        let vanilla_answers = InfoAnswers {
+            vanilla: true,
+            ..InfoAnswers::default()
+        };
+        let vanilla = Fingerprint::guess(vanilla_answers, Some(&modified("0.6.4", "CTF")));
+        assert_eq!(vanilla.software, Software::Vanilla06);
+        assert!(vanilla.confidence > 0.6);
+
+        let seven = Fingerprint::guess(InfoAnswers::default(), Some(&modified("0.7.5", "DM")));
+        assert_eq!(seven.software, Software::Vanilla07);
+
+        let zcatch = Fingerprint::guess(vanilla_answers, Some(&modified("0.6.4", "zCatch/LMS")));
+        assert_eq!(
+            zcatch.software,
+            Software::Mod {
+                name: "zCatch".to_owned(),
+                ddnet_based: false
+            }
+        );
+
+        let fork = Fingerprint::guess(ALL, Some(&modified("0.6.4 [iDDRace]", "DDraceNetwork")));
+        assert_eq!(
+            fork.software,
+            Software::Mod {
+                name: "iDDRace".to_owned(),
+                ddnet_based: true
+            }
+        );
+
+        let unknown = Fingerprint::guess(ALL, Some(&modified("0.6.4, 16.0", "Gores")));
+        assert_eq!(
+            unknown.software,
+            Software::Mod {
+                name: "Gores".to_owned(),
+                ddnet_based: true
+            }
+        );
+        assert!(unknown.confidence <= 0.6);
+
+        // Counting spectators as players.
+        let mut counted = modified("0.6.4, 16.0", "DDraceNetwork");
+        counted.player_count = counted.client_count;
+        counted.players[0].is_spectator = true;
+        let odd = Fingerprint::guess(ALL, Some(&counted));
+        assert!(odd
+            .hints
+            .contains(&"flags spectators inconsistently with its player count"));
+        assert!(!ddnet
+            .hints
+            .iter()
+            .any(|x| x.starts_with("flags spectators")));
+        assert!(odd.confidence < ddnet.confidence);
+
+        let blind = Fingerprint::guess(ALL, None);
+        assert_eq!(blind.software, Software::DDNet(None));
+        assert!(blind.confidence < ddnet.confidence);
+    }
+
+    #[test]
+    fn probes_servers() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut responder = InfoResponder::new();
+            responder.set_info(sample_info());
+
+            let mut buf = [0; MAX_DATAGRAM_SIZE];
+            while let Ok((len, from)) = server.recv_from(&mut buf) {
+                let request = &buf[..len];
+                let replies = match responder.respond(request, Instant::now()) {
+                    Some(replies) => replies,
+                    None => {
+                        assert_eq!(PacketType::GetInfo64Legacy, request[10..14]);
+                        let mut reply = vec![0xff; 10];
+                        reply.extend_from_slice(b"dtsf");
+                        reply.extend_from_slice(format!("{}\0", request[14]).as_bytes());
+                        vec![reply]
+                    }
+                };
+                for reply in replies {
+                    server.send_to(&reply, from).unwrap();
+                }
+            }
+        });
+
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut query = FingerprintQuery::new(address, Duration::from_secs(2));
+        assert_eq!(drive(&sock, &mut query).unwrap(), QueryStatus::Complete);
+        assert_eq!(query.answers(), ALL);
+
+        let fingerprint = query.finish().unwrap();
+        assert_eq!(
+            fingerprint.software,
+            Software::DDNet(Some(Version::new(15, 3, 1)))
+        );
+    }
+}
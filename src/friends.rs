@@ -0,0 +1,234 @@
+use crate::browser::*;
+use crate::config::*;
+use crate::server::*;
+use crate::util::*;
+
+/// An entry of the friends list.
+///
+/// An empty name makes every member of the clan a friend.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Friend {
+    pub name: String,
+    /// The clan the player must be in, `None` matches any clan.
+    pub clan: Option<String>,
+}
+
+impl Friend {
+    pub fn new(name: &str, clan: Option<&str>) -> Friend {
+        Friend {
+            name: name.to_owned(),
+            clan: clan.map(|x| x.to_owned()),
+        }
+    }
+
+    /// A friend entry matching every member of the clan.
+    pub fn clan(clan: &str) -> Friend {
+        Friend::new("", Some(clan))
+    }
+
+    /// Whether this entry matches a whole clan.
+    pub fn is_clan(&self) -> bool {
+        self.name.is_empty()
+    }
+}
+
+/// An online friend, along with the server it's playing on.
+#[derive(Debug)]
+pub struct OnlineFriend<'s, 'a> {
+    pub friend: &'s Friend,
+    pub server: &'s ServerEntry<'a>,
+    pub player: &'s Player<'a>,
+}
+
+/// A list of friends to look for in the server list.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FriendList {
+    pub friends: Vec<Friend>,
+    /// Compare names and clans ignoring case.
+    pub case_insensitive: bool,
+    /// Match named friends in any clan, like `cl_friends_ignore_clan` in DDNet.
+    ///
+    /// Clan entries still need the clan.
+    pub ignore_clan: bool,
+}
+
+impl FriendList {
+    pub fn new() -> FriendList {
+        FriendList::default()
+    }
+
+    /// Adds a friend, unless it's already in the list.
+    pub fn add(&mut self, friend: Friend) {
+        if !self.friends.contains(&friend) {
+            self.friends.push(friend);
+        }
+    }
+
+    /// Removes a friend, returning whether it was in the list.
+    pub fn remove(&mut self, friend: &Friend) -> bool {
+        let len = self.friends.len();
+        self.friends.retain(|x| x != friend);
+        len != self.friends.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.friends.is_empty()
+    }
+
+    fn eq(&self, a: &str, b: &str) -> bool {
+        if self.case_insensitive {
+            a.to_lowercase() == b.to_lowercase()
+        } else {
+            a == b
+        }
+    }
+
+    /// Returns the first entry matching the player.
+    pub fn find(&self, player: &Player) -> Option<&Friend> {
+        self.friends.iter().find(|friend| {
+            let clan_matches = match &friend.clan {
+                _ if self.ignore_clan && !friend.is_clan() => true,
+                Some(clan) => self.eq(clan, &player.clan),
+                None => true,
+            };
+
+            clan_matches && (friend.is_clan() || self.eq(&friend.name, &player.name))
+        })
+    }
+
+    /// Whether the player is a friend.
+    pub fn is_friend(&self, player: &Player) -> bool {
+        self.find(player).is_some()
+    }
+
+    /// Returns every friend playing on the given servers.
+    pub fn find_online<'s, 'a>(
+        &'s self,
+        servers: &'s [ServerEntry<'a>],
+    ) -> Vec<OnlineFriend<'s, 'a>> {
+        let mut online = Vec::new();
+
+        for server in servers {
+            for player in &server.info.players {
+                if let Some(friend) = self.find(player) {
+                    online.push(OnlineFriend {
+                        friend,
+                        server,
+                        player,
+                    });
+                }
+            }
+        }
+
+        online
+    }
+
+    /// Reads the `add_friend` lines of a client config, ignoring everything else.
+    ///
+    /// See also [ClientConfig::parse()]
+    pub fn from_config(config: &str) -> FriendList {
+        ClientConfig::parse(config).friends
+    }
+
+    /// Writes the list as `add_friend` config lines.
+    ///
+    /// The config has no way to match any clan, so friends without a clan are written with
+    /// an empty one.
+    pub fn to_config(&self) -> String {
+        let mut config = String::new();
+
+        for friend in &self.friends {
+            config.push_str(&format!(
+                "add_friend {} {}\n",
+                quote_arg(&friend.name),
+                quote_arg(friend.clan.as_deref().unwrap_or_default())
+            ));
+        }
+
+        config
+    }
+}
+
+/// Parses the arguments of an `add_friend "name" "clan"` command.
+///
+/// A missing clan is read as an empty one, which only matches players without a clan.
+pub(crate) fn parse_add_friend(args: &[String]) -> Option<Friend> {
+    match args {
+        [cmd, name, rest @ ..] if cmd == "add_friend" && rest.len() <= 1 => {
+            let clan = rest.first().map(|x| x.as_str()).unwrap_or_default();
+            if name.is_empty() && clan.is_empty() {
+                return None;
+            }
+            Some(Friend::new(name, Some(clan)))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn finds_friends() {
+        let data = include_bytes!("samples/server_info.data");
+        let servers = vec![ServerEntry {
+            address: "127.0.0.1:8303".parse().unwrap(),
+            ping: Duration::from_millis(10),
+            queried_at: SystemTime::now(),
+            info: ServerInfo::parse_main(data).unwrap(),
+        }];
+
+        let mut list = FriendList::new();
+        list.add(Friend::new("dino bleu", None));
+        list.add(Friend::clan("NYKS"));
+        list.add(Friend::new("GRIM", Some("other clan")));
+
+        let online = list.find_online(&servers);
+        assert_eq!(online.len(), 3);
+        assert!(online.iter().all(|x| x.player.clan == "NYKS"));
+        assert!(online.iter().all(|x| x.friend.is_clan()));
+        assert_eq!(online[0].player.name, "smurf hesap");
+
+        // An empty clan only matches players without one.
+        let mut no_clan = FriendList::new();
+        no_clan.add(Friend::new("smurf hesap", Some("")));
+        assert!(no_clan.find_online(&servers).is_empty());
+        no_clan.ignore_clan = true;
+        assert_eq!(no_clan.find_online(&servers).len(), 1);
+
+        list.case_insensitive = true;
+        let online = list.find_online(&servers);
+        let names: Vec<_> = online.iter().map(|x| &*x.player.name).take(2).collect();
+        assert_eq!(names, vec!["smurf hesap", "Dino Bleu"]);
+        assert_eq!(online.len(), 4);
+        assert_eq!(online[1].server.address, servers[0].address);
+    }
+
+    #[test]
+    fn reads_config() {
+        let config = r#"
+player_name "nameless tee"
+add_friend "Dino Bleu" "Helper"
+add_friend "" "NYKS"
+add_friend "quoted \"name\"" ""
+add_friend "no clan"
+add_friend ""
+"#;
+
+        let list = FriendList::from_config(config);
+        assert_eq!(
+            list.friends,
+            vec![
+                Friend::new("Dino Bleu", Some("Helper")),
+                Friend::clan("NYKS"),
+                Friend::new("quoted \"name\"", Some("")),
+                Friend::new("no clan", Some("")),
+            ]
+        );
+
+        assert_eq!(FriendList::from_config(&list.to_config()), list);
+    }
+}
@@ -0,0 +1,354 @@
+//! Where and when players were seen, built from repeated scans.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime};
+
+use crate::browser::*;
+use crate::scan::*;
+
+/// A player seen on a server without interruption, with the same map, clan and country.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sighting {
+    pub name: String,
+    pub clan: String,
+    pub country: i32,
+    pub address: SocketAddr,
+    pub map: String,
+    pub first_seen: SystemTime,
+    pub last_seen: SystemTime,
+}
+
+/// The time a player spent on a server, from its sightings merged across map changes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerSessions {
+    pub address: SocketAddr,
+    /// The first and last time seen of each session, oldest first.
+    pub intervals: Vec<(SystemTime, SystemTime)>,
+}
+
+/// An index of player sightings, fed with server infos.
+///
+/// A sighting ends when the server is queried without the player, or when the player
+/// wasn't seen for longer than [PlayerHistory::max_gap].
+#[derive(Debug, Clone)]
+pub struct PlayerHistory {
+    /// How long a player can go unseen and still be in the same sighting.
+    pub max_gap: Duration,
+    sightings: Vec<Sighting>,
+    /// The sightings still going on, by server and player.
+    open: HashMap<OpenKey, usize>,
+}
+
+/// A player on a server: the name, clan and country, as players can share names.
+type OpenKey = (SocketAddr, String, String, i32);
+
+/// The edit distance between two strings, counted in chars.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = diagonal + (ca != cb) as usize;
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+/// Whether the name looks like the one searched: containing it or a typo away from it,
+/// ignoring case.
+fn fuzzy_match(query: &str, name: &str) -> bool {
+    let query = query.to_lowercase();
+    let name = name.to_lowercase();
+    name.contains(&query) || edit_distance(&query, &name) <= (query.chars().count() / 4).max(1)
+}
+
+impl PlayerHistory {
+    /// Creates an empty history, ending sightings after ten minutes unseen.
+    pub fn new() -> PlayerHistory {
+        PlayerHistory {
+            max_gap: Duration::from_secs(10 * 60),
+            sightings: Vec::new(),
+            open: HashMap::new(),
+        }
+    }
+
+    /// Every sighting, in the order they started.
+    pub fn sightings(&self) -> &[Sighting] {
+        &self.sightings
+    }
+
+    pub fn len(&self) -> usize {
+        self.sightings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sightings.is_empty()
+    }
+
+    /// Records the players of a server, as seen when it was queried.
+    ///
+    /// Entries must be recorded in the order they were queried.
+    pub fn record(&mut self, entry: &ServerEntry<'_>) {
+        let at = entry.queried_at;
+        let max_gap = self.max_gap;
+        let info = &entry.info;
+        let players: HashSet<(&str, &str, i32)> = info
+            .players
+            .iter()
+            .map(|x| (&*x.name, &*x.clan, x.country))
+            .collect();
+
+        // Whoever is missing left.
+        self.open.retain(|(address, name, clan, country), _| {
+            *address != entry.address || players.contains(&(&**name, &**clan, *country))
+        });
+
+        for player in &info.players {
+            let key = (
+                entry.address,
+                player.name.to_string(),
+                player.clan.to_string(),
+                player.country,
+            );
+
+            if let Some(&index) = self.open.get(&key) {
+                let sighting = &mut self.sightings[index];
+                let recent = at
+                    .duration_since(sighting.last_seen)
+                    .map_or(true, |x| x <= max_gap);
+
+                if recent && sighting.map == info.map {
+                    sighting.last_seen = sighting.last_seen.max(at);
+                    continue;
+                }
+            }
+
+            self.open.insert(key, self.sightings.len());
+            self.sightings.push(Sighting {
+                name: player.name.to_string(),
+                clan: player.clan.to_string(),
+                country: player.country,
+                address: entry.address,
+                map: info.map.to_string(),
+                first_seen: at,
+                last_seen: at,
+            });
+        }
+    }
+
+    /// Records every server of a scan.
+    pub fn record_scan(&mut self, scan: &Scan) {
+        let mut servers: Vec<&ServerEntry<'_>> = scan.servers.iter().collect();
+        servers.sort_by_key(|x| x.queried_at);
+        for entry in servers {
+            self.record(entry);
+        }
+    }
+
+    fn find<F: Fn(&Sighting) -> bool>(&self, f: F) -> Vec<&Sighting> {
+        let mut found: Vec<&Sighting> = self.sightings.iter().filter(|x| f(x)).collect();
+        found.sort_by_key(|x| (x.first_seen, x.last_seen));
+        found
+    }
+
+    /// The sightings of the player with exactly this name, oldest first.
+    pub fn by_name(&self, name: &str) -> Vec<&Sighting> {
+        self.find(|x| x.name == name)
+    }
+
+    /// The sightings of players with a name close to this one, oldest first.
+    ///
+    /// Names containing the searched one, or a few typos away from it, match regardless
+    /// of case.
+    pub fn by_name_fuzzy(&self, name: &str) -> Vec<&Sighting> {
+        self.find(|x| fuzzy_match(name, &x.name))
+    }
+
+    /// The sightings of the members of the clan, oldest first.
+    pub fn by_clan(&self, clan: &str) -> Vec<&Sighting> {
+        self.find(|x| x.clan == clan)
+    }
+
+    /// The latest sighting of the player with exactly this name.
+    pub fn last_seen(&self, name: &str) -> Option<&Sighting> {
+        self.sightings
+            .iter()
+            .filter(|x| x.name == name)
+            .max_by_key(|x| x.last_seen)
+    }
+
+    /// The sessions of the player with exactly this name on each server, sorted by address.
+    ///
+    /// Sightings on a server less than [PlayerHistory::max_gap] apart, like before and
+    /// after a map change, make a single session.
+    pub fn sessions(&self, name: &str) -> Vec<ServerSessions> {
+        let mut servers: BTreeMap<SocketAddr, Vec<(SystemTime, SystemTime)>> = BTreeMap::new();
+        for sighting in self.by_name(name) {
+            let intervals = servers.entry(sighting.address).or_default();
+            match intervals.last_mut() {
+                Some((_, end))
+                    if sighting
+                        .first_seen
+                        .duration_since(*end)
+                        .map_or(true, |x| x <= self.max_gap) =>
+                {
+                    *end = (*end).max(sighting.last_seen);
+                }
+                _ => intervals.push((sighting.first_seen, sighting.last_seen)),
+            }
+        }
+
+        servers
+            .into_iter()
+            .map(|(address, intervals)| ServerSessions { address, intervals })
+            .collect()
+    }
+}
+
+impl Default for PlayerHistory {
+    fn default() -> PlayerHistory {
+        PlayerHistory::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::*;
+    use pretty_assertions::assert_eq;
+    use std::borrow::Cow;
+    use std::time::UNIX_EPOCH;
+
+    fn entry(
+        address: &str,
+        map: &'static str,
+        names: &[&str],
+        minute: u64,
+    ) -> ServerEntry<'static> {
+        let mut info = ServerInfo::parse_main(include_bytes!("samples/server_info.data"))
+            .unwrap()
+            .into_owned();
+        let template = info.players[0].clone();
+        info.map = Cow::Borrowed(map);
+        info.players = names
+            .iter()
+            .map(|name| Player {
+                name: Cow::Owned(name.to_string()),
+                clan: Cow::Borrowed(if name.starts_with("tee") { "Tees" } else { "" }),
+                ..template.clone()
+            })
+            .collect();
+
+        ServerEntry {
+            address: address.parse().unwrap(),
+            ping: Duration::from_millis(20),
+            queried_at: UNIX_EPOCH + Duration::from_secs(minute * 60),
+            info,
+        }
+    }
+
+    fn at(minute: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(minute * 60)
+    }
+
+    #[test]
+    fn tracks_sightings() {
+        let mut history = PlayerHistory::new();
+        history.record(&entry(
+            "127.0.0.1:8303",
+            "Kobra",
+            &["teefan", "nameless"],
+            0,
+        ));
+        history.record(&entry("127.0.0.1:8304", "Gold Mine", &["teebro"], 1));
+        history.record(&entry("127.0.0.1:8303", "Kobra", &["teefan"], 5));
+        // The map changed, a new sighting in the same session.
+        history.record(&entry("127.0.0.1:8303", "Tutorial", &["teefan"], 8));
+        // Left, then came back.
+        history.record(&entry("127.0.0.1:8303", "Tutorial", &[], 10));
+        history.record(&entry("127.0.0.1:8303", "Tutorial", &["teefan"], 40));
+        // Switched servers.
+        history.record(&entry(
+            "127.0.0.1:8304",
+            "Gold Mine",
+            &["teefan", "teebro"],
+            50,
+        ));
+
+        let teefan = history.by_name("teefan");
+        assert_eq!(teefan.len(), 4);
+        assert_eq!((teefan[0].first_seen, teefan[0].last_seen), (at(0), at(5)));
+        assert_eq!(teefan[1].map, "Tutorial");
+
+        let last = history.last_seen("teefan").unwrap();
+        assert_eq!(last.address, "127.0.0.1:8304".parse().unwrap());
+        assert_eq!(last.last_seen, at(50));
+        assert!(history.last_seen("nobody").is_none());
+
+        assert_eq!(
+            history.sessions("teefan"),
+            vec![
+                ServerSessions {
+                    address: "127.0.0.1:8303".parse().unwrap(),
+                    intervals: vec![(at(0), at(8)), (at(40), at(40))],
+                },
+                ServerSessions {
+                    address: "127.0.0.1:8304".parse().unwrap(),
+                    intervals: vec![(at(50), at(50))],
+                },
+            ]
+        );
+
+        // Unseen for too long, though the server was never queried without the player.
+        let teebro = history.by_name("teebro");
+        assert_eq!(teebro.len(), 2);
+
+        assert_eq!(history.by_clan("Tees").len(), 6);
+        assert_eq!(history.by_clan("").len(), 1);
+
+        let fuzzy = history.by_name_fuzzy("TeeFn");
+        assert!(fuzzy.iter().all(|x| x.name == "teefan"));
+        assert_eq!(fuzzy.len(), 4);
+        assert_eq!(history.by_name_fuzzy("tee").len(), 6);
+        assert!(history.by_name_fuzzy("unknown").is_empty());
+    }
+
+    #[test]
+    fn measures_edit_distance() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "tee"), 3);
+        assert_eq!(edit_distance("tee", "tee"), 0);
+    }
+
+    #[test]
+    fn tells_namesakes_apart() {
+        let namesakes = |minute| {
+            let mut entry = entry("127.0.0.1:8303", "Kobra", &["nameless", "nameless"], minute);
+            entry.info.players[1].clan = Cow::Borrowed("Tees");
+            entry
+        };
+
+        let mut history = PlayerHistory::new();
+        history.record(&namesakes(0));
+        history.record(&namesakes(5));
+        let sightings = history.by_name("nameless");
+        assert_eq!(sightings.len(), 2);
+        assert!(sightings.iter().all(|x| x.last_seen == at(5)));
+
+        // One of them left.
+        let mut entry = namesakes(8);
+        entry.info.players.pop();
+        history.record(&entry);
+        history.record(&namesakes(9));
+        assert_eq!(history.by_name("nameless").len(), 3);
+        assert_eq!(history.by_clan("").len(), 1);
+        assert_eq!(history.by_clan("Tees").len(), 2);
+    }
+}
@@ -0,0 +1,255 @@
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::ops::RangeInclusive;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::browser::*;
+use crate::errors::*;
+use crate::query::*;
+use crate::util::*;
+
+/// Where and how long to look for servers on the local network.
+///
+/// The defaults match the LAN tab of the client: ports 8303 to 8310 on the
+/// broadcast address, waiting a second for replies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LanDiscovery {
+    pub broadcast: IpAddr,
+    pub ports: RangeInclusive<u16>,
+    pub timeout: Duration,
+}
+
+impl Default for LanDiscovery {
+    fn default() -> LanDiscovery {
+        LanDiscovery {
+            broadcast: IpAddr::V4(Ipv4Addr::BROADCAST),
+            ports: 8303..=8310,
+            timeout: Duration::from_secs(1),
+        }
+    }
+}
+
+impl LanDiscovery {
+    /// Creates the query, to run it on a socket of your own.
+    pub fn query(&self) -> LanQuery {
+        LanQuery::new(self.broadcast, self.ports.clone(), self.timeout)
+    }
+
+    /// Broadcasts the info request and collects the replies until the timeout.
+    ///
+    /// Broadcasting is enabled on the socket, which must not be connected.
+    /// The servers are sorted by address.
+    pub fn discover(&self, sock: &UdpSocket) -> Result<Vec<ServerEntry<'static>>> {
+        sock.set_broadcast(true)?;
+
+        let mut query = self.query();
+        drive(sock, &mut query)?;
+
+        Ok(query.finish())
+    }
+}
+
+/// LAN discovery request.
+///
+/// A single info request is sent to every port, every server answering it
+/// gets its own [InfoQuery]. Since there's no telling how many servers there are,
+/// it only completes once the timeout passes.
+#[derive(Debug)]
+pub struct LanQuery {
+    broadcast: IpAddr,
+    ports: RangeInclusive<u16>,
+    timeout: Duration,
+    request: Vec<u8>,
+    token: u8,
+    extra_token: u16,
+    next_port: Option<u16>,
+    sent_at: Option<(Instant, SystemTime)>,
+    deadline: Option<Instant>,
+    servers: BTreeMap<SocketAddr, InfoQuery>,
+}
+
+impl LanQuery {
+    pub fn new(broadcast: IpAddr, ports: RangeInclusive<u16>, timeout: Duration) -> LanQuery {
+        let (buf, extra_token, token) = create_packet(PacketType::GetInfo, Some(b"xe"), true);
+        let token = token.expect("token should always have value here.");
+
+        log::debug!("generated extra_token={}, token={}", extra_token, token);
+
+        LanQuery {
+            broadcast,
+            next_port: Some(*ports.start()).filter(|_| !ports.is_empty()),
+            ports,
+            timeout,
+            request: buf.to_vec(),
+            token,
+            extra_token,
+            sent_at: None,
+            deadline: None,
+            servers: BTreeMap::new(),
+        }
+    }
+
+    /// The addresses of the servers that replied so far.
+    pub fn servers(&self) -> impl Iterator<Item = SocketAddr> + '_ {
+        self.servers.keys().copied()
+    }
+
+    /// Parses the replies into owned entries, sorted by address.
+    ///
+    /// Servers whose main packet never arrived are left out.
+    pub fn finish(self) -> Vec<ServerEntry<'static>> {
+        self.servers
+            .into_iter()
+            .filter_map(|(address, query)| match query.finish() {
+                Ok(entry) => Some(entry),
+                Err(e) => {
+                    log::debug!("dropping lan server {}: {}", address, e);
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+impl Query for LanQuery {
+    fn poll_transmit(&mut self, now: Instant) -> Option<Transmit> {
+        let port = self.next_port?;
+        self.next_port = port.checked_add(1).filter(|x| self.ports.contains(x));
+
+        if self.sent_at.is_none() {
+            self.sent_at = Some((now, SystemTime::now()));
+            self.deadline = Some(now + self.timeout);
+        }
+
+        Some(Transmit {
+            destination: (self.broadcast, port).into(),
+            contents: self.request.clone(),
+        })
+    }
+
+    fn handle_datagram(&mut self, from: SocketAddr, data: &[u8], now: Instant) -> Result<()> {
+        let (sent_at, queried_at) = self.sent_at.ok_or(RequestError::InvalidData(
+            "datagram before the request was sent",
+        ))?;
+        let deadline = self.deadline.unwrap_or(now);
+        let (token, extra_token) = (self.token, self.extra_token);
+
+        let query = self.servers.entry(from).or_insert_with(|| {
+            log::debug!("lan server found at {}", from);
+            InfoQuery::sent(from, deadline, token, extra_token, sent_at, queried_at)
+        });
+
+        let result = query.handle_datagram(from, data, now);
+        if query.received() == 0 {
+            self.servers.remove(&from);
+        }
+
+        result
+    }
+
+    fn poll(&mut self, now: Instant) -> QueryStatus {
+        match self.deadline {
+            Some(x) if now >= x => QueryStatus::Complete,
+            _ => QueryStatus::Pending,
+        }
+    }
+
+    fn poll_timeout(&self) -> Option<Instant> {
+        self.deadline
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::testing::*;
+    use pretty_assertions::assert_eq;
+
+    /// Binds two sockets on consecutive ports.
+    fn bind_pair() -> (UdpSocket, UdpSocket) {
+        for _ in 0..50 {
+            let first = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let port = first.local_addr().unwrap().port();
+            if let Some(next) = port.checked_add(1) {
+                if let Ok(second) = UdpSocket::bind(("127.0.0.1", next)) {
+                    return (first, second);
+                }
+            }
+        }
+        panic!("no consecutive free ports");
+    }
+
+    #[test]
+    fn discovers_servers() {
+        let (first, second) = bind_pair();
+        let ports = first.local_addr().unwrap().port()..=second.local_addr().unwrap().port();
+        let addresses = vec![first.local_addr().unwrap(), second.local_addr().unwrap()];
+
+        let responders: Vec<_> = vec![(first, true), (second, false)]
+            .into_iter()
+            .map(|(server, send_more)| {
+                std::thread::spawn(move || {
+                    let mut buf = [0; MAX_DATAGRAM_SIZE];
+                    let (len, from) = server.recv_from(&mut buf).unwrap();
+                    let request = &buf[..len];
+                    assert_eq!(PacketType::GetInfo, request[10..14]);
+
+                    let main = answer(include_bytes!("samples/server_info.data"), request);
+                    server.send_to(&main, from).unwrap();
+                    if send_more {
+                        let more = answer(include_bytes!("samples/server_info_more.data"), request);
+                        server.send_to(&more, from).unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let discovery = LanDiscovery {
+            broadcast: "127.0.0.1".parse().unwrap(),
+            ports,
+            timeout: Duration::from_millis(300),
+        };
+        let servers = discovery.discover(&sock).unwrap();
+        for responder in responders {
+            responder.join().unwrap();
+        }
+
+        assert_eq!(
+            servers.iter().map(|x| x.address).collect::<Vec<_>>(),
+            addresses
+        );
+        assert_eq!(servers[0].info.players.len(), 63);
+        assert!(servers[1].info.players.len() < 63);
+        assert!(servers.iter().all(|x| x.info.map == "Multeasymap"));
+    }
+
+    #[test]
+    fn sends_to_every_port() {
+        let now = Instant::now();
+        let mut query = LanQuery::new(
+            IpAddr::V4(Ipv4Addr::BROADCAST),
+            8303..=8310,
+            Duration::from_secs(1),
+        );
+
+        let sent: Vec<_> = std::iter::from_fn(|| query.poll_transmit(now)).collect();
+        assert_eq!(sent.len(), 8);
+        assert_eq!(sent[0].destination, "255.255.255.255:8303".parse().unwrap());
+        assert_eq!(sent[7].destination, "255.255.255.255:8310".parse().unwrap());
+        assert!(sent.iter().all(|x| x.contents == sent[0].contents));
+
+        // Replies with a wrong token don't count as servers.
+        let from = "192.168.1.2:8303".parse().unwrap();
+        assert!(query
+            .handle_datagram(from, include_bytes!("samples/server_info.data"), now)
+            .is_err());
+        assert_eq!(query.servers().count(), 0);
+
+        assert_eq!(query.poll(now), QueryStatus::Pending);
+        assert_eq!(
+            query.poll(now + Duration::from_secs(1)),
+            QueryStatus::Complete
+        );
+    }
+}
@@ -24,20 +24,87 @@
 //! use std::borrow::Cow;
 //!
 //! let master = MasterServer {
-//! 	hostname: Cow::Borrowed("49.12.97.180"),
-//! 	port: 8300,
+//!     hostname: Cow::Borrowed("49.12.97.180"),
+//!     port: 8300,
 //! };
 //! let sock = UdpSocket::bind("0.0.0.0:0").expect("can't bind socket");
 //! let servers = master.get_server_list(&sock).unwrap();
 //! ```
+//!
+//! A socket bound to `0.0.0.0:0` only reaches IPv4 servers, use a [DualStackSocket]
+//! to query servers of both families:
+//! ```rust,no_run
+//! use teestatus::*;
+//! use std::borrow::Cow;
+//!
+//! let socks = DualStackSocket::bind().expect("can't bind sockets");
+//! println!("usable families: {:?}", usable_families());
+//!
+//! let master = MasterServer {
+//!     hostname: Cow::Borrowed("49.12.97.180"),
+//!     port: 8300,
+//! };
+//! let servers = socks.get_server_list(&master).unwrap();
+//!
+//! let mut buffers = ServerInfo::create_buffers();
+//! if let Some(&(ip, port)) = servers.iter().next() {
+//!     let entry = socks.query((ip, port).into(), &mut buffers).unwrap();
+//!     println!("{}: {}", entry.address, entry.info.name);
+//! }
+//! ```
 
 pub mod errors;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod protocol;
 
+mod bot;
+mod browser;
+mod cache;
+mod config;
+mod console;
+mod datafile;
+mod decoder;
+mod demo;
+mod econ;
+mod fingerprint;
+mod friends;
+mod history;
+mod lan;
+mod map;
+mod query;
+mod rcon;
+mod relay;
 mod server;
+mod session;
 mod masterserver;
-mod common;
+mod net;
+mod scan;
+mod score;
 mod util;
+mod version;
 
-pub use common::*;
+pub use bot::*;
+pub use browser::*;
+pub use cache::*;
+pub use config::*;
+pub use console::*;
+pub use datafile::*;
+pub use decoder::*;
+pub use demo::*;
+pub use econ::*;
+pub use fingerprint::*;
+pub use friends::*;
+pub use history::*;
+pub use lan::*;
+pub use map::*;
+pub use query::*;
+pub use rcon::*;
+pub use relay::*;
 pub use server::*;
+pub use session::*;
 pub use masterserver::*;
+pub use net::*;
+pub use scan::*;
+pub use score::*;
+pub use version::*;
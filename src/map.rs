@@ -0,0 +1,577 @@
+use std::net::{SocketAddr, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::datafile::*;
+use crate::errors::*;
+use crate::protocol::*;
+use crate::query::*;
+use crate::server::*;
+use crate::session::*;
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// The CRC32 used by teeworlds to identify maps, the same as zlib's.
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        CRC32_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// The checksum and size of a map, which servers advertise along with its name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MapChecksum {
+    pub crc: u32,
+    pub size: u32,
+}
+
+impl MapChecksum {
+    /// Computes the checksum of a map file contents.
+    pub fn of(data: &[u8]) -> MapChecksum {
+        MapChecksum {
+            crc: crc32(data),
+            size: data.len() as u32,
+        }
+    }
+
+    /// Reads a map file and computes its checksum.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<MapChecksum> {
+        Ok(MapChecksum::of(&std::fs::read(path)?))
+    }
+
+    /// Whether this is the map the server is running.
+    ///
+    /// Only servers answering with `iext` advertise the checksum, without it this
+    /// always returns false.
+    pub fn matches(&self, info: &ServerInfo) -> bool {
+        info.map_crc.map(|x| x as u32) == Some(self.crc)
+            && info.map_size.map(|x| x as u32) == Some(self.size)
+    }
+}
+
+/// Whether a map name can be used as a file name.
+fn is_valid_map_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && !name
+            .chars()
+            .any(|x| matches!(x, '/' | '\\' | ':' | '\0') || x.is_control())
+}
+
+/// The download of a map over a game connection, one chunk after the other.
+#[derive(Debug)]
+pub struct MapDownload {
+    name: String,
+    checksum: MapChecksum,
+    data: Vec<u8>,
+    chunk: i32,
+    done: bool,
+}
+
+impl MapDownload {
+    /// Starts downloading the map announced in a `MapChange` message.
+    pub fn new(name: &str, crc: u32, size: u32) -> MapDownload {
+        MapDownload {
+            name: name.to_owned(),
+            checksum: MapChecksum { crc, size },
+            data: Vec::new(),
+            chunk: 0,
+            done: false,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn checksum(&self) -> MapChecksum {
+        self.checksum
+    }
+
+    /// How many bytes were received so far.
+    pub fn received(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Whether the last chunk arrived.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// The request for the next chunk.
+    pub fn request(&self) -> Packer {
+        let mut request = pack_system_message(SystemMessage::RequestMapData);
+        request.add_int(self.chunk);
+        request
+    }
+
+    /// Handles the fields of a `MapData` message.
+    ///
+    /// Chunks of another map or not the one requested are ignored.
+    pub fn handle_data(&mut self, unpacker: &mut Unpacker<'_>) -> Result<()> {
+        let last = unpacker.get_int()? != 0;
+        let crc = unpacker.get_int()? as u32;
+        let chunk = unpacker.get_int()?;
+        let size = unpacker.get_int()?;
+        if size < 0 {
+            return Err(RequestError::InvalidData("negative map chunk size"));
+        }
+        let data = unpacker.get_raw(size as usize)?;
+
+        if self.done || crc != self.checksum.crc || chunk != self.chunk {
+            log::debug!("ignoring map chunk {} with crc {:08x}", chunk, crc);
+            return Ok(());
+        }
+
+        if self.data.len() + data.len() > self.checksum.size as usize {
+            return Err(RequestError::InvalidData("map bigger than announced"));
+        }
+
+        self.data.extend_from_slice(data);
+        self.chunk += 1;
+        self.done = last;
+        Ok(())
+    }
+
+    /// Returns the map, failing if it isn't complete or doesn't match the checksum.
+    pub fn finish(self) -> Result<Vec<u8>> {
+        if !self.done {
+            return Err(RequestError::Missing);
+        }
+
+        if MapChecksum::of(&self.data) != self.checksum {
+            return Err(RequestError::InvalidData("map crc mismatch"));
+        }
+
+        Ok(self.data)
+    }
+}
+
+/// A directory holding downloaded maps, as `<name>_<crc>.map` like the client does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MapCache {
+    pub dir: PathBuf,
+}
+
+impl MapCache {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> MapCache {
+        MapCache { dir: dir.into() }
+    }
+
+    /// Where the map with this name and crc is stored.
+    ///
+    /// Fails if the name could escape the directory.
+    pub fn path(&self, name: &str, crc: u32) -> Result<PathBuf> {
+        if !is_valid_map_name(name) {
+            return Err(RequestError::InvalidData("invalid map name"));
+        }
+
+        Ok(self.dir.join(format!("{}_{:08x}.map", name, crc)))
+    }
+
+    /// The path of the map if it's cached and intact.
+    pub fn get(&self, name: &str, checksum: MapChecksum) -> Option<PathBuf> {
+        let path = self.path(name, checksum.crc).ok()?;
+        match MapChecksum::from_file(&path) {
+            Ok(x) if x == checksum => Some(path),
+            Ok(_) => {
+                log::warn!("ignoring corrupted cached map {}", path.display());
+                None
+            }
+            Err(_) => None,
+        }
+    }
+
+    /// Stores a map, after checking it matches its crc.
+    pub fn store(&self, name: &str, crc: u32, data: &[u8]) -> Result<PathBuf> {
+        if crc32(data) != crc {
+            return Err(RequestError::InvalidData("map crc mismatch"));
+        }
+
+        let path = self.path(name, crc)?;
+        std::fs::create_dir_all(&self.dir)?;
+
+        // Write it whole first so a failed write never leaves a truncated map.
+        let partial = path.with_extension("map.tmp");
+        std::fs::write(&partial, data)?;
+        std::fs::rename(&partial, &path)?;
+        Ok(path)
+    }
+
+    /// Connects to the server and downloads the map it's running, unless it's cached
+    /// already. Returns the path of the map in the cache.
+    ///
+    /// Waits for the socket read timeout, or [DEFAULT_TIMEOUT] if it has none, between
+    /// each message.
+    pub fn download(
+        &self,
+        sock: &UdpSocket,
+        address: SocketAddr,
+        password: Option<&str>,
+    ) -> Result<PathBuf> {
+        let timeout = sock.read_timeout()?.unwrap_or(DEFAULT_TIMEOUT);
+        let mut session = ClientSession::connect(sock, address)?;
+
+        let mut info = pack_system_message(SystemMessage::Info);
+        info.add_string(GAME_VERSION)
+            .add_string(password.unwrap_or(""));
+        session.send(info.as_bytes(), true)?;
+
+        let mut download: Option<MapDownload> = None;
+        let mut deadline = Instant::now() + timeout;
+
+        let path = loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RequestError::Timeout);
+            }
+            session.pump(sock, deadline - now)?;
+
+            let mut finished = None;
+            while let Some(event) = session.poll_event() {
+                let data = match event {
+                    SessionEvent::Chunk { data, .. } => data,
+                    SessionEvent::Closed { .. } => return Err(RequestError::Disconnected),
+                    SessionEvent::Connected => continue,
+                };
+
+                let (id, system, mut unpacker) = unpack_message(&data)?;
+                if !system {
+                    continue;
+                }
+
+                match SystemMessage::from_id(id) {
+                    Some(SystemMessage::MapChange) => {
+                        let name = unpacker.get_string()?;
+                        let crc = unpacker.get_int()? as u32;
+                        let size = unpacker.get_int()? as u32;
+                        log::debug!("server map is {} {:08x} ({} bytes)", name, crc, size);
+
+                        if let Some(path) = self.get(name, MapChecksum { crc, size }) {
+                            finished = Some(path);
+                            break;
+                        }
+
+                        let map = MapDownload::new(name, crc, size);
+                        session.send(map.request().as_bytes(), true)?;
+                        download = Some(map);
+                    }
+                    Some(SystemMessage::MapData) => {
+                        let map = match download.as_mut() {
+                            Some(map) => map,
+                            None => continue,
+                        };
+                        map.handle_data(&mut unpacker)?;
+                        deadline = Instant::now() + timeout;
+
+                        if !map.is_done() {
+                            session.send(map.request().as_bytes(), true)?;
+                            continue;
+                        }
+
+                        let map = download.take().expect("download in progress");
+                        let name = map.name().to_owned();
+                        let crc = map.checksum().crc;
+                        finished = Some(self.store(&name, crc, &map.finish()?)?);
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+
+            if let Some(path) = finished {
+                break path;
+            }
+        };
+
+        session.close(None);
+        session.pump(sock, std::time::Duration::from_secs(0))?;
+        Ok(path)
+    }
+}
+
+/// The item types of a map datafile.
+pub mod map_item {
+    pub const VERSION: u16 = 0;
+    pub const INFO: u16 = 1;
+    pub const IMAGE: u16 = 2;
+    pub const ENVELOPE: u16 = 3;
+    pub const GROUP: u16 = 4;
+    pub const LAYER: u16 = 5;
+    pub const ENVELOPE_POINTS: u16 = 6;
+    pub const SOUND: u16 = 7;
+}
+
+/// What a map says about itself, from its info item.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MapInfo {
+    pub author: Option<String>,
+    pub version: Option<String>,
+    pub credits: Option<String>,
+    pub license: Option<String>,
+}
+
+/// The metadata of a map file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MapDetails {
+    /// The version of the map format, from the version item.
+    pub version: Option<i32>,
+    /// Only maps saved by recent editors have it.
+    pub info: Option<MapInfo>,
+    pub images: usize,
+    pub envelopes: usize,
+    pub groups: usize,
+    pub layers: usize,
+    pub sounds: usize,
+}
+
+impl MapDetails {
+    /// Reads the metadata of a parsed map.
+    pub fn from_datafile(datafile: &Datafile) -> Result<MapDetails> {
+        let version = datafile
+            .find_item(map_item::VERSION, 0)
+            .and_then(|x| x.data.first().copied());
+
+        let info = match datafile.find_item(map_item::INFO, 0) {
+            Some(item) => {
+                // The item version, then the indexes of the strings.
+                let string = |i: usize| match item.data.get(i) {
+                    Some(&index) => datafile.string(index),
+                    None => Ok(None),
+                };
+                Some(MapInfo {
+                    author: string(1)?,
+                    version: string(2)?,
+                    credits: string(3)?,
+                    license: string(4)?,
+                })
+            }
+            None => None,
+        };
+
+        let count = |type_id| datafile.items_of_type(type_id).count();
+        Ok(MapDetails {
+            version,
+            info,
+            images: count(map_item::IMAGE),
+            envelopes: count(map_item::ENVELOPE),
+            groups: count(map_item::GROUP),
+            layers: count(map_item::LAYER),
+            sounds: count(map_item::SOUND),
+        })
+    }
+
+    /// Reads the metadata of a map file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<MapDetails> {
+        MapDetails::from_datafile(&Datafile::load(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::testing::*;
+    use pretty_assertions::assert_eq;
+    use std::time::Duration;
+
+    fn temp_cache(name: &str) -> MapCache {
+        let dir = std::env::temp_dir().join(format!("teestatus-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        MapCache::new(dir)
+    }
+
+    fn sample_map() -> Vec<u8> {
+        (0..3000u32).map(|x| (x * 7 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn computes_crc() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(
+            crc32(b"The quick brown fox jumps over the lazy dog"),
+            0x414f_a339
+        );
+
+        let checksum = MapChecksum::of(b"123456789");
+        assert_eq!(checksum.size, 9);
+
+        let info = ServerInfo::parse_main(include_bytes!("samples/server_info.data")).unwrap();
+        let advertised = MapChecksum {
+            crc: info.map_crc.unwrap() as u32,
+            size: info.map_size.unwrap() as u32,
+        };
+        assert!(advertised.matches(&info));
+        assert!(!checksum.matches(&info));
+    }
+
+    #[test]
+    fn caches_maps() {
+        let cache = temp_cache("cache");
+        let map = sample_map();
+        let checksum = MapChecksum::of(&map);
+
+        assert_eq!(cache.get("dm1", checksum), None);
+        assert!(cache.store("dm1", checksum.crc ^ 1, &map).is_err());
+        assert!(cache.store("../dm1", checksum.crc, &map).is_err());
+
+        let path = cache.store("dm1", checksum.crc, &map).unwrap();
+        assert_eq!(
+            path.file_name().unwrap().to_str().unwrap(),
+            format!("dm1_{:08x}.map", checksum.crc)
+        );
+        assert_eq!(cache.get("dm1", checksum), Some(path.clone()));
+
+        std::fs::write(&path, b"corrupted").unwrap();
+        assert_eq!(cache.get("dm1", checksum), None);
+
+        std::fs::remove_dir_all(&cache.dir).unwrap();
+    }
+
+    #[test]
+    fn downloads_maps() {
+        let cache = temp_cache("download");
+        let map = sample_map();
+        let checksum = MapChecksum::of(&map);
+
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap();
+
+        let served = map.clone();
+        let stand_in = std::thread::spawn(move || {
+            let mut server = StandIn::accept(server);
+            server.expect_system(SystemMessage::Info);
+
+            let mut map_change = pack_system_message(SystemMessage::MapChange);
+            map_change
+                .add_string("dm1")
+                .add_int(checksum.crc as i32)
+                .add_int(checksum.size as i32);
+            server.send(&map_change);
+
+            let chunks: Vec<&[u8]> = served.chunks(900).collect();
+            for (i, chunk) in chunks.iter().enumerate() {
+                let request = server.expect_system(SystemMessage::RequestMapData);
+                assert_eq!(Unpacker::new(&request).get_int().unwrap(), i as i32);
+
+                let mut data = pack_system_message(SystemMessage::MapData);
+                data.add_int((i == chunks.len() - 1) as i32)
+                    .add_int(checksum.crc as i32)
+                    .add_int(i as i32)
+                    .add_int(chunk.len() as i32)
+                    .add_raw(chunk);
+                server.send(&data);
+            }
+        });
+
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sock.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let path = cache.download(&sock, address, None).unwrap();
+        stand_in.join().unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), map);
+        assert_eq!(cache.get("dm1", checksum), Some(path));
+        std::fs::remove_dir_all(&cache.dir).unwrap();
+    }
+
+    #[test]
+    fn reads_details() {
+        let item = |type_id, id, data: Vec<i32>| DatafileItem { type_id, id, data };
+        let file = build_datafile(
+            &[
+                item(map_item::VERSION, 0, vec![1]),
+                item(map_item::INFO, 0, vec![1, 0, -1, 1, 2]),
+                item(map_item::IMAGE, 0, vec![1, 64, 64, 1, 3, -1]),
+                item(map_item::GROUP, 0, vec![3, 0, 0, 100, 100, 0, 2]),
+                item(map_item::LAYER, 0, vec![0, 2, 0]),
+                item(map_item::LAYER, 1, vec![0, 2, 0]),
+            ],
+            &[
+                b"Saavik\0",
+                b"by the team\0",
+                b"CC-BY-SA 3.0\0",
+                b"grass_main\0",
+            ],
+        );
+
+        let details = MapDetails::from_datafile(&Datafile::parse(&file).unwrap()).unwrap();
+        assert_eq!(
+            details,
+            MapDetails {
+                version: Some(1),
+                info: Some(MapInfo {
+                    author: Some("Saavik".to_owned()),
+                    version: None,
+                    credits: Some("by the team".to_owned()),
+                    license: Some("CC-BY-SA 3.0".to_owned()),
+                }),
+                images: 1,
+                envelopes: 0,
+                groups: 1,
+                layers: 2,
+                sounds: 0,
+            }
+        );
+
+        let details =
+            MapDetails::from_datafile(&Datafile::parse(&build_datafile(&[], &[])).unwrap());
+        assert_eq!(details.unwrap(), MapDetails::default());
+    }
+
+    #[test]
+    fn rejects_bad_chunks() {
+        let map = sample_map();
+        let checksum = MapChecksum::of(&map);
+        let mut download = MapDownload::new("dm1", checksum.crc, checksum.size);
+
+        let chunk = |last: bool, crc: u32, chunk: i32, data: &[u8]| {
+            let mut packer = Packer::new();
+            packer
+                .add_int(last as i32)
+                .add_int(crc as i32)
+                .add_int(chunk)
+                .add_int(data.len() as i32)
+                .add_raw(data);
+            packer
+        };
+
+        // Another map and a chunk not requested.
+        let other = chunk(false, 1, 0, &map[..10]);
+        download
+            .handle_data(&mut Unpacker::new(other.as_bytes()))
+            .unwrap();
+        let later = chunk(false, checksum.crc, 1, &map[..10]);
+        download
+            .handle_data(&mut Unpacker::new(later.as_bytes()))
+            .unwrap();
+        assert_eq!(download.received(), 0);
+
+        // A truncated map.
+        let last = chunk(true, checksum.crc, 0, &map[..10]);
+        download
+            .handle_data(&mut Unpacker::new(last.as_bytes()))
+            .unwrap();
+        assert!(download.is_done());
+        assert!(download.finish().is_err());
+    }
+}
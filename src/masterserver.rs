@@ -1,13 +1,9 @@
-use byteorder::{BigEndian, ReadBytesExt};
 use std::collections::HashSet;
-use std::net::{Ipv6Addr, UdpSocket};
-use std::{
-    borrow::Cow,
-    net::{IpAddr, Ipv4Addr},
-};
+use std::borrow::Cow;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs, UdpSocket};
 
-use crate::util::*;
 use crate::errors::*;
+use crate::query::*;
 
 pub struct MasterServer<'a> {
     pub hostname: Cow<'a, str>,
@@ -19,104 +15,27 @@ pub struct MasterServer<'a> {
 impl<'a> MasterServer<'a> {
     // Returns a vector filled with a pair of ip + port.
     pub fn get_server_list(&self, sock: &UdpSocket) -> Result<HashSet<(IpAddr, u16)>> {
-        sock.connect(format!("{}:{}", self.hostname, self.port))
-            .unwrap();
-
-        sock.set_nonblocking(true)?;
-
-        let (buf, _, _) = create_packet(PacketType::GetCount, Some(b"\xff\xff"), false);
-        let sent = sock.send(&buf)?;
-        log::debug!("sent GetCount = {}", sent);
-
-        let (buf, _, _) = create_packet(PacketType::GetList, Some(b"\xff\xff"), false);
-        let sent = sock.send(&buf)?;
-        log::debug!("sent GetList = {}", sent);
-
-        let (buf, _, _) = create_packet(PacketType::GetInfo, Some(b"xe"), true);
-        let sent = sock.send(&buf)?;
-        log::debug!("sent GetInfo = {}", sent);
-
-        sock.set_nonblocking(false)?;
-
-        let mut count = None;
-        let mut servers = HashSet::new();
-
-        'outer: loop {
-            let mut recvbuf = [0; 1400];
-            let res = sock.recv(&mut recvbuf);
-
-            match res {
-                Err(_) => break,
-                Ok(res) => {
-                    log::debug!("received data size: {}", res);
-                    if res > 0 {
-                        let packet_id = &recvbuf[10..14];
-
-                        log::debug!("Received packet with id: {:?}", packet_id);
-
-                        if PacketType::Count == *packet_id {
-                            log::debug!("Processing Count packet.");
-
-                            let mut val = &recvbuf[14..=15];
-                            count = Some(val.read_u16::<BigEndian>()?);
-
-                            log::debug!("master server count: {:?}", count);
-                        } else if PacketType::List == *packet_id {
-                            log::debug!("Processing List packet.");
-                            let mut ip;
-
-                            for i in (14..(recvbuf.len() - 14)).step_by(18) {
-                                if &recvbuf[i..i + 12]
-                                    == b"\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\xff\xff"
-                                {
-                                    let mut raw = &recvbuf[i + 12..i + 16];
-                                    ip = IpAddr::V4(Ipv4Addr::new(
-                                        raw.read_u8()?,
-                                        raw.read_u8()?,
-                                        raw.read_u8()?,
-                                        raw.read_u8()?,
-                                    ));
-                                } else {
-                                    let mut raw = &recvbuf[i..i + 16];
-                                    ip = IpAddr::V6(Ipv6Addr::new(
-                                        raw.read_u16::<BigEndian>()?,
-                                        raw.read_u16::<BigEndian>()?,
-                                        raw.read_u16::<BigEndian>()?,
-                                        raw.read_u16::<BigEndian>()?,
-                                        raw.read_u16::<BigEndian>()?,
-                                        raw.read_u16::<BigEndian>()?,
-                                        raw.read_u16::<BigEndian>()?,
-                                        raw.read_u16::<BigEndian>()?,
-                                    ));
-                                }
-
-                                let port = (&recvbuf[i + 16..i + 18]).read_u16::<BigEndian>()?;
-
-                                if port == 0 || ip.is_unspecified() {
-                                    continue;
-                                }
-                                log::debug!("Adding ip '{}' and port {}", ip, port);
-                                servers.insert((ip, port));
-
-                                if let Some(count) = count {
-                                    if servers.len() >= count as usize {
-                                        log::debug!("Added all servers.");
-                                        break 'outer;
-                                    }
-                                }
-                            }
-                        }
-                    } else {
-                        break;
-                    }
-                }
-            }
-        }
+        sock.connect((&*self.hostname, self.port))?;
+        request_server_list(sock)
+    }
 
-        Ok(servers)
+    /// Resolves the master server hostname.
+    pub fn resolve(&self) -> Result<Vec<SocketAddr>> {
+        Ok((&*self.hostname, self.port).to_socket_addrs()?.collect())
     }
 }
 
+/// Requests the server list on a socket already connected to a master server.
+///
+/// Waits for the socket read timeout, or [DEFAULT_TIMEOUT] if it has none,
+/// after the last datagram received.
+pub(crate) fn request_server_list(sock: &UdpSocket) -> Result<HashSet<(IpAddr, u16)>> {
+    let timeout = sock.read_timeout()?.unwrap_or(DEFAULT_TIMEOUT);
+    let mut query = ListQuery::new(sock.peer_addr()?, timeout);
+    drive(sock, &mut query)?;
+    Ok(query.finish())
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -0,0 +1,264 @@
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::Duration;
+
+use crate::browser::*;
+use crate::errors::*;
+use crate::masterserver::*;
+
+/// An IP address family.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AddressFamily {
+    V4,
+    V6,
+}
+
+impl AddressFamily {
+    /// The family of the given address.
+    ///
+    /// IPv4-mapped IPv6 addresses count as IPv4.
+    pub fn of(address: &SocketAddr) -> AddressFamily {
+        match address.ip() {
+            IpAddr::V4(_) => AddressFamily::V4,
+            IpAddr::V6(ip) if ip.to_ipv4_mapped().is_some() => AddressFamily::V4,
+            IpAddr::V6(_) => AddressFamily::V6,
+        }
+    }
+
+    pub(crate) fn unspecified(self) -> SocketAddr {
+        match self {
+            AddressFamily::V4 => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            AddressFamily::V6 => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        }
+    }
+
+    /// A public address used to check if the host has a route for this family.
+    ///
+    /// Connecting a UDP socket sends nothing, it only picks a route.
+    fn probe(self) -> SocketAddr {
+        match self {
+            AddressFamily::V4 => (Ipv4Addr::new(198, 51, 100, 1), 8303).into(),
+            AddressFamily::V6 => (Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1), 8303).into(),
+        }
+    }
+}
+
+/// Returns the address families this host can reach the internet with.
+pub fn usable_families() -> Vec<AddressFamily> {
+    [AddressFamily::V4, AddressFamily::V6]
+        .iter()
+        .copied()
+        .filter(|&family| {
+            UdpSocket::bind(family.unspecified())
+                .and_then(|sock| sock.connect(family.probe()))
+                .is_ok()
+        })
+        .collect()
+}
+
+/// A pair of UDP sockets, one for each address family, so queries can reach
+/// both IPv4 and IPv6 servers.
+///
+/// Each query is sent through the socket matching the address of the server.
+#[derive(Debug)]
+pub struct DualStackSocket {
+    v4: Option<UdpSocket>,
+    v6: Option<UdpSocket>,
+}
+
+impl DualStackSocket {
+    /// Binds a socket for every family available on this host.
+    ///
+    /// Fails only if no family can be bound.
+    pub fn bind() -> Result<DualStackSocket> {
+        let v4 = UdpSocket::bind(AddressFamily::V4.unspecified());
+        let v6 = UdpSocket::bind(AddressFamily::V6.unspecified());
+
+        match (v4, v6) {
+            (Err(e), Err(_)) => Err(e.into()),
+            (v4, v6) => {
+                if let Err(e) = &v6 {
+                    log::debug!("ipv6 is not available: {}", e);
+                }
+                if let Err(e) = &v4 {
+                    log::debug!("ipv4 is not available: {}", e);
+                }
+                Ok(DualStackSocket {
+                    v4: v4.ok(),
+                    v6: v6.ok(),
+                })
+            }
+        }
+    }
+
+    /// Creates a dual stack socket from already bound sockets.
+    pub fn from_sockets(v4: Option<UdpSocket>, v6: Option<UdpSocket>) -> DualStackSocket {
+        DualStackSocket { v4, v6 }
+    }
+
+    /// The families this socket can send to.
+    pub fn families(&self) -> Vec<AddressFamily> {
+        let mut families = Vec::new();
+        if self.v4.is_some() {
+            families.push(AddressFamily::V4);
+        }
+        if self.v6.is_some() {
+            families.push(AddressFamily::V6);
+        }
+        families
+    }
+
+    /// Returns the socket of the given family, if bound.
+    pub fn get(&self, family: AddressFamily) -> Option<&UdpSocket> {
+        match family {
+            AddressFamily::V4 => self.v4.as_ref(),
+            AddressFamily::V6 => self.v6.as_ref(),
+        }
+    }
+
+    /// Returns the socket able to reach the given address.
+    pub fn for_address(&self, address: &SocketAddr) -> Result<&UdpSocket> {
+        let family = AddressFamily::of(address);
+        self.get(family).ok_or_else(|| {
+            RequestError::IoError(std::io::Error::new(
+                std::io::ErrorKind::AddrNotAvailable,
+                format!("no {:?} socket to reach {}", family, address),
+            ))
+        })
+    }
+
+    fn sockets(&self) -> impl Iterator<Item = &UdpSocket> {
+        self.v4.iter().chain(self.v6.iter())
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        for sock in self.sockets() {
+            sock.set_read_timeout(timeout)?;
+        }
+        Ok(())
+    }
+
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        for sock in self.sockets() {
+            sock.set_write_timeout(timeout)?;
+        }
+        Ok(())
+    }
+
+    /// Requests the info of the server, through the socket of its family.
+    ///
+    /// See also [ServerEntry::query()]
+    pub fn query<'a>(
+        &self,
+        address: SocketAddr,
+        buffers: &'a mut [Vec<u8>],
+    ) -> Result<ServerEntry<'a>> {
+        ServerEntry::query(self.for_address(&address)?, unmap(address), buffers)
+    }
+
+    /// Requests the server list of a master server, using the first resolved
+    /// address of a family we can reach.
+    ///
+    /// See also [MasterServer::get_server_list()]
+    pub fn get_server_list(&self, master: &MasterServer) -> Result<HashSet<(IpAddr, u16)>> {
+        let address = master
+            .resolve()?
+            .into_iter()
+            .find(|x| self.get(AddressFamily::of(x)).is_some())
+            .ok_or(RequestError::Missing)?;
+
+        let sock = self.for_address(&address)?;
+        sock.connect(unmap(address))?;
+        request_server_list(sock)
+    }
+}
+
+/// Turns IPv4-mapped addresses into plain IPv4 ones, so they fit the IPv4 socket.
+fn unmap(address: SocketAddr) -> SocketAddr {
+    match address.ip() {
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => (ip, address.port()).into(),
+            None => address,
+        },
+        IpAddr::V4(_) => address,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::borrow::Cow;
+
+    #[test]
+    fn routes_by_family() {
+        let socks = DualStackSocket::bind().unwrap();
+        assert!(!socks.families().is_empty());
+
+        for family in socks.families() {
+            let address = match family {
+                AddressFamily::V4 => "127.0.0.1:8303".parse().unwrap(),
+                AddressFamily::V6 => "[::1]:8303".parse().unwrap(),
+            };
+            let sock = socks.for_address(&address).unwrap();
+            assert_eq!(
+                AddressFamily::of(&sock.local_addr().unwrap()),
+                AddressFamily::of(&address)
+            );
+        }
+
+        let mapped: SocketAddr = "[::ffff:1.2.3.4]:8303".parse().unwrap();
+        assert_eq!(AddressFamily::of(&mapped), AddressFamily::V4);
+        assert_eq!(unmap(mapped), "1.2.3.4:8303".parse().unwrap());
+
+        let v4_only =
+            DualStackSocket::from_sockets(Some(UdpSocket::bind("127.0.0.1:0").unwrap()), None);
+        assert!(v4_only
+            .for_address(&"[2001:db8::1]:8303".parse().unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn fetches_list_over_ipv6() {
+        let master = match UdpSocket::bind("[::1]:0") {
+            Ok(sock) => sock,
+            // No IPv6 on this host.
+            Err(_) => return,
+        };
+        let port = master.local_addr().unwrap().port();
+
+        let responder = std::thread::spawn(move || {
+            let mut buf = [0; 1400];
+            // Count, list and info requests.
+            let (_, from) = master.recv_from(&mut buf).unwrap();
+
+            let mut count = b"\xff\xff\xff\xff\xff\xff\xff\xff\xff\xffsiz2".to_vec();
+            count.extend_from_slice(&[0, 2]);
+            master.send_to(&count, from).unwrap();
+
+            let mut list = b"\xff\xff\xff\xff\xff\xff\xff\xff\xff\xfflis2".to_vec();
+            list.extend_from_slice(b"\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\xff\xff");
+            list.extend_from_slice(&[10, 0, 0, 1, 0x20, 0x6f]);
+            list.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+            list.extend_from_slice(&[0x20, 0x6f]);
+            list.resize(1400, 0);
+            master.send_to(&list, from).unwrap();
+        });
+
+        let socks = DualStackSocket::bind().unwrap();
+        socks
+            .set_read_timeout(Some(Duration::from_millis(1000)))
+            .unwrap();
+
+        let servers = socks
+            .get_server_list(&MasterServer {
+                hostname: Cow::Borrowed("::1"),
+                port,
+            })
+            .unwrap();
+        responder.join().unwrap();
+
+        assert!(servers.contains(&("10.0.0.1".parse().unwrap(), 8303)));
+        assert!(servers.contains(&("2001:db8::1".parse().unwrap(), 8303)));
+    }
+}
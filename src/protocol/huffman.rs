@@ -0,0 +1,231 @@
+use std::cmp::Reverse;
+use std::sync::OnceLock;
+
+use crate::errors::*;
+
+const EOF_SYMBOL: usize = 256;
+const MAX_SYMBOLS: usize = EOF_SYMBOL + 1;
+const MAX_NODES: usize = MAX_SYMBOLS * 2 - 1;
+const NO_LEAF: u16 = 0xffff;
+
+/// The symbol frequencies used by teeworlds to build its tree.
+///
+/// The last one, for the end of data symbol, is ignored and taken as 1.
+#[rustfmt::skip]
+pub const FREQUENCY_TABLE: [u32; MAX_SYMBOLS] = [
+    1 << 30, 4545, 2657, 431, 1950, 919, 444, 482, 2244, 617, 838, 542, 715, 1814, 304, 240, 754, 212, 647, 186,
+    283, 131, 146, 166, 543, 164, 167, 136, 179, 859, 363, 113, 157, 154, 204, 108, 137, 180, 202, 176,
+    872, 404, 168, 134, 151, 111, 113, 109, 120, 126, 129, 100, 41, 20, 16, 22, 18, 18, 17, 19,
+    16, 37, 13, 21, 362, 166, 99, 78, 95, 88, 81, 70, 83, 284, 91, 187, 77, 68, 52, 68,
+    59, 66, 61, 638, 71, 157, 50, 46, 69, 43, 11, 24, 13, 19, 10, 12, 12, 20, 14, 9,
+    20, 20, 10, 10, 15, 15, 12, 12, 7, 19, 15, 14, 13, 18, 35, 19, 17, 14, 8, 5,
+    15, 17, 9, 15, 14, 18, 8, 10, 2173, 134, 157, 68, 188, 60, 170, 60, 194, 62, 175, 71,
+    148, 67, 167, 78, 211, 67, 156, 69, 1674, 90, 174, 53, 147, 89, 181, 51, 174, 63, 163, 80,
+    167, 94, 128, 122, 223, 153, 218, 77, 200, 110, 190, 73, 174, 69, 145, 66, 277, 143, 141, 60,
+    136, 53, 180, 57, 142, 57, 158, 61, 166, 112, 152, 92, 26, 22, 21, 28, 20, 26, 30, 21,
+    32, 27, 20, 17, 23, 21, 30, 22, 22, 21, 27, 25, 17, 27, 23, 18, 39, 26, 15, 21,
+    12, 18, 18, 27, 20, 18, 15, 19, 11, 17, 33, 12, 18, 15, 19, 18, 16, 26, 17, 18,
+    9, 10, 25, 22, 22, 17, 20, 16, 6, 16, 15, 20, 14, 18, 24, 335, 1517,
+];
+
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    bits: u32,
+    num_bits: u32,
+    leafs: [u16; 2],
+}
+
+/// The huffman coder used to compress the game packets.
+///
+/// Codes are written least significant bit first, and every compressed buffer ends with
+/// the end of data symbol followed by a byte holding the remaining bits.
+#[derive(Debug, Clone)]
+pub struct Huffman {
+    nodes: Vec<Node>,
+}
+
+impl Huffman {
+    /// Builds the tree from the frequency of each byte.
+    ///
+    /// Ties are resolved like the reference implementation, so the codes match it.
+    pub fn new(frequencies: &[u32; MAX_SYMBOLS]) -> Huffman {
+        let mut nodes = vec![
+            Node {
+                bits: 0,
+                num_bits: u32::MAX,
+                leafs: [NO_LEAF; 2],
+            };
+            MAX_SYMBOLS
+        ];
+
+        // (frequency, node id)
+        let mut left: Vec<(u32, u16)> = (0..MAX_SYMBOLS)
+            .map(|i| {
+                let frequency = if i == EOF_SYMBOL { 1 } else { frequencies[i] };
+                (frequency, i as u16)
+            })
+            .collect();
+
+        while left.len() > 1 {
+            // A stable sort, like the bubble sort of the reference implementation.
+            left.sort_by_key(|x| Reverse(x.0));
+
+            let (last_frequency, last_id) = left.pop().expect("at least two nodes left");
+            let (frequency, id) = left.last_mut().expect("at least two nodes left");
+
+            nodes.push(Node {
+                bits: 0,
+                num_bits: 0,
+                leafs: [last_id, *id],
+            });
+            *id = (nodes.len() - 1) as u16;
+            *frequency += last_frequency;
+        }
+
+        let mut huffman = Huffman { nodes };
+        huffman.set_bits(huffman.start(), 0, 0);
+        huffman
+    }
+
+    /// The coder used by teeworlds and DDNet.
+    pub fn teeworlds() -> &'static Huffman {
+        static HUFFMAN: OnceLock<Huffman> = OnceLock::new();
+        HUFFMAN.get_or_init(|| Huffman::new(&FREQUENCY_TABLE))
+    }
+
+    fn start(&self) -> usize {
+        MAX_NODES - 1
+    }
+
+    fn set_bits(&mut self, node: usize, bits: u32, depth: u32) {
+        let [zero, one] = self.nodes[node].leafs;
+        if one != NO_LEAF {
+            self.set_bits(one as usize, bits | (1 << depth), depth + 1);
+        }
+        if zero != NO_LEAF {
+            self.set_bits(zero as usize, bits, depth + 1);
+        }
+
+        let node = &mut self.nodes[node];
+        if node.num_bits != 0 {
+            node.bits = bits;
+            node.num_bits = depth;
+        }
+    }
+
+    /// Compresses the data.
+    pub fn compress(&self, data: &[u8]) -> Vec<u8> {
+        let mut output = Vec::with_capacity(data.len() + 2);
+        let mut bits: u64 = 0;
+        let mut bit_count = 0;
+
+        let symbols = data.iter().map(|&x| x as usize).chain(Some(EOF_SYMBOL));
+        for symbol in symbols {
+            let node = &self.nodes[symbol];
+            bits |= (node.bits as u64) << bit_count;
+            bit_count += node.num_bits;
+
+            while bit_count >= 8 {
+                output.push(bits as u8);
+                bits >>= 8;
+                bit_count -= 8;
+            }
+        }
+
+        // The reference implementation always writes this byte, even if empty.
+        output.push(bits as u8);
+        output
+    }
+
+    /// Decompresses the data, up to the end of data symbol.
+    ///
+    /// Fails if the data ends before it.
+    pub fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut output = Vec::with_capacity(data.len() * 2);
+        let mut bits = data
+            .iter()
+            .flat_map(|&byte| (0..8).map(move |i| (byte >> i) & 1));
+
+        loop {
+            let mut node = self.start();
+            while self.nodes[node].num_bits == 0 {
+                let bit = bits
+                    .next()
+                    .ok_or(RequestError::InvalidData("huffman data ends without eof"))?;
+                node = self.nodes[node].leafs[bit as usize] as usize;
+            }
+
+            if node == EOF_SYMBOL {
+                return Ok(output);
+            }
+            output.push(node as u8);
+        }
+    }
+}
+
+/// Compresses the data with the teeworlds huffman coder.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    Huffman::teeworlds().compress(data)
+}
+
+/// Decompresses data compressed with the teeworlds huffman coder.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+    Huffman::teeworlds().decompress(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn builds_a_complete_tree() {
+        let huffman = Huffman::teeworlds();
+        assert_eq!(huffman.nodes.len(), MAX_NODES);
+
+        // Every symbol has a code and together they fill the code space.
+        let kraft: f64 = huffman.nodes[..MAX_SYMBOLS]
+            .iter()
+            .map(|x| 0.5f64.powi(x.num_bits as i32))
+            .sum();
+        assert!((kraft - 1.0).abs() < 1e-9);
+
+        // The zero byte is by far the most common.
+        assert_eq!(huffman.nodes[0].num_bits, 1);
+    }
+
+    #[test]
+    fn compresses() {
+        let vectors: &[(&[u8], &[u8])] = &[
+            (b"", &[0x8a, 0x1b]),
+            (&[0], &[0x15, 0x37, 0x00]),
+            (&[0; 8], &[0xff, 0x8a, 0x1b]),
+            (
+                b"teeworlds",
+                &[
+                    0x50, 0xc2, 0x09, 0x9c, 0xa0, 0xb8, 0xb5, 0x45, 0x70, 0x72, 0x25, 0x38, 0x91,
+                    0x4e, 0x15, 0x37, 0x00,
+                ],
+            ),
+        ];
+
+        for (data, compressed) in vectors {
+            assert_eq!(compress(data), *compressed);
+            assert_eq!(decompress(compressed).unwrap(), *data);
+        }
+    }
+
+    #[test]
+    fn roundtrips() {
+        let data: Vec<u8> = (0..=255).chain((0..=255).rev()).collect();
+        assert_eq!(decompress(&compress(&data)).unwrap(), data);
+
+        // Game messages are mostly small ints.
+        let sample: Vec<u8> = (0..200).flat_map(|x| vec![0, 0, x % 8, 0]).collect();
+        let compressed = compress(&sample);
+        assert!(compressed.len() < sample.len() / 2);
+        assert_eq!(decompress(&compressed).unwrap(), sample);
+
+        assert!(decompress(&compressed[..compressed.len() / 2]).is_err());
+    }
+}
@@ -0,0 +1,215 @@
+use crate::errors::*;
+use crate::protocol::packer::*;
+
+/// The version string sent by 0.6 clients.
+pub const GAME_VERSION: &str = "0.6 626fce9a778df4d4";
+
+/// System messages of the 0.6 protocol, handled by the engine rather than the game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SystemMessage {
+    Info,
+    MapChange,
+    MapData,
+    ConReady,
+    Snap,
+    SnapEmpty,
+    SnapSingle,
+    SnapSmall,
+    InputTiming,
+    RconAuthStatus,
+    RconLine,
+    AuthChallenge,
+    AuthResult,
+    Ready,
+    EnterGame,
+    Input,
+    RconCmd,
+    RconAuth,
+    RequestMapData,
+    AuthStart,
+    AuthResponse,
+    Ping,
+    PingReply,
+    Error,
+    RconCmdAdd,
+    RconCmdRem,
+}
+
+impl SystemMessage {
+    const ALL: [SystemMessage; 26] = [
+        SystemMessage::Info,
+        SystemMessage::MapChange,
+        SystemMessage::MapData,
+        SystemMessage::ConReady,
+        SystemMessage::Snap,
+        SystemMessage::SnapEmpty,
+        SystemMessage::SnapSingle,
+        SystemMessage::SnapSmall,
+        SystemMessage::InputTiming,
+        SystemMessage::RconAuthStatus,
+        SystemMessage::RconLine,
+        SystemMessage::AuthChallenge,
+        SystemMessage::AuthResult,
+        SystemMessage::Ready,
+        SystemMessage::EnterGame,
+        SystemMessage::Input,
+        SystemMessage::RconCmd,
+        SystemMessage::RconAuth,
+        SystemMessage::RequestMapData,
+        SystemMessage::AuthStart,
+        SystemMessage::AuthResponse,
+        SystemMessage::Ping,
+        SystemMessage::PingReply,
+        SystemMessage::Error,
+        SystemMessage::RconCmdAdd,
+        SystemMessage::RconCmdRem,
+    ];
+
+    pub fn id(self) -> i32 {
+        Self::ALL
+            .iter()
+            .position(|&x| x == self)
+            .expect("every message is listed") as i32
+            + 1
+    }
+
+    pub fn from_id(id: i32) -> Option<SystemMessage> {
+        Self::ALL.get((id as usize).checked_sub(1)?).copied()
+    }
+}
+
+/// Game messages of the 0.6 protocol, `Sv` ones are sent by the server and `Cl` ones
+/// by the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GameMessage {
+    SvMotd,
+    SvBroadcast,
+    SvChat,
+    SvKillMsg,
+    SvSoundGlobal,
+    SvTuneParams,
+    SvExtraProjectile,
+    SvReadyToEnter,
+    SvWeaponPickup,
+    SvEmoticon,
+    SvVoteClearOptions,
+    SvVoteOptionListAdd,
+    SvVoteOptionAdd,
+    SvVoteOptionRemove,
+    SvVoteSet,
+    SvVoteStatus,
+    ClSay,
+    ClSetTeam,
+    ClSetSpectatorMode,
+    ClStartInfo,
+    ClChangeInfo,
+    ClKill,
+    ClEmoticon,
+    ClVote,
+    ClCallVote,
+}
+
+impl GameMessage {
+    const ALL: [GameMessage; 25] = [
+        GameMessage::SvMotd,
+        GameMessage::SvBroadcast,
+        GameMessage::SvChat,
+        GameMessage::SvKillMsg,
+        GameMessage::SvSoundGlobal,
+        GameMessage::SvTuneParams,
+        GameMessage::SvExtraProjectile,
+        GameMessage::SvReadyToEnter,
+        GameMessage::SvWeaponPickup,
+        GameMessage::SvEmoticon,
+        GameMessage::SvVoteClearOptions,
+        GameMessage::SvVoteOptionListAdd,
+        GameMessage::SvVoteOptionAdd,
+        GameMessage::SvVoteOptionRemove,
+        GameMessage::SvVoteSet,
+        GameMessage::SvVoteStatus,
+        GameMessage::ClSay,
+        GameMessage::ClSetTeam,
+        GameMessage::ClSetSpectatorMode,
+        GameMessage::ClStartInfo,
+        GameMessage::ClChangeInfo,
+        GameMessage::ClKill,
+        GameMessage::ClEmoticon,
+        GameMessage::ClVote,
+        GameMessage::ClCallVote,
+    ];
+
+    pub fn id(self) -> i32 {
+        Self::ALL
+            .iter()
+            .position(|&x| x == self)
+            .expect("every message is listed") as i32
+            + 1
+    }
+
+    pub fn from_id(id: i32) -> Option<GameMessage> {
+        Self::ALL.get((id as usize).checked_sub(1)?).copied()
+    }
+}
+
+/// Starts a message, packing its id and whether it's a system message.
+pub fn pack_message(id: i32, system: bool) -> Packer {
+    let mut packer = Packer::new();
+    packer.add_int((id << 1) | system as i32);
+    packer
+}
+
+/// Starts a system message.
+pub fn pack_system_message(message: SystemMessage) -> Packer {
+    pack_message(message.id(), true)
+}
+
+/// Starts a game message.
+pub fn pack_game_message(message: GameMessage) -> Packer {
+    pack_message(message.id(), false)
+}
+
+/// Reads the id of a message and whether it's a system message, returning the
+/// unpacker positioned at its fields.
+pub fn unpack_message(data: &[u8]) -> Result<(i32, bool, Unpacker<'_>)> {
+    let mut unpacker = Unpacker::new(data);
+    let header = unpacker.get_int()?;
+    Ok((header >> 1, header & 1 != 0, unpacker))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn packs_message_ids() {
+        assert_eq!(SystemMessage::Info.id(), 1);
+        assert_eq!(SystemMessage::Ready.id(), 14);
+        assert_eq!(SystemMessage::RconAuth.id(), 18);
+        assert_eq!(SystemMessage::RconCmdRem.id(), 26);
+        assert_eq!(SystemMessage::from_id(11), Some(SystemMessage::RconLine));
+        assert_eq!(SystemMessage::from_id(0), None);
+        assert_eq!(SystemMessage::from_id(27), None);
+
+        let mut packer = pack_system_message(SystemMessage::RconCmd);
+        packer.add_string("status");
+        assert_eq!(packer.as_bytes(), b"\x23status\0");
+
+        let (id, system, mut unpacker) = unpack_message(packer.as_bytes()).unwrap();
+        assert_eq!((id, system), (17, true));
+        assert_eq!(unpacker.get_string().unwrap(), "status");
+
+        let (id, system, _) = unpack_message(pack_message(5, false).as_bytes()).unwrap();
+        assert_eq!((id, system), (5, false));
+
+        assert_eq!(GameMessage::SvChat.id(), 3);
+        assert_eq!(GameMessage::SvReadyToEnter.id(), 8);
+        assert_eq!(GameMessage::ClStartInfo.id(), 20);
+        assert_eq!(GameMessage::from_id(25), Some(GameMessage::ClCallVote));
+        assert_eq!(GameMessage::from_id(26), None);
+        assert_eq!(
+            pack_game_message(GameMessage::ClSetTeam).as_bytes(),
+            &[0x24]
+        );
+    }
+}
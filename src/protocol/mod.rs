@@ -0,0 +1,22 @@
+//! Building blocks of the game protocol, beyond the connless requests.
+//!
+//! The game packets start with a [PacketHeader], followed by either a [ControlMessage]
+//! or chunks, each with its [ChunkHeader]. Their payload is compressed with [Huffman] and
+//! the messages in the chunks are built with a [Packer] and read with an [Unpacker].
+//!
+//! Once in game, the server sends the world as a [Snapshot] every few ticks, put together
+//! by a [SnapshotStorage]. Its items are decoded through [SnapObject].
+
+mod huffman;
+mod message;
+mod objects;
+mod packer;
+mod packet;
+mod snapshot;
+
+pub use huffman::*;
+pub use message::*;
+pub use objects::*;
+pub use packer::*;
+pub use packet::*;
+pub use snapshot::*;
@@ -0,0 +1,560 @@
+//! The snapshot items of the 0.6 game, their sizes are listed in
+//! [ITEM_SIZES](crate::protocol::ITEM_SIZES).
+
+use std::convert::TryInto;
+
+/// The red team, the one playing in non team modes.
+pub const TEAM_RED: i32 = 0;
+/// The blue team.
+pub const TEAM_BLUE: i32 = 1;
+/// The spectators.
+pub const TEAM_SPECTATORS: i32 = -1;
+
+/// A snapshot item type, decoded from the ints it's made of.
+pub trait SnapObject: Sized {
+    /// The type id of the items.
+    const TYPE_ID: u16;
+
+    /// Decodes an item, returns `None` if it's too small.
+    fn decode(data: &[i32]) -> Option<Self>;
+}
+
+/// The first `N` ints of the item.
+fn fields<const N: usize>(data: &[i32]) -> Option<[i32; N]> {
+    data.get(..N)?.try_into().ok()
+}
+
+/// Decodes a string stored in ints, 4 bytes each, offset by 128 so they are never 0.
+fn ints_to_string(ints: &[i32]) -> String {
+    let bytes: Vec<u8> = ints
+        .iter()
+        .flat_map(|x| x.to_be_bytes())
+        .map(|x| x.wrapping_sub(128))
+        .take_while(|&x| x != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Projectile {
+    pub x: i32,
+    pub y: i32,
+    pub vel_x: i32,
+    pub vel_y: i32,
+    pub weapon: i32,
+    pub start_tick: i32,
+}
+
+impl SnapObject for Projectile {
+    const TYPE_ID: u16 = 2;
+
+    fn decode(data: &[i32]) -> Option<Self> {
+        let [x, y, vel_x, vel_y, weapon, start_tick] = fields(data)?;
+        Some(Projectile {
+            x,
+            y,
+            vel_x,
+            vel_y,
+            weapon,
+            start_tick,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Laser {
+    pub x: i32,
+    pub y: i32,
+    pub from_x: i32,
+    pub from_y: i32,
+    pub start_tick: i32,
+}
+
+impl SnapObject for Laser {
+    const TYPE_ID: u16 = 3;
+
+    fn decode(data: &[i32]) -> Option<Self> {
+        let [x, y, from_x, from_y, start_tick] = fields(data)?;
+        Some(Laser {
+            x,
+            y,
+            from_x,
+            from_y,
+            start_tick,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pickup {
+    pub x: i32,
+    pub y: i32,
+    /// Health, armor, weapon or ninja.
+    pub kind: i32,
+    /// The weapon, for weapon pickups.
+    pub subtype: i32,
+}
+
+impl SnapObject for Pickup {
+    const TYPE_ID: u16 = 4;
+
+    fn decode(data: &[i32]) -> Option<Self> {
+        let [x, y, kind, subtype] = fields(data)?;
+        Some(Pickup {
+            x,
+            y,
+            kind,
+            subtype,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Flag {
+    pub x: i32,
+    pub y: i32,
+    pub team: i32,
+}
+
+impl SnapObject for Flag {
+    const TYPE_ID: u16 = 5;
+
+    fn decode(data: &[i32]) -> Option<Self> {
+        let [x, y, team] = fields(data)?;
+        Some(Flag { x, y, team })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GameInfo {
+    pub game_flags: i32,
+    pub game_state_flags: i32,
+    pub round_start_tick: i32,
+    pub warmup_timer: i32,
+    pub score_limit: i32,
+    /// In minutes.
+    pub time_limit: i32,
+    pub round_num: i32,
+    pub round_current: i32,
+}
+
+impl GameInfo {
+    pub const FLAG_TEAMS: i32 = 1;
+    pub const FLAG_FLAGS: i32 = 1 << 1;
+    pub const STATE_GAMEOVER: i32 = 1;
+    pub const STATE_SUDDENDEATH: i32 = 1 << 1;
+    pub const STATE_PAUSED: i32 = 1 << 2;
+}
+
+impl SnapObject for GameInfo {
+    const TYPE_ID: u16 = 6;
+
+    fn decode(data: &[i32]) -> Option<Self> {
+        let [game_flags, game_state_flags, round_start_tick, warmup_timer, score_limit, time_limit, round_num, round_current] =
+            fields(data)?;
+        Some(GameInfo {
+            game_flags,
+            game_state_flags,
+            round_start_tick,
+            warmup_timer,
+            score_limit,
+            time_limit,
+            round_num,
+            round_current,
+        })
+    }
+}
+
+/// Where a flag is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlagCarrier {
+    /// Not on the map.
+    Missing,
+    AtStand,
+    /// Lying somewhere after being dropped.
+    Taken,
+    /// Carried by the player with this client id.
+    Player(i32),
+}
+
+impl FlagCarrier {
+    fn from_int(value: i32) -> FlagCarrier {
+        match value {
+            -3 => FlagCarrier::Missing,
+            -2 => FlagCarrier::AtStand,
+            -1 => FlagCarrier::Taken,
+            id => FlagCarrier::Player(id),
+        }
+    }
+}
+
+/// The scores of team games, and the flags in ctf.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GameData {
+    pub teamscore_red: i32,
+    pub teamscore_blue: i32,
+    pub flag_carrier_red: FlagCarrier,
+    pub flag_carrier_blue: FlagCarrier,
+}
+
+impl SnapObject for GameData {
+    const TYPE_ID: u16 = 7;
+
+    fn decode(data: &[i32]) -> Option<Self> {
+        let [teamscore_red, teamscore_blue, flag_carrier_red, flag_carrier_blue] = fields(data)?;
+        Some(GameData {
+            teamscore_red,
+            teamscore_blue,
+            flag_carrier_red: FlagCarrier::from_int(flag_carrier_red),
+            flag_carrier_blue: FlagCarrier::from_int(flag_carrier_blue),
+        })
+    }
+}
+
+/// The physics state of a character, positions are in 1/32 of a tile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CharacterCore {
+    pub tick: i32,
+    pub x: i32,
+    pub y: i32,
+    pub vel_x: i32,
+    pub vel_y: i32,
+    pub angle: i32,
+    pub direction: i32,
+    pub jumped: i32,
+    pub hooked_player: i32,
+    pub hook_state: i32,
+    pub hook_tick: i32,
+    pub hook_x: i32,
+    pub hook_y: i32,
+    pub hook_dx: i32,
+    pub hook_dy: i32,
+}
+
+impl SnapObject for CharacterCore {
+    const TYPE_ID: u16 = 8;
+
+    fn decode(data: &[i32]) -> Option<Self> {
+        let [tick, x, y, vel_x, vel_y, angle, direction, jumped, hooked_player, hook_state, hook_tick, hook_x, hook_y, hook_dx, hook_dy] =
+            fields(data)?;
+        Some(CharacterCore {
+            tick,
+            x,
+            y,
+            vel_x,
+            vel_y,
+            angle,
+            direction,
+            jumped,
+            hooked_player,
+            hook_state,
+            hook_tick,
+            hook_x,
+            hook_y,
+            hook_dx,
+            hook_dy,
+        })
+    }
+}
+
+/// A character alive in the world, its id is the client id of its player.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Character {
+    pub core: CharacterCore,
+    pub player_flags: i32,
+    pub health: i32,
+    pub armor: i32,
+    pub ammo_count: i32,
+    pub weapon: i32,
+    pub emote: i32,
+    pub attack_tick: i32,
+}
+
+impl SnapObject for Character {
+    const TYPE_ID: u16 = 9;
+
+    fn decode(data: &[i32]) -> Option<Self> {
+        let core = CharacterCore::decode(data)?;
+        let [player_flags, health, armor, ammo_count, weapon, emote, attack_tick] =
+            fields(data.get(15..)?)?;
+        Some(Character {
+            core,
+            player_flags,
+            health,
+            armor,
+            ammo_count,
+            weapon,
+            emote,
+            attack_tick,
+        })
+    }
+}
+
+/// The team and score of a player.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlayerInfo {
+    /// Whether it's the player receiving the snapshot.
+    pub local: bool,
+    pub client_id: i32,
+    pub team: i32,
+    pub score: i32,
+    pub latency: i32,
+}
+
+impl SnapObject for PlayerInfo {
+    const TYPE_ID: u16 = 10;
+
+    fn decode(data: &[i32]) -> Option<Self> {
+        let [local, client_id, team, score, latency] = fields(data)?;
+        Some(PlayerInfo {
+            local: local != 0,
+            client_id,
+            team,
+            score,
+            latency,
+        })
+    }
+}
+
+/// The name and look of a player, its id is the client id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientInfo {
+    pub name: String,
+    pub clan: String,
+    pub country: i32,
+    pub skin: String,
+    pub use_custom_color: bool,
+    pub color_body: i32,
+    pub color_feet: i32,
+}
+
+impl SnapObject for ClientInfo {
+    const TYPE_ID: u16 = 11;
+
+    fn decode(data: &[i32]) -> Option<Self> {
+        let [country] = fields(data.get(7..)?)?;
+        let [use_custom_color, color_body, color_feet] = fields(data.get(14..)?)?;
+        Some(ClientInfo {
+            name: ints_to_string(&data[..4]),
+            clan: ints_to_string(&data[4..7]),
+            country,
+            skin: ints_to_string(&data[8..14]),
+            use_custom_color: use_custom_color != 0,
+            color_body,
+            color_feet,
+        })
+    }
+}
+
+/// Who a spectator is watching, sent only to spectators.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpectatorInfo {
+    /// The client id watched, -1 for the free view.
+    pub spectator_id: i32,
+    pub x: i32,
+    pub y: i32,
+}
+
+impl SnapObject for SpectatorInfo {
+    const TYPE_ID: u16 = 12;
+
+    fn decode(data: &[i32]) -> Option<Self> {
+        let [spectator_id, x, y] = fields(data)?;
+        Some(SpectatorInfo { spectator_id, x, y })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Explosion {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl SnapObject for Explosion {
+    const TYPE_ID: u16 = 14;
+
+    fn decode(data: &[i32]) -> Option<Self> {
+        let [x, y] = fields(data)?;
+        Some(Explosion { x, y })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Spawn {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl SnapObject for Spawn {
+    const TYPE_ID: u16 = 15;
+
+    fn decode(data: &[i32]) -> Option<Self> {
+        let [x, y] = fields(data)?;
+        Some(Spawn { x, y })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HammerHit {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl SnapObject for HammerHit {
+    const TYPE_ID: u16 = 16;
+
+    fn decode(data: &[i32]) -> Option<Self> {
+        let [x, y] = fields(data)?;
+        Some(HammerHit { x, y })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Death {
+    pub x: i32,
+    pub y: i32,
+    pub client_id: i32,
+}
+
+impl SnapObject for Death {
+    const TYPE_ID: u16 = 17;
+
+    fn decode(data: &[i32]) -> Option<Self> {
+        let [x, y, client_id] = fields(data)?;
+        Some(Death { x, y, client_id })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SoundGlobal {
+    pub x: i32,
+    pub y: i32,
+    pub sound_id: i32,
+}
+
+impl SnapObject for SoundGlobal {
+    const TYPE_ID: u16 = 18;
+
+    fn decode(data: &[i32]) -> Option<Self> {
+        let [x, y, sound_id] = fields(data)?;
+        Some(SoundGlobal { x, y, sound_id })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SoundWorld {
+    pub x: i32,
+    pub y: i32,
+    pub sound_id: i32,
+}
+
+impl SnapObject for SoundWorld {
+    const TYPE_ID: u16 = 19;
+
+    fn decode(data: &[i32]) -> Option<Self> {
+        let [x, y, sound_id] = fields(data)?;
+        Some(SoundWorld { x, y, sound_id })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DamageIndicator {
+    pub x: i32,
+    pub y: i32,
+    pub angle: i32,
+}
+
+impl SnapObject for DamageIndicator {
+    const TYPE_ID: u16 = 20;
+
+    fn decode(data: &[i32]) -> Option<Self> {
+        let [x, y, angle] = fields(data)?;
+        Some(DamageIndicator { x, y, angle })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::snapshot::*;
+    use pretty_assertions::assert_eq;
+
+    /// Encodes a string the way [ints_to_string] decodes it.
+    fn string_to_ints(value: &str, len: usize) -> Vec<i32> {
+        let mut bytes = value.as_bytes().to_vec();
+        bytes.resize(len * 4, 0);
+        // The last byte is always the terminator.
+        bytes[len * 4 - 1] = 0;
+        bytes
+            .chunks(4)
+            .map(|x| {
+                i32::from_be_bytes([
+                    x[0].wrapping_add(128),
+                    x[1].wrapping_add(128),
+                    x[2].wrapping_add(128),
+                    x[3].wrapping_add(128),
+                ])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn decodes_objects() {
+        let mut snapshot = Snapshot::new();
+
+        let mut client_info = string_to_ints("nameless tee", 4);
+        client_info.extend(string_to_ints("Team", 3));
+        client_info.push(276);
+        client_info.extend(string_to_ints("pinky", 6));
+        client_info.extend(&[1, 65408, 10187898]);
+        snapshot.insert(ClientInfo::TYPE_ID, 3, client_info);
+
+        snapshot.insert(PlayerInfo::TYPE_ID, 3, vec![1, 3, TEAM_BLUE, 12, 40]);
+        snapshot.insert(PlayerInfo::TYPE_ID, 5, vec![0, 5, TEAM_RED, 7, 80]);
+        let mut character: Vec<i32> = (0..15).collect();
+        character.extend(&[0, 10, 5, 10, 1, 0, 42]);
+        snapshot.insert(Character::TYPE_ID, 3, character);
+        snapshot.insert(GameData::TYPE_ID, 0, vec![2, 1, 5, -2]);
+        // Truncated items are skipped.
+        snapshot.insert(Flag::TYPE_ID, 0, vec![1, 2]);
+
+        assert_eq!(
+            snapshot.object::<ClientInfo>(3),
+            Some(ClientInfo {
+                name: "nameless tee".to_owned(),
+                clan: "Team".to_owned(),
+                country: 276,
+                skin: "pinky".to_owned(),
+                use_custom_color: true,
+                color_body: 65408,
+                color_feet: 10187898,
+            })
+        );
+
+        let scores: Vec<_> = snapshot
+            .objects::<PlayerInfo>()
+            .map(|(_, x)| (x.client_id, x.team, x.score))
+            .collect();
+        assert_eq!(scores, vec![(3, TEAM_BLUE, 12), (5, TEAM_RED, 7)]);
+
+        let character = snapshot.object::<Character>(3).unwrap();
+        assert_eq!((character.core.x, character.core.y), (1, 2));
+        assert_eq!((character.health, character.attack_tick), (10, 42));
+
+        let game_data = snapshot.object::<GameData>(0).unwrap();
+        assert_eq!(game_data.flag_carrier_red, FlagCarrier::Player(5));
+        assert_eq!(game_data.flag_carrier_blue, FlagCarrier::AtStand);
+
+        assert_eq!(snapshot.objects::<Flag>().count(), 0);
+        assert_eq!(snapshot.object::<Laser>(0), None);
+    }
+
+    #[test]
+    fn decodes_strings() {
+        assert_eq!(ints_to_string(&string_to_ints("", 4)), "");
+        assert_eq!(ints_to_string(&string_to_ints("abc", 1)), "abc");
+        // Too long names get cut.
+        assert_eq!(ints_to_string(&string_to_ints("abcdefgh", 2)), "abcdefg");
+        assert_eq!(ints_to_string(&string_to_ints("ünïcode", 4)), "ünïcode");
+    }
+}
@@ -0,0 +1,230 @@
+use crate::errors::*;
+
+/// The most bytes a packed int takes.
+pub const MAX_INT_SIZE: usize = 5;
+
+/// Packs an int the teeworlds way, appending it to the buffer.
+///
+/// The first byte holds an extension bit, the sign bit and 6 bits of the value,
+/// the next ones an extension bit and 7 bits each. Negative values are stored inverted.
+pub fn pack_int(buf: &mut Vec<u8>, value: i32) {
+    let mut byte = ((value >> 25) & 0x40) as u8;
+    // Invert negative values.
+    let mut value = (value ^ (value >> 31)) as u32;
+
+    byte |= (value & 0x3f) as u8;
+    value >>= 6;
+
+    while value != 0 {
+        buf.push(byte | 0x80);
+        byte = (value & 0x7f) as u8;
+        value >>= 7;
+    }
+
+    buf.push(byte);
+}
+
+/// Unpacks an int, returning it along with the remaining data.
+pub fn unpack_int(data: &[u8]) -> Result<(i32, &[u8])> {
+    let first = *data
+        .first()
+        .ok_or(RequestError::InvalidData("missing int"))?;
+    let sign = (first >> 6) & 1;
+    let mut value = (first & 0x3f) as u32;
+    let mut len = 1;
+
+    if first & 0x80 != 0 {
+        for (i, &byte) in data[1..].iter().enumerate().take(MAX_INT_SIZE - 1) {
+            value |= ((byte & 0x7f) as u32) << (6 + 7 * i);
+            len += 1;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+
+        if data[len - 1] & 0x80 != 0 && len < MAX_INT_SIZE {
+            return Err(RequestError::InvalidData("truncated int"));
+        }
+    }
+
+    let value = if sign == 1 { !value } else { value } as i32;
+    Ok((value, &data[len..]))
+}
+
+/// Packs a list of ints, like the snapshot deltas.
+pub fn pack_ints(values: &[i32]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(values.len());
+    for &value in values {
+        pack_int(&mut buf, value);
+    }
+    buf
+}
+
+/// Unpacks a list of ints packed with [pack_ints].
+pub fn unpack_ints(mut data: &[u8]) -> Result<Vec<i32>> {
+    let mut values = Vec::with_capacity(data.len());
+    while !data.is_empty() {
+        let (value, rest) = unpack_int(data)?;
+        values.push(value);
+        data = rest;
+    }
+    Ok(values)
+}
+
+/// Builds a message out of ints, strings and raw data.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Packer {
+    buf: Vec<u8>,
+}
+
+impl Packer {
+    pub fn new() -> Packer {
+        Packer::default()
+    }
+
+    pub fn add_int(&mut self, value: i32) -> &mut Packer {
+        pack_int(&mut self.buf, value);
+        self
+    }
+
+    /// Adds a zero terminated string.
+    ///
+    /// Zero bytes inside it would end it early, so they are dropped.
+    pub fn add_string(&mut self, value: &str) -> &mut Packer {
+        self.buf.extend(value.bytes().filter(|&x| x != 0));
+        self.buf.push(0);
+        self
+    }
+
+    pub fn add_raw(&mut self, data: &[u8]) -> &mut Packer {
+        self.buf.extend_from_slice(data);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// Reads a message packed by a [Packer], doing zero copy.
+#[derive(Debug, Clone, Copy)]
+pub struct Unpacker<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Unpacker<'a> {
+    pub fn new(data: &'a [u8]) -> Unpacker<'a> {
+        Unpacker { data }
+    }
+
+    pub fn get_int(&mut self) -> Result<i32> {
+        let (value, rest) = unpack_int(self.data)?;
+        self.data = rest;
+        Ok(value)
+    }
+
+    /// Reads a zero terminated string.
+    pub fn get_string(&mut self) -> Result<&'a str> {
+        let end = self
+            .data
+            .iter()
+            .position(|&x| x == 0)
+            .ok_or(RequestError::InvalidData("unterminated string"))?;
+        let value = std::str::from_utf8(&self.data[..end])?;
+        self.data = &self.data[end + 1..];
+        Ok(value)
+    }
+
+    pub fn get_raw(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.data.len() {
+            return Err(RequestError::InvalidData("raw data out of bounds"));
+        }
+
+        let (value, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(value)
+    }
+
+    /// The data not read yet.
+    pub fn remaining(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn packs_ints() {
+        let vectors: &[(i32, &[u8])] = &[
+            (0, &[0x00]),
+            (1, &[0x01]),
+            (63, &[0x3f]),
+            (64, &[0x80, 0x01]),
+            (-1, &[0x40]),
+            (-64, &[0x7f]),
+            (-65, &[0xc0, 0x01]),
+            (1000, &[0xa8, 0x0f]),
+            (i32::MAX, &[0xbf, 0xff, 0xff, 0xff, 0x0f]),
+            (i32::MIN, &[0xff, 0xff, 0xff, 0xff, 0x0f]),
+        ];
+
+        for &(value, packed) in vectors {
+            let mut buf = Vec::new();
+            pack_int(&mut buf, value);
+            assert_eq!(buf, packed, "packing {}", value);
+            assert_eq!(unpack_int(packed).unwrap(), (value, &[][..]));
+        }
+
+        assert!(unpack_int(&[]).is_err());
+        assert!(unpack_int(&[0x80]).is_err());
+
+        let values = [0, -1, 1000, i32::MIN];
+        assert_eq!(unpack_ints(&pack_ints(&values)).unwrap(), values);
+        assert!(unpack_ints(&[0x00, 0x80]).is_err());
+    }
+
+    #[test]
+    fn packs_messages() {
+        let mut packer = Packer::new();
+        packer
+            .add_int(1000)
+            .add_string("0.6 626fce9a778df4d4")
+            .add_string("")
+            .add_int(-1)
+            .add_raw(b"\x01\x02");
+
+        let mut expected = vec![0xa8, 0x0f];
+        expected.extend_from_slice(b"0.6 626fce9a778df4d4\0\0");
+        expected.extend_from_slice(&[0x40, 0x01, 0x02]);
+        assert_eq!(packer.as_bytes(), &expected[..]);
+
+        let mut unpacker = Unpacker::new(packer.as_bytes());
+        assert_eq!(unpacker.get_int().unwrap(), 1000);
+        assert_eq!(unpacker.get_string().unwrap(), "0.6 626fce9a778df4d4");
+        assert_eq!(unpacker.get_string().unwrap(), "");
+        assert_eq!(unpacker.get_int().unwrap(), -1);
+        assert!(unpacker.get_raw(3).is_err());
+        assert_eq!(unpacker.get_raw(2).unwrap(), b"\x01\x02");
+        assert!(unpacker.is_empty());
+        assert!(unpacker.get_string().is_err());
+    }
+}
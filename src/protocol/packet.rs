@@ -0,0 +1,450 @@
+use crate::errors::*;
+use crate::protocol::huffman;
+
+/// Sequence numbers of vital chunks wrap around at this value.
+pub const MAX_SEQUENCE: u16 = 1 << 10;
+
+/// The biggest packet sent over the network.
+pub const MAX_PACKET_SIZE: usize = 1400;
+
+/// The biggest payload a packet carries, after the header.
+pub const MAX_PAYLOAD: usize = MAX_PACKET_SIZE - PACKET_HEADER_SIZE;
+
+pub const PACKET_HEADER_SIZE: usize = 3;
+
+/// Magic sent with the connect and accept messages by clients and servers supporting
+/// the DDNet security token.
+pub const SECURITY_TOKEN_MAGIC: &[u8; 4] = b"TKEN";
+
+/// A token DDNet appends to every packet of a connection, to prevent spoofing.
+pub type SecurityToken = [u8; 4];
+
+/// Flags of a packet header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct PacketFlags(pub u8);
+
+impl PacketFlags {
+    /// The packet holds a control message instead of chunks.
+    pub const CONTROL: PacketFlags = PacketFlags(1);
+    /// The packet is a connless request or response.
+    pub const CONNLESS: PacketFlags = PacketFlags(1 << 1);
+    /// The peer asks for all the vital chunks not acknowledged yet.
+    pub const RESEND: PacketFlags = PacketFlags(1 << 2);
+    /// The payload is huffman compressed.
+    pub const COMPRESSION: PacketFlags = PacketFlags(1 << 3);
+
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Whether all the flags in `other` are set.
+    pub fn contains(self, other: PacketFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl std::ops::BitOr for PacketFlags {
+    type Output = PacketFlags;
+
+    fn bitor(self, rhs: PacketFlags) -> PacketFlags {
+        PacketFlags(self.0 | rhs.0)
+    }
+}
+
+/// The header starting every packet of a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketHeader {
+    pub flags: PacketFlags,
+    /// The sequence of the last vital chunk received by the sender.
+    pub ack: u16,
+    pub num_chunks: u8,
+}
+
+impl PacketHeader {
+    pub fn pack(&self) -> [u8; PACKET_HEADER_SIZE] {
+        [
+            (self.flags.bits() << 4) | ((self.ack >> 8) & 0xf) as u8,
+            self.ack as u8,
+            self.num_chunks,
+        ]
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<PacketHeader> {
+        if data.len() < PACKET_HEADER_SIZE {
+            return Err(RequestError::InvalidData("packet too short"));
+        }
+
+        Ok(PacketHeader {
+            flags: PacketFlags(data[0] >> 4),
+            ack: (((data[0] & 0xf) as u16) << 8) | data[1] as u16,
+            num_chunks: data[2],
+        })
+    }
+}
+
+/// A packet of a connection, with its payload decompressed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    pub header: PacketHeader,
+    pub payload: Vec<u8>,
+}
+
+impl Packet {
+    /// Builds the datagram, compressing the payload if that makes it smaller.
+    ///
+    /// The security token, if any, is appended to the payload before compressing it,
+    /// DDNet checks it at the end of the decompressed data.
+    pub fn pack(&self, token: Option<SecurityToken>) -> Vec<u8> {
+        let mut header = self.header;
+        let mut payload = self.payload.clone();
+        if let Some(token) = token {
+            payload.extend_from_slice(&token);
+        }
+        let compressed = huffman::compress(&payload);
+
+        let payload =
+            if !header.flags.contains(PacketFlags::CONTROL) && compressed.len() < payload.len() {
+                header.flags = header.flags | PacketFlags::COMPRESSION;
+                compressed
+            } else {
+                payload
+            };
+
+        let mut data = Vec::with_capacity(PACKET_HEADER_SIZE + payload.len());
+        data.extend_from_slice(&header.pack());
+        data.extend_from_slice(&payload);
+        data
+    }
+
+    /// Reads a datagram, decompressing the payload if needed.
+    ///
+    /// A trailing security token is left in the payload, the chunks and control
+    /// messages know where they end.
+    pub fn unpack(data: &[u8]) -> Result<Packet> {
+        let mut header = PacketHeader::unpack(data)?;
+        let payload = &data[PACKET_HEADER_SIZE..];
+
+        if header.flags.contains(PacketFlags::CONNLESS) {
+            return Err(RequestError::InvalidData("connless packet"));
+        }
+
+        let payload = if header.flags.contains(PacketFlags::COMPRESSION) {
+            header.flags = PacketFlags(header.flags.bits() & !PacketFlags::COMPRESSION.bits());
+            huffman::decompress(payload)?
+        } else {
+            payload.to_vec()
+        };
+
+        Ok(Packet { header, payload })
+    }
+}
+
+/// The header of a chunk, the unit messages are sent in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkHeader {
+    /// Vital chunks are resent until acknowledged and delivered in order.
+    pub vital: bool,
+    /// The chunk was already sent before.
+    pub resend: bool,
+    pub size: u16,
+    /// Only meaningful for vital chunks.
+    pub sequence: u16,
+}
+
+impl ChunkHeader {
+    pub fn pack(&self, buf: &mut Vec<u8>) {
+        let flags = (self.vital as u8) | ((self.resend as u8) << 1);
+        buf.push((flags << 6) | ((self.size >> 4) & 0x3f) as u8);
+
+        if self.vital {
+            buf.push(((self.sequence >> 2) & 0xf0) as u8 | (self.size & 0xf) as u8);
+            buf.push(self.sequence as u8);
+        } else {
+            buf.push((self.size & 0xf) as u8);
+        }
+    }
+
+    /// Reads a chunk header, returning it with the remaining data.
+    pub fn unpack(data: &[u8]) -> Result<(ChunkHeader, &[u8])> {
+        if data.len() < 2 {
+            return Err(RequestError::InvalidData("chunk header too short"));
+        }
+
+        let flags = data[0] >> 6;
+        let vital = flags & 1 != 0;
+        let size = (((data[0] & 0x3f) as u16) << 4) | (data[1] & 0xf) as u16;
+
+        if !vital {
+            let header = ChunkHeader {
+                vital,
+                resend: flags & 2 != 0,
+                size,
+                sequence: 0,
+            };
+            return Ok((header, &data[2..]));
+        }
+
+        let low = *data
+            .get(2)
+            .ok_or(RequestError::InvalidData("chunk header too short"))?;
+        let header = ChunkHeader {
+            vital,
+            resend: flags & 2 != 0,
+            size,
+            sequence: (((data[1] & 0xf0) as u16) << 2) | low as u16,
+        };
+        Ok((header, &data[3..]))
+    }
+}
+
+/// Splits a payload into its chunks.
+pub fn unpack_chunks(payload: &[u8], num_chunks: u8) -> Result<Vec<(ChunkHeader, &[u8])>> {
+    let mut chunks = Vec::with_capacity(num_chunks as usize);
+    let mut data = payload;
+
+    for _ in 0..num_chunks {
+        let (header, rest) = ChunkHeader::unpack(data)?;
+        if header.size as usize > rest.len() {
+            return Err(RequestError::InvalidData("chunk out of bounds"));
+        }
+
+        let (chunk, rest) = rest.split_at(header.size as usize);
+        chunks.push((header, chunk));
+        data = rest;
+    }
+
+    Ok(chunks)
+}
+
+/// Whether the sequence was already acknowledged, being at most half
+/// the sequence space behind the ack.
+pub fn is_sequence_acked(sequence: u16, ack: u16) -> bool {
+    let behind = (ack + MAX_SEQUENCE - sequence) % MAX_SEQUENCE;
+    behind < MAX_SEQUENCE / 2
+}
+
+/// Messages controlling the connection itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlMessage {
+    KeepAlive,
+    /// Sent by the client to start connecting, announcing whether it supports
+    /// the security token.
+    Connect {
+        security_token: bool,
+    },
+    /// The server accepted the connection, DDNet servers send the security token to use.
+    ConnectAccept {
+        token: Option<SecurityToken>,
+    },
+    /// The client got the connect accept.
+    Accept,
+    Close {
+        reason: Option<String>,
+    },
+}
+
+impl ControlMessage {
+    pub fn id(&self) -> u8 {
+        match self {
+            ControlMessage::KeepAlive => 0,
+            ControlMessage::Connect { .. } => 1,
+            ControlMessage::ConnectAccept { .. } => 2,
+            ControlMessage::Accept => 3,
+            ControlMessage::Close { .. } => 4,
+        }
+    }
+
+    /// Builds the control packet.
+    pub fn pack(&self, ack: u16, token: Option<SecurityToken>) -> Vec<u8> {
+        let mut payload = vec![self.id()];
+
+        match self {
+            ControlMessage::Connect {
+                security_token: true,
+            } => payload.extend_from_slice(SECURITY_TOKEN_MAGIC),
+            ControlMessage::ConnectAccept { token: Some(token) } => {
+                payload.extend_from_slice(SECURITY_TOKEN_MAGIC);
+                payload.extend_from_slice(token);
+            }
+            ControlMessage::Close {
+                reason: Some(reason),
+            } => {
+                payload.extend(reason.bytes().filter(|&x| x != 0));
+                payload.push(0);
+            }
+            _ => {}
+        }
+
+        Packet {
+            header: PacketHeader {
+                flags: PacketFlags::CONTROL,
+                ack,
+                num_chunks: 0,
+            },
+            payload,
+        }
+        .pack(token)
+    }
+
+    /// Reads the control message of a control packet payload.
+    pub fn unpack(payload: &[u8]) -> Result<ControlMessage> {
+        let (&id, extra) = payload
+            .split_first()
+            .ok_or(RequestError::InvalidData("empty control message"))?;
+        let has_magic = extra.starts_with(SECURITY_TOKEN_MAGIC);
+
+        Ok(match id {
+            0 => ControlMessage::KeepAlive,
+            1 => ControlMessage::Connect {
+                security_token: has_magic,
+            },
+            2 => {
+                let token = extra.get(4..8).filter(|_| has_magic).map(|x| {
+                    let mut token = [0; 4];
+                    token.copy_from_slice(x);
+                    token
+                });
+                ControlMessage::ConnectAccept { token }
+            }
+            3 => ControlMessage::Accept,
+            4 => {
+                let end = extra.iter().position(|&x| x == 0).unwrap_or(extra.len());
+                let reason = String::from_utf8_lossy(&extra[..end]).into_owned();
+                ControlMessage::Close {
+                    reason: Some(reason).filter(|x| !x.is_empty()),
+                }
+            }
+            _ => return Err(RequestError::InvalidData("unknown control message")),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn packs_headers() {
+        let header = PacketHeader {
+            flags: PacketFlags::RESEND,
+            ack: 0x2ab,
+            num_chunks: 3,
+        };
+        assert_eq!(header.pack(), [0x42, 0xab, 0x03]);
+        assert_eq!(PacketHeader::unpack(&header.pack()).unwrap(), header);
+
+        let chunk = ChunkHeader {
+            vital: true,
+            resend: false,
+            size: 0x123,
+            sequence: 0x3cd,
+        };
+        let mut buf = Vec::new();
+        chunk.pack(&mut buf);
+        assert_eq!(buf, [0x52, 0xf3, 0xcd]);
+        assert_eq!(ChunkHeader::unpack(&buf).unwrap(), (chunk, &[][..]));
+
+        let chunk = ChunkHeader {
+            vital: false,
+            resend: false,
+            size: 5,
+            sequence: 0,
+        };
+        let mut buf = Vec::new();
+        chunk.pack(&mut buf);
+        assert_eq!(buf, [0x00, 0x05]);
+    }
+
+    #[test]
+    fn packs_control_messages() {
+        let connect = ControlMessage::Connect {
+            security_token: true,
+        };
+        assert_eq!(connect.pack(0, None), b"\x10\x00\x00\x01TKEN");
+
+        let accept = ControlMessage::ConnectAccept {
+            token: Some([1, 2, 3, 4]),
+        };
+        let packed = accept.pack(0, None);
+        let packet = Packet::unpack(&packed).unwrap();
+        assert!(packet.header.flags.contains(PacketFlags::CONTROL));
+        assert_eq!(ControlMessage::unpack(&packet.payload).unwrap(), accept);
+
+        // With a security token appended.
+        let close = ControlMessage::Close {
+            reason: Some("kicked".to_owned()),
+        };
+        let packed = close.pack(7, Some([9, 9, 9, 9]));
+        assert_eq!(packed, b"\x10\x07\x00\x04kicked\x00\x09\x09\x09\x09");
+        let packet = Packet::unpack(&packed).unwrap();
+        assert_eq!(ControlMessage::unpack(&packet.payload).unwrap(), close);
+    }
+
+    #[test]
+    fn packs_chunks() {
+        let mut payload = Vec::new();
+        for (i, data) in [&b"\x01\x00\x00\x00"[..], &[0; 8]].iter().enumerate() {
+            ChunkHeader {
+                vital: i == 0,
+                resend: false,
+                size: data.len() as u16,
+                sequence: 1,
+            }
+            .pack(&mut payload);
+            payload.extend_from_slice(data);
+        }
+
+        let packet = Packet {
+            header: PacketHeader {
+                flags: PacketFlags::default(),
+                ack: 0,
+                num_chunks: 2,
+            },
+            payload,
+        };
+        let packed = packet.pack(Some([1, 2, 3, 4]));
+        assert!(PacketFlags(packed[0] >> 4).contains(PacketFlags::COMPRESSION));
+
+        // The token is compressed along with the chunks.
+        let unpacked = Packet::unpack(&packed).unwrap();
+        assert_eq!(unpacked.header, packet.header);
+        assert_eq!(unpacked.payload[..packet.payload.len()], packet.payload[..]);
+        assert_eq!(unpacked.payload[packet.payload.len()..], [1, 2, 3, 4]);
+
+        let chunks = unpack_chunks(&unpacked.payload, 2).unwrap();
+        assert_eq!(chunks[0].1, b"\x01\x00\x00\x00");
+        assert_eq!(chunks[0].0.sequence, 1);
+        assert_eq!(chunks[1].1, [0; 8]);
+        assert!(!chunks[1].0.vital);
+
+        assert!(unpack_chunks(&unpacked.payload, 3).is_err());
+    }
+
+    #[test]
+    fn compresses_security_tokens() {
+        let packet = Packet {
+            header: PacketHeader {
+                flags: PacketFlags::default(),
+                ack: 0,
+                num_chunks: 1,
+            },
+            payload: vec![0; 32],
+        };
+        let packed = packet.pack(Some([0xde, 0xad, 0xbe, 0xef]));
+        assert!(PacketFlags(packed[0] >> 4).contains(PacketFlags::COMPRESSION));
+
+        let payload = huffman::decompress(&packed[PACKET_HEADER_SIZE..]).unwrap();
+        assert_eq!(payload.len(), 36);
+        assert_eq!(&payload[32..], [0xde, 0xad, 0xbe, 0xef]);
+    }
+
+    #[test]
+    fn compares_sequences() {
+        assert!(is_sequence_acked(5, 5));
+        assert!(is_sequence_acked(4, 5));
+        assert!(!is_sequence_acked(6, 5));
+        assert!(is_sequence_acked(1020, 3));
+        assert!(!is_sequence_acked(3, 1020));
+    }
+}
@@ -0,0 +1,454 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::errors::*;
+use crate::protocol::message::*;
+use crate::protocol::objects::*;
+use crate::protocol::packer::*;
+
+/// The most parts a snapshot is split into.
+pub const MAX_SNAPSHOT_PARTS: i32 = 64;
+
+/// The size in ints of the items of each 0.6 type, indexed by type id.
+///
+/// Deltas only carry the size of items whose type isn't listed here, or is listed with 0.
+pub const ITEM_SIZES: [usize; 21] = [
+    0, 10, 6, 5, 4, 3, 8, 4, 15, 22, 5, 17, 3, 2, 2, 2, 2, 3, 3, 3, 3,
+];
+
+/// The most ints a snapshot item can hold.
+const MAX_ITEM_SIZE: i32 = 1024;
+
+/// How many snapshots a [SnapshotStorage] keeps at most, 3 seconds of game.
+const MAX_STORED_SNAPSHOTS: usize = 150;
+
+fn item_size(type_id: u16) -> Option<usize> {
+    ITEM_SIZES
+        .get(type_id as usize)
+        .copied()
+        .filter(|&x| x != 0)
+}
+
+/// The state of the game at a tick, as a set of items each identified by its type and id.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    items: BTreeMap<(u16, u16), Vec<i32>>,
+}
+
+impl Snapshot {
+    pub fn new() -> Snapshot {
+        Snapshot::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// The data of the item with the given type and id.
+    pub fn get(&self, type_id: u16, id: u16) -> Option<&[i32]> {
+        self.items.get(&(type_id, id)).map(|x| &x[..])
+    }
+
+    /// Adds an item, replacing the one with the same type and id.
+    pub fn insert(&mut self, type_id: u16, id: u16, data: Vec<i32>) {
+        self.items.insert((type_id, id), data);
+    }
+
+    /// The items, as their type, id and data, sorted by type then id.
+    pub fn items(&self) -> impl Iterator<Item = (u16, u16, &[i32])> {
+        self.items
+            .iter()
+            .map(|(&(type_id, id), data)| (type_id, id, &data[..]))
+    }
+
+    /// The items of the given type decoded, along with their id.
+    ///
+    /// Items too small for their type are skipped.
+    pub fn objects<T: SnapObject>(&self) -> impl Iterator<Item = (u16, T)> + '_ {
+        self.items
+            .range((T::TYPE_ID, 0)..=(T::TYPE_ID, u16::MAX))
+            .filter_map(|(&(_, id), data)| Some((id, T::decode(data)?)))
+    }
+
+    /// The item of the given type and id decoded.
+    pub fn object<T: SnapObject>(&self, id: u16) -> Option<T> {
+        T::decode(self.get(T::TYPE_ID, id)?)
+    }
+
+    /// The checksum sent along with the snapshot, the sum of all the item data.
+    pub fn crc(&self) -> i32 {
+        self.items
+            .values()
+            .flatten()
+            .fold(0i32, |crc, &x| crc.wrapping_add(x))
+    }
+
+    /// Builds the snapshot described by a delta against this one.
+    ///
+    /// The delta holds the keys of the items removed, then the items added or changed,
+    /// the changed ones as the difference with their previous value.
+    pub fn apply_delta(&self, delta: &[i32]) -> Result<Snapshot> {
+        let mut delta = delta.iter().copied();
+        let mut next = || {
+            delta
+                .next()
+                .ok_or(RequestError::InvalidData("delta too short"))
+        };
+
+        let num_deleted = next()?;
+        let num_updated = next()?;
+        // Temporary items, never sent.
+        next()?;
+        if num_deleted < 0 || num_updated < 0 {
+            return Err(RequestError::InvalidData("negative delta item count"));
+        }
+
+        let mut snapshot = self.clone();
+        for _ in 0..num_deleted {
+            let key = next()?;
+            snapshot.items.remove(&((key >> 16) as u16, key as u16));
+        }
+
+        for _ in 0..num_updated {
+            let type_id = next()?;
+            let id = next()?;
+            if !(0..=u16::MAX as i32).contains(&type_id) || !(0..=u16::MAX as i32).contains(&id) {
+                return Err(RequestError::InvalidData("delta item key out of range"));
+            }
+            let key = (type_id as u16, id as u16);
+
+            let size = match item_size(key.0) {
+                Some(size) => size,
+                None => {
+                    let size = next()?;
+                    if !(0..=MAX_ITEM_SIZE).contains(&size) {
+                        return Err(RequestError::InvalidData("delta item size out of range"));
+                    }
+                    size as usize
+                }
+            };
+
+            let mut data = Vec::with_capacity(size);
+            for _ in 0..size {
+                data.push(next()?);
+            }
+
+            if let Some(old) = self.items.get(&key) {
+                if old.len() == data.len() {
+                    for (x, old) in data.iter_mut().zip(old) {
+                        *x = x.wrapping_add(*old);
+                    }
+                }
+            }
+            snapshot.items.insert(key, data);
+        }
+
+        Ok(snapshot)
+    }
+
+    /// Describes the given snapshot as a delta against this one, see
+    /// [Snapshot::apply_delta].
+    pub fn create_delta(&self, to: &Snapshot) -> Vec<i32> {
+        let deleted: Vec<i32> = self
+            .items
+            .keys()
+            .filter(|x| !to.items.contains_key(x))
+            .map(|&(type_id, id)| ((type_id as i32) << 16) | id as i32)
+            .collect();
+
+        let mut num_updated = 0;
+        let mut updates = Vec::new();
+        for (&(type_id, id), data) in &to.items {
+            let old = self
+                .items
+                .get(&(type_id, id))
+                .filter(|x| x.len() == data.len());
+            if old == Some(data) {
+                continue;
+            }
+
+            num_updated += 1;
+            updates.push(type_id as i32);
+            updates.push(id as i32);
+            if item_size(type_id).is_none() {
+                updates.push(data.len() as i32);
+            }
+            match old {
+                Some(old) => {
+                    updates.extend(data.iter().zip(old).map(|(x, old)| x.wrapping_sub(*old)))
+                }
+                None => updates.extend_from_slice(data),
+            }
+        }
+
+        let mut delta = vec![deleted.len() as i32, num_updated, 0];
+        delta.extend(deleted);
+        delta.extend(updates);
+        delta
+    }
+}
+
+/// Puts together the snapshots sent by the server, each as a delta against an earlier
+/// one and maybe split in parts, keeping the ones later deltas may use.
+///
+/// The server only uses the snapshots we told it about through [SnapshotStorage::ack_tick]
+/// in the input messages, it sends full snapshots until then.
+#[derive(Debug, Default)]
+pub struct SnapshotStorage {
+    /// By tick.
+    snapshots: VecDeque<(i32, Snapshot)>,
+    /// The parts of the snapshot being received.
+    parts_tick: i32,
+    parts: BTreeMap<i32, Vec<u8>>,
+    ack_tick: Option<i32>,
+}
+
+impl SnapshotStorage {
+    pub fn new() -> SnapshotStorage {
+        SnapshotStorage::default()
+    }
+
+    /// The tick of the last snapshot rebuilt, -1 if the server should send a full one.
+    pub fn ack_tick(&self) -> i32 {
+        self.ack_tick.unwrap_or(-1)
+    }
+
+    /// The last snapshot rebuilt, along with its tick.
+    pub fn latest(&self) -> Option<(i32, &Snapshot)> {
+        self.snapshots.back().map(|(tick, x)| (*tick, x))
+    }
+
+    /// Handles a snapshot message, returning the snapshot and its tick once complete.
+    ///
+    /// Messages other than `Snap`, `SnapEmpty` and `SnapSingle` are ignored. Fails if
+    /// the delta refers to a snapshot we don't have or the checksum doesn't match, in
+    /// which case the next ack asks for a full snapshot.
+    pub fn handle_message(
+        &mut self,
+        message: SystemMessage,
+        unpacker: &mut Unpacker<'_>,
+    ) -> Result<Option<(i32, &Snapshot)>> {
+        if !matches!(
+            message,
+            SystemMessage::Snap | SystemMessage::SnapEmpty | SystemMessage::SnapSingle
+        ) {
+            return Ok(None);
+        }
+
+        let tick = unpacker.get_int()?;
+        let delta_tick = tick.wrapping_sub(unpacker.get_int()?);
+
+        let (num_parts, part) = match message {
+            SystemMessage::Snap => (unpacker.get_int()?, unpacker.get_int()?),
+            _ => (1, 0),
+        };
+        if !(1..=MAX_SNAPSHOT_PARTS).contains(&num_parts) || !(0..num_parts).contains(&part) {
+            return Err(RequestError::InvalidData("snapshot part out of range"));
+        }
+
+        let (crc, data) = match message {
+            SystemMessage::SnapEmpty => (None, &[][..]),
+            _ => {
+                let crc = unpacker.get_int()?;
+                let size = unpacker.get_int()?;
+                if size < 0 {
+                    return Err(RequestError::InvalidData("negative snapshot part size"));
+                }
+                (Some(crc), unpacker.get_raw(size as usize)?)
+            }
+        };
+
+        if tick != self.parts_tick {
+            self.parts_tick = tick;
+            self.parts.clear();
+        }
+        self.parts.insert(part, data.to_vec());
+        if self.parts.len() < num_parts as usize {
+            return Ok(None);
+        }
+
+        let data: Vec<u8> = std::mem::take(&mut self.parts)
+            .into_values()
+            .flatten()
+            .collect();
+
+        match self.rebuild(tick, delta_tick, crc, &data) {
+            Ok(()) => {
+                self.ack_tick = Some(tick);
+                Ok(self.latest())
+            }
+            Err(e) => {
+                self.ack_tick = None;
+                Err(e)
+            }
+        }
+    }
+
+    fn rebuild(&mut self, tick: i32, delta_tick: i32, crc: Option<i32>, data: &[u8]) -> Result<()> {
+        let empty = Snapshot::new();
+        let base = if delta_tick < 0 {
+            &empty
+        } else {
+            self.snapshots
+                .iter()
+                .find(|(x, _)| *x == delta_tick)
+                .map(|(_, x)| x)
+                .ok_or(RequestError::InvalidData("snapshot delta base missing"))?
+        };
+
+        let snapshot = if data.is_empty() {
+            // An empty snapshot repeats its base.
+            base.clone()
+        } else {
+            base.apply_delta(&unpack_ints(data)?)?
+        };
+
+        if let Some(crc) = crc {
+            if snapshot.crc() != crc {
+                return Err(RequestError::InvalidData("snapshot crc mismatch"));
+            }
+        }
+
+        // Later deltas won't go further back than this one.
+        self.snapshots
+            .retain(|(x, _)| *x >= delta_tick && *x < tick);
+        while self.snapshots.len() >= MAX_STORED_SNAPSHOTS {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back((tick, snapshot));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn sample() -> Snapshot {
+        let mut snapshot = Snapshot::new();
+        // Game data and a flag.
+        snapshot.insert(7, 0, vec![1, 0, -2, 3]);
+        snapshot.insert(5, 0, vec![320, 640, 0]);
+        // A type without a known size.
+        snapshot.insert(300, 4, vec![7, 8]);
+        snapshot
+    }
+
+    fn snap_message(
+        message: SystemMessage,
+        tick: i32,
+        delta_tick: i32,
+        part: Option<(i32, i32)>,
+        crc: i32,
+        data: &[u8],
+    ) -> Packer {
+        let mut packer = pack_system_message(message);
+        packer.add_int(tick).add_int(tick - delta_tick);
+        if let Some((num_parts, part)) = part {
+            packer.add_int(num_parts).add_int(part);
+        }
+        if message != SystemMessage::SnapEmpty {
+            packer.add_int(crc).add_int(data.len() as i32).add_raw(data);
+        }
+        packer
+    }
+
+    fn handle(storage: &mut SnapshotStorage, packer: &Packer) -> Result<Option<(i32, Snapshot)>> {
+        let (id, _, mut unpacker) = unpack_message(packer.as_bytes()).unwrap();
+        let message = SystemMessage::from_id(id).unwrap();
+        Ok(storage
+            .handle_message(message, &mut unpacker)?
+            .map(|(tick, x)| (tick, x.clone())))
+    }
+
+    #[test]
+    fn applies_deltas() {
+        let empty = Snapshot::new();
+        let first = sample();
+
+        let delta = empty.create_delta(&first);
+        assert_eq!(
+            delta,
+            vec![0, 3, 0, 5, 0, 320, 640, 0, 7, 0, 1, 0, -2, 3, 300, 4, 2, 7, 8]
+        );
+        assert_eq!(empty.apply_delta(&delta).unwrap(), first);
+
+        let mut second = first.clone();
+        second.insert(5, 0, vec![330, 600, 0]);
+        second.insert(9, 1, vec![0; 22]);
+        second.items.remove(&(300, 4));
+
+        let delta = first.create_delta(&second);
+        assert_eq!(
+            delta,
+            [
+                &[1, 2, 0, 300 << 16 | 4, 5, 0, 10, -40, 0, 9, 1][..],
+                &[0; 22]
+            ]
+            .concat()
+        );
+        assert_eq!(first.apply_delta(&delta).unwrap(), second);
+        assert_eq!(second.crc(), 1 + 3 + 330 + 600 - 2);
+
+        assert!(first.apply_delta(&delta[..delta.len() - 1]).is_err());
+        assert!(first.apply_delta(&[0, 1, 0, 300, 1, -1]).is_err());
+    }
+
+    #[test]
+    fn stores_snapshots() {
+        let mut storage = SnapshotStorage::new();
+        assert_eq!(storage.ack_tick(), -1);
+
+        // A full snapshot split in two parts.
+        let first = sample();
+        let data = pack_ints(&Snapshot::new().create_delta(&first));
+        let (start, end) = data.split_at(5);
+        let crc = first.crc();
+        let part = snap_message(SystemMessage::Snap, 100, -1, Some((2, 1)), crc, end);
+        assert_eq!(handle(&mut storage, &part).unwrap(), None);
+        let part = snap_message(SystemMessage::Snap, 100, -1, Some((2, 0)), crc, start);
+        assert_eq!(
+            handle(&mut storage, &part).unwrap(),
+            Some((100, first.clone()))
+        );
+        assert_eq!(storage.ack_tick(), 100);
+
+        let mut second = first.clone();
+        second.insert(5, 0, vec![330, 600, 0]);
+        let data = pack_ints(&first.create_delta(&second));
+        let single = snap_message(
+            SystemMessage::SnapSingle,
+            102,
+            100,
+            None,
+            second.crc(),
+            &data,
+        );
+        assert_eq!(
+            handle(&mut storage, &single).unwrap(),
+            Some((102, second.clone()))
+        );
+
+        let empty = snap_message(SystemMessage::SnapEmpty, 104, 102, None, 0, &[]);
+        assert_eq!(
+            handle(&mut storage, &empty).unwrap(),
+            Some((104, second.clone()))
+        );
+        assert_eq!(storage.latest().map(|x| x.0), Some(104));
+
+        // Snapshots older than the last base are gone.
+        let stale = snap_message(SystemMessage::SnapEmpty, 106, 100, None, 0, &[]);
+        assert!(handle(&mut storage, &stale).is_err());
+        assert_eq!(storage.ack_tick(), -1);
+
+        let wrong = snap_message(SystemMessage::SnapSingle, 108, 104, None, 1, &data);
+        assert!(handle(&mut storage, &wrong).is_err());
+
+        let other = pack_system_message(SystemMessage::Ping);
+        assert_eq!(handle(&mut storage, &other).unwrap(), None);
+    }
+}
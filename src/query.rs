@@ -0,0 +1,783 @@
+//! Socket-free state machines for the connless requests.
+//!
+//! A query tells what datagrams to send through [Query::poll_transmit], gets fed the
+//! datagrams received with [Query::handle_datagram] and reports its progress through
+//! [Query::poll]. This lets the requests run on any transport, [drive] runs them on a
+//! blocking [UdpSocket].
+
+use byteorder::{BigEndian, ReadBytesExt};
+use std::collections::{BTreeMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::browser::*;
+use crate::decoder::*;
+use crate::errors::*;
+use crate::net::*;
+use crate::server::*;
+use crate::util::*;
+
+/// The timeout used by the blocking functions when the socket has no read timeout.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The biggest datagram a server sends.
+pub const MAX_DATAGRAM_SIZE: usize = 1400;
+
+/// A datagram to send.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transmit {
+    pub destination: SocketAddr,
+    pub contents: Vec<u8>,
+}
+
+/// The progress of a query.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryStatus {
+    /// Waiting for more datagrams.
+    Pending,
+    /// Everything expected was received.
+    Complete,
+    /// The timeout passed, what was received so far can still be used.
+    TimedOut,
+}
+
+/// A request driven by its user, who does the actual sending and receiving.
+pub trait Query {
+    /// Returns the next datagram to send, if any.
+    fn poll_transmit(&mut self, now: Instant) -> Option<Transmit>;
+
+    /// Feeds a received datagram.
+    ///
+    /// Datagrams not meant for this query return an error and are otherwise ignored.
+    fn handle_datagram(&mut self, from: SocketAddr, data: &[u8], now: Instant) -> Result<()>;
+
+    /// Returns the current status, checking the timeout against `now`.
+    fn poll(&mut self, now: Instant) -> QueryStatus;
+
+    /// When the query times out, if it's waiting for anything.
+    fn poll_timeout(&self) -> Option<Instant>;
+}
+
+/// Server info request.
+///
+/// Completes once the main packet and enough `iex+` packets for every client arrived.
+#[derive(Debug)]
+pub struct InfoQuery {
+    address: SocketAddr,
+    timeout: Duration,
+    token: u8,
+    extra_token: u16,
+    request: Option<Vec<u8>>,
+    sent_at: Option<Instant>,
+    queried_at: Option<SystemTime>,
+    ping: Option<Duration>,
+    deadline: Option<Instant>,
+    main: Option<Vec<u8>>,
+    more: BTreeMap<i32, Vec<u8>>,
+    expected_players: Option<usize>,
+    received_players: usize,
+    legacy: bool,
+    decoders: Option<Arc<InfoDecoders>>,
+}
+
+impl InfoQuery {
+    /// Creates a request for the server at the given address, with random tokens.
+    pub fn new(address: SocketAddr, timeout: Duration) -> InfoQuery {
+        let (buf, extra_token, token) = create_packet(PacketType::GetInfo, Some(b"xe"), true);
+        let token = token.expect("token should always have value here.");
+
+        log::debug!("generated extra_token={}, token={}", extra_token, token);
+
+        InfoQuery {
+            address,
+            timeout,
+            token,
+            extra_token,
+            request: Some(buf.to_vec()),
+            sent_at: None,
+            queried_at: None,
+            ping: None,
+            deadline: None,
+            main: None,
+            more: BTreeMap::new(),
+            expected_players: None,
+            received_players: 0,
+            legacy: false,
+            decoders: None,
+        }
+    }
+
+    /// Creates a query for a request already sent by someone else with the given tokens,
+    /// like a broadcast answered by several servers.
+    pub(crate) fn sent(
+        address: SocketAddr,
+        deadline: Instant,
+        token: u8,
+        extra_token: u16,
+        sent_at: Instant,
+        queried_at: SystemTime,
+    ) -> InfoQuery {
+        InfoQuery {
+            address,
+            timeout: deadline.saturating_duration_since(sent_at),
+            token,
+            extra_token,
+            request: None,
+            sent_at: Some(sent_at),
+            queried_at: Some(queried_at),
+            ping: None,
+            deadline: Some(deadline),
+            main: None,
+            more: BTreeMap::new(),
+            expected_players: None,
+            received_players: 0,
+            legacy: false,
+            decoders: None,
+        }
+    }
+
+    /// Runs the decoders on the info once it's received.
+    pub fn with_decoders(mut self, decoders: Arc<InfoDecoders>) -> InfoQuery {
+        self.decoders = Some(decoders);
+        self
+    }
+
+    /// The address of the server queried.
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// The time between sending the request and getting the first response.
+    pub fn ping(&self) -> Option<Duration> {
+        self.ping
+    }
+
+    /// When the request was sent.
+    pub fn queried_at(&self) -> Option<SystemTime> {
+        self.queried_at
+    }
+
+    /// Number of datagrams accepted so far.
+    pub fn received(&self) -> usize {
+        self.main.iter().count() + self.more.len()
+    }
+
+    fn check_token(&self, token: i32) -> Result<()> {
+        let received_token = (token & 0xff) as u8;
+        let received_extra_token = ((token >> 8) & 0xffff) as u16;
+
+        // Vanilla servers only echo back the single byte token.
+        if received_token != self.token
+            || (received_extra_token != 0 && received_extra_token != self.extra_token)
+        {
+            return Err(RequestError::TokenError {
+                wanted_extra_token: self.extra_token,
+                wanted_token: self.token,
+                received_extra_token,
+                received_token,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn is_complete(&self) -> bool {
+        match self.expected_players {
+            Some(_) if self.legacy => true,
+            Some(expected) => self.received_players >= expected,
+            None => false,
+        }
+    }
+
+    /// Copies the received datagrams into the buffers and parses them doing zero copy.
+    ///
+    /// Fails if the main packet never arrived. Packets that don't fit the buffers are dropped.
+    ///
+    /// See also [ServerInfo::create_buffers()]
+    pub fn into_info(self, buffers: &mut [Vec<u8>]) -> Result<ServerInfo<'_>> {
+        let main = self.main.ok_or(RequestError::Timeout)?;
+
+        if self.more.len() + 1 > buffers.len() {
+            log::warn!(
+                "{} info packets received but only {} buffers available",
+                self.more.len() + 1,
+                buffers.len()
+            );
+        }
+
+        let mut datagrams = std::iter::once(main).chain(self.more.into_values());
+        let mut lens = Vec::new();
+        for buffer in buffers.iter_mut() {
+            match datagrams.next() {
+                Some(datagram) => {
+                    buffer.clear();
+                    buffer.extend_from_slice(&datagram);
+                    lens.push(datagram.len());
+                }
+                None => break,
+            }
+        }
+
+        let mut iter = buffers.iter().zip(lens);
+        let (main, len) = iter.next().ok_or(RequestError::Missing)?;
+        let mut info = ServerInfo::parse_main(&main[..len])?;
+        for (more, len) in iter {
+            info.parse_more(&more[..len])?;
+        }
+        if let Some(decoders) = &self.decoders {
+            info.decode_extensions(decoders);
+        }
+
+        Ok(info)
+    }
+
+    /// Parses the received datagrams into an owned entry.
+    ///
+    /// Fails if the main packet never arrived.
+    pub fn finish(self) -> Result<ServerEntry<'static>> {
+        let main = self.main.as_ref().ok_or(RequestError::Timeout)?;
+
+        let mut info = ServerInfo::parse_main(main)?.into_owned();
+        for more in self.more.values() {
+            info.players.extend(
+                parse_more_players(more)?
+                    .into_iter()
+                    .map(Player::into_owned),
+            );
+        }
+        if let Some(decoders) = &self.decoders {
+            info.decode_extensions(decoders);
+        }
+
+        Ok(ServerEntry {
+            address: self.address,
+            ping: self.ping.unwrap_or_default(),
+            queried_at: self.queried_at.unwrap_or_else(SystemTime::now),
+            info,
+        })
+    }
+}
+
+impl Query for InfoQuery {
+    fn poll_transmit(&mut self, now: Instant) -> Option<Transmit> {
+        let contents = self.request.take()?;
+        self.sent_at = Some(now);
+        self.queried_at = Some(SystemTime::now());
+        self.deadline = Some(now + self.timeout);
+
+        Some(Transmit {
+            destination: self.address,
+            contents,
+        })
+    }
+
+    fn handle_datagram(&mut self, from: SocketAddr, data: &[u8], now: Instant) -> Result<()> {
+        if from != self.address {
+            return Err(RequestError::InvalidData("datagram from another address"));
+        }
+
+        if data.len() < 14 {
+            return Err(RequestError::InvalidData("datagram too short"));
+        }
+
+        let packet_type = &data[10..14];
+
+        if PacketType::Info == *packet_type || PacketType::InfoExtended == *packet_type {
+            if self.main.is_some() {
+                return Err(RequestError::InvalidData("duplicated info packet"));
+            }
+
+            let info = ServerInfo::parse_main(data)?;
+            self.check_token(info.token)?;
+
+            self.legacy = PacketType::Info == *packet_type;
+            self.expected_players = Some(info.client_count.max(0) as usize);
+            self.received_players += info.players.len();
+            self.main = Some(data.to_vec());
+        } else if PacketType::InfoExtendedMore == *packet_type {
+            let (token, number) = parse_more_header(data)?;
+            self.check_token(token)?;
+
+            if self.more.contains_key(&number) {
+                return Err(RequestError::InvalidData("duplicated info more packet"));
+            }
+
+            self.received_players += parse_more_players(data)?.len();
+            self.more.insert(number, data.to_vec());
+        } else {
+            return Err(RequestError::InvalidData("unexpected packet type"));
+        }
+
+        if self.ping.is_none() {
+            self.ping = self.sent_at.map(|x| now.saturating_duration_since(x));
+        }
+
+        log::debug!(
+            "players parsed={} total_players={:?}",
+            self.received_players,
+            self.expected_players
+        );
+
+        Ok(())
+    }
+
+    fn poll(&mut self, now: Instant) -> QueryStatus {
+        if self.is_complete() {
+            QueryStatus::Complete
+        } else if matches!(self.deadline, Some(x) if now >= x) {
+            QueryStatus::TimedOut
+        } else {
+            QueryStatus::Pending
+        }
+    }
+
+    fn poll_timeout(&self) -> Option<Instant> {
+        if self.is_complete() {
+            None
+        } else {
+            self.deadline
+        }
+    }
+}
+
+/// Queries the info of a server on a socket of its own.
+pub(crate) fn query_info(address: SocketAddr, timeout: Duration) -> Result<ServerInfo<'static>> {
+    let sock = UdpSocket::bind(AddressFamily::of(&address).unspecified())?;
+    let mut query = InfoQuery::new(address, timeout);
+    drive(&sock, &mut query)?;
+    Ok(query.finish()?.info)
+}
+
+/// Master server list request.
+///
+/// Completes once the number of servers announced by the master was received.
+/// Masters don't always announce it, so the list gathered until the timeout is also valid.
+/// The timeout is reset by every datagram received.
+#[derive(Debug)]
+pub struct ListQuery {
+    address: SocketAddr,
+    timeout: Duration,
+    requests: Vec<Vec<u8>>,
+    deadline: Option<Instant>,
+    count: Option<u16>,
+    servers: HashSet<(IpAddr, u16)>,
+}
+
+impl ListQuery {
+    pub fn new(address: SocketAddr, timeout: Duration) -> ListQuery {
+        let (count, _, _) = create_packet(PacketType::GetCount, Some(b"\xff\xff"), false);
+        let (list, _, _) = create_packet(PacketType::GetList, Some(b"\xff\xff"), false);
+
+        ListQuery {
+            address,
+            timeout,
+            // Popped from the end.
+            requests: vec![list.to_vec(), count.to_vec()],
+            deadline: None,
+            count: None,
+            servers: HashSet::new(),
+        }
+    }
+
+    /// The number of servers announced by the master, if received.
+    pub fn count(&self) -> Option<u16> {
+        self.count
+    }
+
+    /// The servers received so far.
+    pub fn servers(&self) -> &HashSet<(IpAddr, u16)> {
+        &self.servers
+    }
+
+    /// Returns the servers received.
+    pub fn finish(self) -> HashSet<(IpAddr, u16)> {
+        self.servers
+    }
+
+    fn is_complete(&self) -> bool {
+        matches!(self.count, Some(count) if self.servers.len() >= count as usize)
+    }
+
+    fn read_list(&mut self, data: &[u8]) -> Result<()> {
+        for entry in data[14..].chunks_exact(18) {
+            let ip = if entry[..12] == b"\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\xff\xff"[..] {
+                IpAddr::V4(Ipv4Addr::new(entry[12], entry[13], entry[14], entry[15]))
+            } else {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(&entry[..16]);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            };
+
+            let port = (&entry[16..18]).read_u16::<BigEndian>()?;
+
+            if port == 0 || ip.is_unspecified() {
+                continue;
+            }
+
+            log::debug!("Adding ip '{}' and port {}", ip, port);
+            self.servers.insert((ip, port));
+        }
+
+        Ok(())
+    }
+}
+
+impl Query for ListQuery {
+    fn poll_transmit(&mut self, now: Instant) -> Option<Transmit> {
+        let contents = self.requests.pop()?;
+        self.deadline = Some(now + self.timeout);
+
+        Some(Transmit {
+            destination: self.address,
+            contents,
+        })
+    }
+
+    fn handle_datagram(&mut self, from: SocketAddr, data: &[u8], now: Instant) -> Result<()> {
+        if from != self.address {
+            return Err(RequestError::InvalidData("datagram from another address"));
+        }
+
+        if data.len() < 14 {
+            return Err(RequestError::InvalidData("datagram too short"));
+        }
+
+        let packet_id = &data[10..14];
+        log::debug!("Received packet with id: {:?}", packet_id);
+
+        if PacketType::Count == *packet_id {
+            let mut val = data.get(14..16).ok_or(RequestError::Missing)?;
+            self.count = Some(val.read_u16::<BigEndian>()?);
+            log::debug!("master server count: {:?}", self.count);
+        } else if PacketType::List == *packet_id {
+            self.read_list(data)?;
+        } else {
+            return Err(RequestError::InvalidData("unexpected packet type"));
+        }
+
+        self.deadline = Some(now + self.timeout);
+        Ok(())
+    }
+
+    fn poll(&mut self, now: Instant) -> QueryStatus {
+        if self.is_complete() {
+            QueryStatus::Complete
+        } else if matches!(self.deadline, Some(x) if now >= x) {
+            QueryStatus::TimedOut
+        } else {
+            QueryStatus::Pending
+        }
+    }
+
+    fn poll_timeout(&self) -> Option<Instant> {
+        if self.is_complete() {
+            None
+        } else {
+            self.deadline
+        }
+    }
+}
+
+/// Runs a query on a blocking socket until it completes or times out.
+///
+/// Connected sockets are supported, as long as they are connected to the address queried.
+/// The socket read timeout is restored afterwards.
+pub fn drive<Q: Query + ?Sized>(sock: &UdpSocket, query: &mut Q) -> Result<QueryStatus> {
+    let read_timeout = sock.read_timeout()?;
+    let result = drive_inner(sock, query);
+    sock.set_read_timeout(read_timeout)?;
+    result
+}
+
+fn drive_inner<Q: Query + ?Sized>(sock: &UdpSocket, query: &mut Q) -> Result<QueryStatus> {
+    let peer = sock.peer_addr().ok();
+    let mut buf = [0; MAX_DATAGRAM_SIZE];
+
+    loop {
+        let now = Instant::now();
+
+        while let Some(transmit) = query.poll_transmit(now) {
+            let sent = if peer == Some(transmit.destination) {
+                sock.send(&transmit.contents)?
+            } else {
+                sock.send_to(&transmit.contents, transmit.destination)?
+            };
+
+            log::debug!("sent {} bytes", sent);
+            if sent != transmit.contents.len() {
+                log::warn!(
+                    "bytes sent ({}) not equal to buffer size ({})!",
+                    sent,
+                    transmit.contents.len()
+                );
+            }
+        }
+
+        match query.poll(now) {
+            QueryStatus::Pending => {}
+            status => return Ok(status),
+        }
+
+        let wait = query
+            .poll_timeout()
+            .map_or(DEFAULT_TIMEOUT, |x| x.saturating_duration_since(now));
+        sock.set_read_timeout(Some(wait.max(Duration::from_millis(1))))?;
+
+        match sock.recv_from(&mut buf) {
+            Ok((len, from)) => {
+                log::debug!("received data size: {}", len);
+                if let Err(e) = query.handle_datagram(from, &buf[..len], Instant::now()) {
+                    log::debug!("ignoring datagram from {}: {}", from, e);
+                }
+            }
+            Err(e)
+                if e.kind() == std::io::ErrorKind::WouldBlock
+                    || e.kind() == std::io::ErrorKind::TimedOut => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::SAMPLES;
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const SAMPLE_TOKEN: u8 = 0xa8;
+    const SAMPLE_EXTRA_TOKEN: u16 = 0x0a09;
+
+    fn sample_query(now: Instant) -> InfoQuery {
+        let mut query = InfoQuery::new("127.0.0.1:8303".parse().unwrap(), Duration::from_secs(1));
+        query.token = SAMPLE_TOKEN;
+        query.extra_token = SAMPLE_EXTRA_TOKEN;
+        query.poll_transmit(now).unwrap();
+        query
+    }
+
+    #[test]
+    fn info_completes() {
+        let now = Instant::now();
+        let mut query = sample_query(now);
+        let from = query.address();
+
+        assert!(query.poll_transmit(now).is_none());
+        assert_eq!(query.poll(now), QueryStatus::Pending);
+
+        // Datagrams may arrive in any order.
+        let first = now + Duration::from_millis(10);
+        query
+            .handle_datagram(from, include_bytes!("samples/server_info_more.data"), first)
+            .unwrap();
+        assert_eq!(query.poll(first), QueryStatus::Pending);
+
+        let later = now + Duration::from_millis(30);
+        query
+            .handle_datagram(from, include_bytes!("samples/server_info.data"), later)
+            .unwrap();
+        assert_eq!(query.poll(later), QueryStatus::Complete);
+        assert_eq!(query.poll_timeout(), None);
+        assert_eq!(query.ping(), Some(Duration::from_millis(10)));
+
+        let mut buffers = ServerInfo::create_buffers();
+        let info = query.into_info(&mut buffers).unwrap();
+        assert_eq!(info.players.len(), 63);
+        assert_eq!(info.map, "Multeasymap");
+        assert!(info.extensions.is_empty());
+    }
+
+    #[test]
+    fn info_runs_decoders() {
+        let mut decoders = InfoDecoders::new();
+        decoders.register(
+            DecoderTarget::Format(InfoFormat::Extended),
+            |info: &ServerInfo<'_>| Some(info.players.len()),
+        );
+
+        let now = Instant::now();
+        let mut query = sample_query(now).with_decoders(Arc::new(decoders));
+        let from = query.address();
+        for sample in &SAMPLES {
+            query.handle_datagram(from, sample, now).unwrap();
+        }
+
+        let queried_at = query.queried_at().unwrap();
+        let entry = query.finish().unwrap();
+        assert_eq!(entry.queried_at, queried_at);
+        assert_eq!(entry.info.extension::<usize>(), Some(&63));
+    }
+
+    #[test]
+    fn info_rejects_and_times_out() {
+        let now = Instant::now();
+        let mut query = sample_query(now);
+        let data = include_bytes!("samples/server_info.data");
+
+        assert!(query
+            .handle_datagram("127.0.0.1:1".parse().unwrap(), data, now)
+            .is_err());
+
+        query.token = 1;
+        assert!(matches!(
+            query.handle_datagram(query.address(), data, now),
+            Err(RequestError::TokenError {
+                wanted_token: 1,
+                received_token: SAMPLE_TOKEN,
+                ..
+            })
+        ));
+        query.token = SAMPLE_TOKEN;
+
+        query.handle_datagram(query.address(), data, now).unwrap();
+        assert!(query.handle_datagram(query.address(), data, now).is_err());
+
+        let later = now + Duration::from_secs(1);
+        assert_eq!(query.poll(later), QueryStatus::TimedOut);
+
+        // The main packet alone is still usable.
+        let entry = query.finish().unwrap();
+        assert_eq!(entry.info.client_count, 63);
+        assert!(entry.info.players.len() < 63);
+
+        let mut query = sample_query(now);
+        assert_eq!(query.poll(later), QueryStatus::TimedOut);
+        assert!(matches!(query.finish(), Err(RequestError::Timeout)));
+    }
+
+    #[test]
+    fn list_completes() {
+        let now = Instant::now();
+        let address = "127.0.0.1:8300".parse().unwrap();
+        let mut query = ListQuery::new(address, Duration::from_secs(1));
+
+        let sent: Vec<_> = std::iter::from_fn(|| query.poll_transmit(now)).collect();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(PacketType::GetCount, sent[0].contents[10..14]);
+        assert_eq!(PacketType::GetList, sent[1].contents[10..14]);
+
+        let mut list = b"\xff\xff\xff\xff\xff\xff\xff\xff\xff\xfflis2".to_vec();
+        list.extend_from_slice(b"\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\xff\xff");
+        list.extend_from_slice(&[10, 0, 0, 1, 0x20, 0x6f]);
+        query.handle_datagram(address, &list, now).unwrap();
+        assert_eq!(query.poll(now), QueryStatus::Pending);
+
+        let later = now + Duration::from_millis(500);
+        let mut count = b"\xff\xff\xff\xff\xff\xff\xff\xff\xff\xffsiz2".to_vec();
+        count.extend_from_slice(&[0, 1]);
+        query.handle_datagram(address, &count, later).unwrap();
+
+        assert_eq!(query.count(), Some(1));
+        assert_eq!(query.poll(later), QueryStatus::Complete);
+        assert_eq!(
+            query.finish().into_iter().collect::<Vec<_>>(),
+            vec![("10.0.0.1".parse().unwrap(), 8303)]
+        );
+    }
+
+    #[test]
+    fn drives_blocking_socket() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap();
+
+        let responder = std::thread::spawn(move || {
+            let mut buf = [0; MAX_DATAGRAM_SIZE];
+            let (_, from) = server.recv_from(&mut buf).unwrap();
+            let mut count = b"\xff\xff\xff\xff\xff\xff\xff\xff\xff\xffsiz2".to_vec();
+            count.extend_from_slice(&[0, 5]);
+            server.send_to(&count, from).unwrap();
+        });
+
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut query = ListQuery::new(address, Duration::from_millis(100));
+        let status = drive(&sock, &mut query).unwrap();
+        responder.join().unwrap();
+
+        assert_eq!(status, QueryStatus::TimedOut);
+        assert_eq!(query.count(), Some(5));
+        assert!(query.servers().is_empty());
+        assert_eq!(sock.read_timeout().unwrap(), None);
+    }
+
+    #[test]
+    fn queries_info() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap();
+        std::thread::spawn(move || testing::serve_samples(&server));
+
+        let info = query_info(address, Duration::from_secs(2)).unwrap();
+        let sample = testing::sample_info();
+        assert_eq!(info.name, sample.name);
+        assert_eq!(info.players, sample.players);
+    }
+}
+
+/// Helpers to stand in for servers in the tests.
+#[cfg(test)]
+pub(crate) mod testing {
+    use std::net::{SocketAddr, UdpSocket};
+
+    use super::MAX_DATAGRAM_SIZE;
+    use crate::server::*;
+    use crate::util::*;
+
+    /// The main and `iex+` replies of a DDNet server with 63 players.
+    pub const SAMPLES: [&[u8]; 2] = [
+        include_bytes!("samples/server_info.data"),
+        include_bytes!("samples/server_info_more.data"),
+    ];
+
+    /// The info of the samples.
+    pub fn sample_info() -> ServerInfo<'static> {
+        let mut info = ServerInfo::parse_main(SAMPLES[0]).unwrap();
+        info.parse_more(SAMPLES[1]).unwrap();
+        info.into_owned()
+    }
+
+    /// Answers the info request with the samples.
+    pub fn send_samples(sock: &UdpSocket, request: &[u8], to: SocketAddr) {
+        for sample in &SAMPLES {
+            sock.send_to(&answer(sample, request), to).unwrap();
+        }
+    }
+
+    /// Answers every info request received with the samples, until the socket fails.
+    pub fn serve_samples(sock: &UdpSocket) {
+        serve_samples_with(sock, |_, _, _| true);
+    }
+
+    /// Like [`serve_samples`], but first hands every datagram to `handle`, which returns
+    /// whether to answer it.
+    pub fn serve_samples_with<F>(sock: &UdpSocket, mut handle: F)
+    where
+        F: FnMut(&UdpSocket, &[u8], SocketAddr) -> bool,
+    {
+        let mut buf = [0; MAX_DATAGRAM_SIZE];
+        while let Ok((len, from)) = sock.recv_from(&mut buf) {
+            let request = &buf[..len];
+            if handle(sock, request, from)
+                && request.len() >= 15
+                && PacketType::GetInfo == request[10..14]
+            {
+                send_samples(sock, request, from);
+            }
+        }
+    }
+
+    /// The token a server puts in its replies to the info request.
+    pub fn request_token(request: &[u8]) -> i32 {
+        request[14] as i32 | (((request[2] as i32) << 8 | request[3] as i32) << 8)
+    }
+
+    /// Rewrites the token of a sample reply to answer the given request.
+    pub fn answer(sample: &[u8], request: &[u8]) -> Vec<u8> {
+        let token = request_token(request);
+        let start = 14;
+        let end = start + sample[start..].iter().position(|&x| x == 0).unwrap();
+
+        let mut reply = sample[..start].to_vec();
+        reply.extend_from_slice(token.to_string().as_bytes());
+        reply.extend_from_slice(&sample[end..]);
+        reply
+    }
+}
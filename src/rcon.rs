@@ -0,0 +1,362 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::errors::*;
+use crate::protocol::*;
+use crate::query::*;
+use crate::session::*;
+
+/// A command the server allows us to run, sent after authenticating.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RconCommand {
+    pub name: String,
+    pub help: String,
+    pub params: String,
+}
+
+/// A remote console client, running over a game connection.
+///
+/// Unlike econ, rcon needs no extra server configuration but a full client
+/// connection, which shows up on the server like a player that never joins the game.
+#[derive(Debug)]
+pub struct Rcon<'a> {
+    sock: &'a UdpSocket,
+    session: ClientSession,
+    timeout: Duration,
+    map: Option<String>,
+    ready: bool,
+    authed: bool,
+    auth_status: Option<bool>,
+    closed: Option<Option<String>>,
+    lines: VecDeque<String>,
+    commands: BTreeMap<String, RconCommand>,
+}
+
+/// Whether a close reason means we got banned.
+fn is_ban(reason: &str) -> bool {
+    reason.to_lowercase().contains("banned")
+}
+
+impl<'a> Rcon<'a> {
+    /// Connects to the server, with its password if it has one, and waits until
+    /// the server is ready to take commands.
+    ///
+    /// Waits for the socket read timeout, or [DEFAULT_TIMEOUT] if it has none,
+    /// for each step.
+    pub fn connect(
+        sock: &'a UdpSocket,
+        address: SocketAddr,
+        password: Option<&str>,
+    ) -> Result<Rcon<'a>> {
+        let timeout = sock.read_timeout()?.unwrap_or(DEFAULT_TIMEOUT);
+        let session = ClientSession::connect(sock, address).map_err(|e| match e {
+            RequestError::Refused(reason) if is_ban(&reason) => RequestError::Banned(reason),
+            e => e,
+        })?;
+
+        let mut rcon = Rcon {
+            sock,
+            session,
+            timeout,
+            map: None,
+            ready: false,
+            authed: false,
+            auth_status: None,
+            closed: None,
+            lines: VecDeque::new(),
+            commands: BTreeMap::new(),
+        };
+
+        let mut info = pack_system_message(SystemMessage::Info);
+        info.add_string(GAME_VERSION)
+            .add_string(password.unwrap_or(""));
+        rcon.send(info)?;
+
+        rcon.wait_for(|x| x.ready)?;
+        log::debug!("rcon connected, map {:?}", rcon.map);
+        Ok(rcon)
+    }
+
+    /// Logs in, `name` is only used by DDNet servers with named keys.
+    ///
+    /// Fails with [RequestError::AuthFailed] on a wrong password, and with
+    /// [RequestError::Banned] if the server banned us for trying too much.
+    pub fn auth(&mut self, name: &str, password: &str) -> Result<()> {
+        let mut auth = pack_system_message(SystemMessage::RconAuth);
+        // Ask for the command list.
+        auth.add_string(name).add_string(password).add_int(1);
+
+        self.auth_status = None;
+        self.send(auth)?;
+        self.wait_for(|x| {
+            x.auth_status.is_some() || x.lines.iter().any(|x| x.starts_with("Wrong password"))
+        })?;
+
+        if self.auth_status == Some(true) {
+            return Ok(());
+        }
+
+        // The failure is told through a console line.
+        let i = self
+            .lines
+            .iter()
+            .position(|x| x.starts_with("Wrong password"));
+        let reason = match i.and_then(|i| self.lines.remove(i)) {
+            Some(line) => line,
+            None => "authentication refused".to_owned(),
+        };
+        Err(RequestError::AuthFailed(reason))
+    }
+
+    /// Whether we are logged in.
+    pub fn is_authed(&self) -> bool {
+        self.authed
+    }
+
+    /// The map the server is running.
+    pub fn map(&self) -> Option<&str> {
+        self.map.as_deref()
+    }
+
+    /// The commands we are allowed to run, as received so far.
+    ///
+    /// DDNet servers send them a few at a time after authenticating.
+    pub fn commands(&self) -> &BTreeMap<String, RconCommand> {
+        &self.commands
+    }
+
+    /// Runs a command, its output comes back through [Rcon::read_line].
+    pub fn execute(&mut self, command: &str) -> Result<()> {
+        if !self.authed {
+            return Err(RequestError::AuthFailed("not authenticated".to_owned()));
+        }
+
+        let mut cmd = pack_system_message(SystemMessage::RconCmd);
+        cmd.add_string(command);
+        self.send(cmd)
+    }
+
+    /// Returns the next console line, waiting for up to `wait` for one.
+    pub fn read_line(&mut self, wait: Duration) -> Result<Option<String>> {
+        if self.lines.is_empty() {
+            match self.wait_for_during(|x| !x.lines.is_empty(), wait) {
+                Err(RequestError::Timeout) => return Ok(None),
+                x => x?,
+            }
+        }
+
+        Ok(self.lines.pop_front())
+    }
+
+    /// Disconnects from the server.
+    pub fn close(mut self) -> Result<()> {
+        self.session.close(None);
+        self.session.pump(self.sock, Duration::from_secs(0))
+    }
+
+    fn send(&mut self, packer: Packer) -> Result<()> {
+        self.session.send(packer.as_bytes(), true)?;
+        self.session.pump(self.sock, Duration::from_secs(0))?;
+        self.handle_events()
+    }
+
+    fn wait_for<F: FnMut(&Self) -> bool>(&mut self, done: F) -> Result<()> {
+        self.wait_for_during(done, self.timeout)
+    }
+
+    fn wait_for_during<F: FnMut(&Self) -> bool>(
+        &mut self,
+        mut done: F,
+        wait: Duration,
+    ) -> Result<()> {
+        let deadline = Instant::now() + wait;
+
+        loop {
+            if done(self) {
+                return Ok(());
+            }
+
+            if let Some(reason) = &self.closed {
+                return Err(match reason {
+                    Some(reason) if is_ban(reason) => RequestError::Banned(reason.clone()),
+                    // Our own timeout.
+                    Some(reason) if reason == "timeout" => RequestError::Timeout,
+                    _ => RequestError::Disconnected,
+                });
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RequestError::Timeout);
+            }
+
+            self.session.pump(self.sock, deadline - now)?;
+            self.handle_events()?;
+        }
+    }
+
+    fn handle_events(&mut self) -> Result<()> {
+        while let Some(event) = self.session.poll_event() {
+            match event {
+                SessionEvent::Chunk { data, .. } => {
+                    if let Err(e) = self.handle_message(&data) {
+                        log::debug!("ignoring malformed message: {}", e);
+                    }
+                }
+                SessionEvent::Closed { reason } => {
+                    log::debug!("rcon connection closed: {:?}", reason);
+                    self.authed = false;
+                    self.closed = Some(reason);
+                }
+                SessionEvent::Connected => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_message(&mut self, data: &[u8]) -> Result<()> {
+        let (id, system, mut unpacker) = unpack_message(data)?;
+        if !system {
+            // Game messages, like the motd.
+            return Ok(());
+        }
+
+        match SystemMessage::from_id(id) {
+            Some(SystemMessage::MapChange) => {
+                self.map = Some(unpacker.get_string()?.to_owned());
+                // We don't need the map to use the console.
+                let ready = pack_system_message(SystemMessage::Ready);
+                self.session.send(ready.as_bytes(), true)?;
+            }
+            Some(SystemMessage::ConReady) => self.ready = true,
+            Some(SystemMessage::RconAuthStatus) => {
+                let authed = unpacker.get_int()? == 1;
+                self.authed = authed;
+                self.auth_status = Some(authed);
+            }
+            Some(SystemMessage::RconLine) => {
+                self.lines.push_back(unpacker.get_string()?.to_owned());
+            }
+            Some(SystemMessage::RconCmdAdd) => {
+                let command = RconCommand {
+                    name: unpacker.get_string()?.to_owned(),
+                    help: unpacker.get_string()?.to_owned(),
+                    params: unpacker.get_string()?.to_owned(),
+                };
+                self.commands.insert(command.name.clone(), command);
+            }
+            Some(SystemMessage::RconCmdRem) => {
+                self.commands.remove(unpacker.get_string()?);
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::testing::*;
+    use pretty_assertions::assert_eq;
+
+    fn line(text: &str) -> Packer {
+        let mut packer = pack_system_message(SystemMessage::RconLine);
+        packer.add_string(text);
+        packer
+    }
+
+    #[test]
+    fn authenticates_and_runs_commands() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap();
+
+        let stand_in = std::thread::spawn(move || {
+            let mut server = StandIn::accept(server);
+            server.get_ready("dm1");
+
+            let auth = server.expect_system(SystemMessage::RconAuth);
+            let mut unpacker = Unpacker::new(&auth);
+            assert_eq!(unpacker.get_string().unwrap(), "");
+            assert_eq!(unpacker.get_string().unwrap(), "guess");
+            assert_eq!(unpacker.get_int().unwrap(), 1);
+            server.send(&line("Wrong password."));
+
+            server.expect_system(SystemMessage::RconAuth);
+            let mut status = pack_system_message(SystemMessage::RconAuthStatus);
+            status.add_int(1).add_int(1);
+            server.send(&status);
+            server.send(&line(
+                "Admin authentication successful. Full remote console access granted.",
+            ));
+            let mut add = pack_system_message(SystemMessage::RconCmdAdd);
+            add.add_string("status")
+                .add_string("List players")
+                .add_string("");
+            server.send(&add);
+
+            let cmd = server.expect_system(SystemMessage::RconCmd);
+            assert_eq!(Unpacker::new(&cmd).get_string().unwrap(), "status");
+            server.send(&line("id=0 addr=127.0.0.1:1234 name='tee' score=0"));
+        });
+
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sock.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let mut rcon = Rcon::connect(&sock, address, None).unwrap();
+        assert_eq!(rcon.map(), Some("dm1"));
+
+        assert!(rcon.execute("shutdown").is_err());
+        match rcon.auth("", "guess") {
+            Err(RequestError::AuthFailed(reason)) => assert_eq!(reason, "Wrong password."),
+            x => panic!("unexpected result: {:?}", x),
+        }
+
+        rcon.auth("", "secret").unwrap();
+        assert!(rcon.is_authed());
+        rcon.execute("status").unwrap();
+
+        let wait = Duration::from_secs(2);
+        assert_eq!(
+            rcon.read_line(wait).unwrap().unwrap(),
+            "Admin authentication successful. Full remote console access granted."
+        );
+        assert_eq!(
+            rcon.read_line(wait).unwrap().unwrap(),
+            "id=0 addr=127.0.0.1:1234 name='tee' score=0"
+        );
+        stand_in.join().unwrap();
+
+        assert_eq!(
+            rcon.commands().get("status").map(|x| &*x.help),
+            Some("List players")
+        );
+        assert_eq!(rcon.read_line(Duration::from_millis(50)).unwrap(), None);
+        rcon.close().unwrap();
+    }
+
+    #[test]
+    fn reports_bans() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap();
+
+        let stand_in = std::thread::spawn(move || {
+            let mut server = StandIn::accept(server);
+            server.get_ready("dm1");
+            server.expect_system(SystemMessage::RconAuth);
+            server.close(
+                "You have been banned for 5 minutes (Too many remote console authentication tries)",
+            );
+        });
+
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sock.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let mut rcon = Rcon::connect(&sock, address, Some("server password")).unwrap();
+        let result = rcon.auth("", "guess");
+        stand_in.join().unwrap();
+
+        assert!(matches!(result, Err(RequestError::Banned(reason)) if reason.contains("Too many")));
+    }
+}
@@ -0,0 +1,542 @@
+//! Answering info requests in place of a server.
+//!
+//! An [InfoResponder] answers the requests from a copy of the server info, echoing the
+//! token of each requester. An [InfoRelay] keeps that copy fresh and sits in front of the
+//! server, so floods of info requests never reach it while the players still can.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::errors::*;
+use crate::net::*;
+use crate::query::*;
+use crate::server::*;
+use crate::util::*;
+
+/// The size of an info request, up to and including the token.
+const INFO_REQUEST_SIZE: usize = 15;
+
+/// How many refreshes can fail before the info is dropped, so a dead backend isn't
+/// advertised as up.
+const STALE_REFRESHES: u32 = 3;
+
+/// How long the forwarding thread first sleeps when no client socket has data.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// How long the forwarding thread sleeps at most, doubling the sleep while nothing is
+/// received. Forwarding a datagram to the backend wakes it up at once.
+const MAX_POLL_INTERVAL: Duration = Duration::from_millis(64);
+
+/// Answers info requests from a cached server info.
+///
+/// The requests answered each second are limited, like DDNet's `sv_server_info_per_second`,
+/// so forged requests can't make the responder flood someone else.
+#[derive(Debug, Clone)]
+pub struct InfoResponder {
+    /// How many requests are answered each second, the others are dropped.
+    pub max_per_second: u32,
+    info: Option<ServerInfo<'static>>,
+    window_start: Option<Instant>,
+    answered: u32,
+}
+
+impl InfoResponder {
+    /// Creates a responder with no info yet, ignoring the requests until it gets one, and
+    /// answering up to 50 requests a second.
+    pub fn new() -> InfoResponder {
+        InfoResponder {
+            max_per_second: 50,
+            info: None,
+            window_start: None,
+            answered: 0,
+        }
+    }
+
+    pub fn info(&self) -> Option<&ServerInfo<'static>> {
+        self.info.as_ref()
+    }
+
+    /// Replaces the info given to the requesters.
+    pub fn set_info(&mut self, info: ServerInfo<'static>) {
+        self.info = Some(info);
+    }
+
+    /// Drops the info, requests get no answer until there is a new one.
+    pub fn clear(&mut self) {
+        self.info = None;
+    }
+
+    /// Whether the datagram is an info request.
+    pub fn is_info_request(data: &[u8]) -> bool {
+        data.len() >= INFO_REQUEST_SIZE && PacketType::GetInfo == data[10..14]
+    }
+
+    /// Returns the datagrams answering the request received at `now`, or `None` if it's
+    /// not an info request.
+    ///
+    /// Requests carrying the DDNet extended token get the `iext` packets, the others the
+    /// vanilla packet. Until there is an info, and past the requests allowed this second,
+    /// requests get no answer.
+    pub fn respond(&mut self, request: &[u8], now: Instant) -> Option<Vec<Vec<u8>>> {
+        if !InfoResponder::is_info_request(request) {
+            return None;
+        }
+
+        let info = match &self.info {
+            Some(info) => info,
+            None => return Some(Vec::new()),
+        };
+
+        match self.window_start {
+            Some(start) if now.saturating_duration_since(start) < Duration::from_secs(1) => {}
+            _ => {
+                self.window_start = Some(now);
+                self.answered = 0;
+            }
+        }
+        if self.answered >= self.max_per_second {
+            return Some(Vec::new());
+        }
+        self.answered += 1;
+
+        let token = request[14] as i32;
+        if &request[..2] == b"xe" {
+            let extra_token = ((request[2] as i32) << 8) | request[3] as i32;
+            Some(info.to_packets(token | (extra_token << 8)))
+        } else {
+            Some(vec![info.to_legacy_packet(token)])
+        }
+    }
+}
+
+/// A relay answering info requests for a backend server, from an info fetched every
+/// `refresh`. Once the backend missed a few refreshes, the requests go unanswered.
+///
+/// The rest of the traffic is forwarded to the backend from a socket for each client,
+/// unless forwarding is disabled, in which case it's dropped. A single thread polls
+/// these sockets, backing off while they're quiet, and past `max_clients` the least
+/// active client is forgotten, so spoofed source addresses can't exhaust the sockets.
+#[derive(Debug, Clone)]
+pub struct InfoRelay {
+    /// The server to relay.
+    pub backend: SocketAddr,
+    /// How often the info of the backend is fetched.
+    pub refresh: Duration,
+    /// How long to wait for the backend to answer an info request.
+    pub timeout: Duration,
+    /// Whether to forward the game traffic.
+    pub forward: bool,
+    /// How long a client can go without traffic either way before it's forgotten.
+    pub idle_timeout: Duration,
+    /// How many clients get their traffic forwarded at once.
+    pub max_clients: usize,
+    /// How many info requests are answered each second.
+    pub max_info_per_second: u32,
+}
+
+/// The socket forwarding the traffic of a client.
+#[derive(Debug)]
+struct Upstream {
+    sock: UdpSocket,
+    last_active: Instant,
+}
+
+/// The clients whose traffic is forwarded, by address.
+#[derive(Debug)]
+struct Clients {
+    backend: SocketAddr,
+    max_clients: usize,
+    idle_timeout: Duration,
+    upstreams: HashMap<SocketAddr, Upstream>,
+}
+
+impl Clients {
+    fn new(relay: &InfoRelay) -> Clients {
+        Clients {
+            backend: relay.backend,
+            max_clients: relay.max_clients,
+            idle_timeout: relay.idle_timeout,
+            upstreams: HashMap::new(),
+        }
+    }
+
+    /// Returns the socket forwarding the traffic of the client, opening it if needed.
+    ///
+    /// Past the maximum, the least active client is forgotten to make room.
+    fn upstream(&mut self, client: SocketAddr, now: Instant) -> Result<&UdpSocket> {
+        if !self.upstreams.contains_key(&client) {
+            if self.upstreams.len() >= self.max_clients.max(1) {
+                let oldest = self
+                    .upstreams
+                    .iter()
+                    .min_by_key(|(_, x)| x.last_active)
+                    .map(|(address, _)| *address);
+                if let Some(oldest) = oldest {
+                    log::debug!("too many clients, forgetting {}", oldest);
+                    self.upstreams.remove(&oldest);
+                }
+            }
+
+            let sock = UdpSocket::bind(AddressFamily::of(&self.backend).unspecified())?;
+            sock.connect(self.backend)?;
+            sock.set_nonblocking(true)?;
+            self.upstreams.insert(
+                client,
+                Upstream {
+                    sock,
+                    last_active: now,
+                },
+            );
+        }
+
+        let upstream = self.upstreams.get_mut(&client).unwrap();
+        upstream.last_active = now;
+        Ok(&upstream.sock)
+    }
+
+    /// Sends the replies of the backend back to the clients, and forgets the idle ones.
+    ///
+    /// Returns whether any reply was received.
+    fn poll(&mut self, sock: &UdpSocket, now: Instant) -> bool {
+        let idle_timeout = self.idle_timeout;
+        self.upstreams.retain(|client, x| {
+            let idle = now.saturating_duration_since(x.last_active) >= idle_timeout;
+            if idle {
+                log::debug!("forgetting client {}", client);
+            }
+            !idle
+        });
+
+        let mut received = false;
+        let mut buf = [0; MAX_DATAGRAM_SIZE];
+        for (client, upstream) in self.upstreams.iter_mut() {
+            loop {
+                match upstream.sock.recv(&mut buf) {
+                    Ok(len) => {
+                        received = true;
+                        upstream.last_active = now;
+                        if let Err(e) = sock.send_to(&buf[..len], client) {
+                            log::debug!("answering {} failed: {}", client, e);
+                        }
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) => {
+                        log::debug!("receiving for {} failed: {}", client, e);
+                        break;
+                    }
+                }
+            }
+        }
+        received
+    }
+}
+
+impl Default for InfoResponder {
+    fn default() -> InfoResponder {
+        InfoResponder::new()
+    }
+}
+
+impl InfoRelay {
+    /// Creates a relay for the server at the given address, fetching its info every five
+    /// seconds and forwarding the game traffic.
+    pub fn new(backend: SocketAddr) -> InfoRelay {
+        InfoRelay {
+            backend,
+            refresh: Duration::from_secs(5),
+            timeout: DEFAULT_TIMEOUT,
+            forward: true,
+            idle_timeout: Duration::from_secs(30),
+            max_clients: 256,
+            max_info_per_second: 50,
+        }
+    }
+
+    /// Relays the datagrams received on the socket, until it fails.
+    ///
+    /// The info is fetched and the replies of the backend are forwarded on two other
+    /// threads, which stop along with the relay.
+    pub fn run(&self, sock: &UdpSocket) -> Result<()> {
+        let mut responder = InfoResponder::new();
+        responder.max_per_second = self.max_info_per_second;
+        let responder = Arc::new(RwLock::new(responder));
+
+        let shared = Arc::downgrade(&responder);
+        let (backend, refresh, timeout) = (self.backend, self.refresh, self.timeout);
+        thread::spawn(move || {
+            let mut fetched_at: Option<Instant> = None;
+            loop {
+                let result = query_info(backend, timeout);
+                let responder = match shared.upgrade() {
+                    Some(responder) => responder,
+                    None => break,
+                };
+                let mut responder = responder.write().unwrap_or_else(|e| e.into_inner());
+
+                match result {
+                    Ok(info) => {
+                        fetched_at = Some(Instant::now());
+                        responder.set_info(info);
+                    }
+                    Err(e) => {
+                        log::warn!("fetching the info of {} failed: {}", backend, e);
+                        let stale = match fetched_at {
+                            Some(x) => x.elapsed() > refresh * STALE_REFRESHES,
+                            None => true,
+                        };
+                        if stale && responder.info().is_some() {
+                            log::warn!("{} seems down, not answering for it", backend);
+                            responder.clear();
+                        }
+                    }
+                }
+                drop(responder);
+
+                thread::sleep(refresh);
+            }
+        });
+
+        let forwarding = Arc::new((Mutex::new(Clients::new(self)), Condvar::new()));
+        if self.forward {
+            let shared = Arc::downgrade(&forwarding);
+            let sender = sock.try_clone()?;
+            thread::spawn(move || {
+                let mut interval = POLL_INTERVAL;
+                loop {
+                    let forwarding = match shared.upgrade() {
+                        Some(forwarding) => forwarding,
+                        None => break,
+                    };
+                    let (clients, wake) = &*forwarding;
+                    let mut clients = clients.lock().unwrap_or_else(|e| e.into_inner());
+                    if clients.poll(&sender, Instant::now()) {
+                        interval = POLL_INTERVAL;
+                        continue;
+                    }
+
+                    let (clients, waited) = wake
+                        .wait_timeout(clients, interval)
+                        .unwrap_or_else(|e| e.into_inner());
+                    drop(clients);
+                    interval = if waited.timed_out() {
+                        (interval * 2).min(MAX_POLL_INTERVAL)
+                    } else {
+                        POLL_INTERVAL
+                    };
+                }
+            });
+        }
+
+        let mut buf = [0; MAX_DATAGRAM_SIZE];
+
+        loop {
+            let (len, from) = sock.recv_from(&mut buf)?;
+            let data = &buf[..len];
+
+            let replies = responder
+                .write()
+                .unwrap_or_else(|e| e.into_inner())
+                .respond(data, Instant::now());
+            if let Some(replies) = replies {
+                for reply in replies {
+                    if let Err(e) = sock.send_to(&reply, from) {
+                        log::debug!("answering {} failed: {}", from, e);
+                    }
+                }
+                continue;
+            }
+
+            if !self.forward {
+                continue;
+            }
+
+            let (clients, wake) = &*forwarding;
+            let mut clients = clients.lock().unwrap_or_else(|e| e.into_inner());
+            let result = clients
+                .upstream(from, Instant::now())
+                .and_then(|x| Ok(x.send(data)?));
+            if let Err(e) = result {
+                log::debug!("forwarding from {} failed: {}", from, e);
+            }
+            // The backend is likely to answer soon.
+            wake.notify_one();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::testing::*;
+    use pretty_assertions::assert_eq;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn echoes_tokens() {
+        let now = Instant::now();
+        let mut responder = InfoResponder::new();
+        let (request, extra_token, token) = create_packet(PacketType::GetInfo, Some(b"xe"), true);
+        assert_eq!(responder.respond(&request, now), Some(Vec::new()));
+        assert_eq!(responder.respond(b"\x10\x00\x00\x01", now), None);
+
+        responder.set_info(sample_info());
+        let replies = responder.respond(&request, now).unwrap();
+        let info = ServerInfo::parse_main(&replies[0]).unwrap();
+        assert_eq!(
+            info.token,
+            token.unwrap() as i32 | (extra_token as i32) << 8
+        );
+        assert_eq!(info.map, "Multeasymap");
+
+        let request = [&[0xff; 10][..], b"gie3", &[42]].concat();
+        let replies = responder.respond(&request, now).unwrap();
+        assert_eq!(replies.len(), 1);
+        assert_eq!(PacketType::Info, replies[0][10..14]);
+        assert!(replies[0][14..].starts_with(b"42\0"));
+
+        responder.clear();
+        assert_eq!(responder.respond(&request, now), Some(Vec::new()));
+    }
+
+    #[test]
+    fn limits_answers() {
+        let now = Instant::now();
+        let mut responder = InfoResponder::new();
+        responder.max_per_second = 10;
+        responder.set_info(sample_info());
+        let request = [&[0xff; 10][..], b"gie3", &[42]].concat();
+
+        let answered = (0..100)
+            .filter(|_| !responder.respond(&request, now).unwrap().is_empty())
+            .count();
+        assert_eq!(answered, 10);
+        assert_eq!(
+            responder.respond(&request, now + Duration::from_millis(999)),
+            Some(Vec::new())
+        );
+
+        // A new second, a new budget.
+        let later = now + Duration::from_secs(1);
+        assert_eq!(responder.respond(&request, later).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn relays_a_server() {
+        // The backend answers info requests and echoes everything else.
+        let backend = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let backend_address = backend.local_addr().unwrap();
+        let info_requests = Arc::new(AtomicUsize::new(0));
+        let counter = info_requests.clone();
+        thread::spawn(move || {
+            serve_samples_with(&backend, |backend, request, from| {
+                if InfoResponder::is_info_request(request) {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    true
+                } else {
+                    backend.send_to(request, from).unwrap();
+                    false
+                }
+            })
+        });
+
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = sock.local_addr().unwrap();
+        let mut relay = InfoRelay::new(backend_address);
+        relay.refresh = Duration::from_secs(60);
+        thread::spawn(move || relay.run(&sock));
+
+        // Wait for the relay to fetch the info.
+        while info_requests.load(Ordering::SeqCst) == 0 {
+            thread::sleep(Duration::from_millis(10));
+        }
+        thread::sleep(Duration::from_millis(50));
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        for _ in 0..3 {
+            let mut query = InfoQuery::new(address, Duration::from_secs(2));
+            drive(&client, &mut query).unwrap();
+            assert_eq!(query.finish().unwrap().info.players.len(), 63);
+        }
+        assert_eq!(info_requests.load(Ordering::SeqCst), 1);
+
+        client.send_to(b"\x10\x00\x00\x01hello", address).unwrap();
+        let mut buf = [0; MAX_DATAGRAM_SIZE];
+        let (len, from) = client.recv_from(&mut buf).unwrap();
+        assert_eq!(from, address);
+        assert_eq!(&buf[..len], b"\x10\x00\x00\x01hello");
+    }
+
+    #[test]
+    fn forgets_dead_backends() {
+        // The backend answers the first info request, then dies.
+        let backend = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let backend_address = backend.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut first = true;
+            serve_samples_with(&backend, |_, _, _| std::mem::replace(&mut first, false))
+        });
+
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = sock.local_addr().unwrap();
+        let mut relay = InfoRelay::new(backend_address);
+        relay.refresh = Duration::from_millis(50);
+        relay.timeout = Duration::from_millis(50);
+        relay.forward = false;
+        thread::spawn(move || relay.run(&sock));
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let query = || {
+            let mut query = InfoQuery::new(address, Duration::from_millis(100));
+            drive(&client, &mut query).and_then(|_| query.finish())
+        };
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while query().is_err() {
+            assert!(Instant::now() < deadline, "the relay never got the info");
+        }
+        while query().is_ok() {
+            assert!(Instant::now() < deadline, "the relay kept the info");
+        }
+    }
+
+    #[test]
+    fn caps_clients() {
+        let backend = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut relay = InfoRelay::new(backend.local_addr().unwrap());
+        relay.max_clients = 2;
+        relay.idle_timeout = Duration::from_secs(10);
+        let mut clients = Clients::new(&relay);
+
+        let now = Instant::now();
+        let address = |port| SocketAddr::from(([127, 0, 0, 1], port));
+        clients.upstream(address(1), now).unwrap();
+        clients
+            .upstream(address(2), now + Duration::from_secs(1))
+            .unwrap();
+        clients
+            .upstream(address(1), now + Duration::from_secs(2))
+            .unwrap();
+
+        // The least active client makes room.
+        clients
+            .upstream(address(3), now + Duration::from_secs(3))
+            .unwrap();
+        assert_eq!(clients.upstreams.len(), 2);
+        assert!(clients.upstreams.contains_key(&address(1)));
+        assert!(!clients.upstreams.contains_key(&address(2)));
+
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert!(!clients.poll(&sock, now + Duration::from_secs(4)));
+        assert_eq!(clients.upstreams.len(), 2);
+        clients.poll(&sock, now + Duration::from_secs(12));
+        assert_eq!(clients.upstreams.len(), 1);
+        clients.poll(&sock, now + Duration::from_secs(13));
+        assert!(clients.upstreams.is_empty());
+    }
+}
@@ -0,0 +1,498 @@
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::browser::*;
+use crate::decoder::*;
+use crate::errors::*;
+use crate::server::*;
+
+const MAGIC: &[u8; 6] = b"TSSCAN";
+const FORMAT_VERSION: u8 = 1;
+
+/// The servers listed by a master server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MasterList {
+    /// The master server, as `hostname:port`.
+    pub master: String,
+    pub servers: Vec<SocketAddr>,
+}
+
+/// The result of a full scan: the master server lists and the info of every server.
+///
+/// Scans can be saved to disk and loaded back to compare them later, see [Scan::diff].
+#[derive(Debug, Clone)]
+pub struct Scan {
+    pub taken_at: SystemTime,
+    pub masters: Vec<MasterList>,
+    pub servers: Vec<ServerEntry<'static>>,
+}
+
+/// A change in the population of a server between two scans.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PopulationChange {
+    pub address: SocketAddr,
+    pub clients_before: i32,
+    pub clients_after: i32,
+    pub players_before: i32,
+    pub players_after: i32,
+    /// Names of the players only found in the newer scan.
+    pub joined: Vec<String>,
+    /// Names of the players only found in the older scan.
+    pub left: Vec<String>,
+}
+
+/// The differences between two scans.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanDiff {
+    /// Servers only found in the newer scan.
+    pub added: Vec<SocketAddr>,
+    /// Servers only found in the older scan.
+    pub removed: Vec<SocketAddr>,
+    /// Servers found in both scans whose population changed.
+    pub changed: Vec<PopulationChange>,
+}
+
+impl Scan {
+    /// Creates an empty scan taken now.
+    pub fn new() -> Scan {
+        Scan {
+            taken_at: SystemTime::now(),
+            masters: Vec::new(),
+            servers: Vec::new(),
+        }
+    }
+
+    /// Writes the scan in the versioned binary format.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_u8(FORMAT_VERSION)?;
+        write_time(w, self.taken_at)?;
+
+        w.write_u32::<BigEndian>(self.masters.len() as u32)?;
+        for list in &self.masters {
+            write_str(w, &list.master)?;
+            w.write_u32::<BigEndian>(list.servers.len() as u32)?;
+            for address in &list.servers {
+                write_address(w, address)?;
+            }
+        }
+
+        w.write_u32::<BigEndian>(self.servers.len() as u32)?;
+        for entry in &self.servers {
+            write_entry(w, entry)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads a scan written by [Scan::write_to].
+    pub fn read_from<R: Read>(r: &mut R) -> Result<Scan> {
+        let mut magic = [0; 6];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(RequestError::InvalidData("not a scan file"));
+        }
+
+        if r.read_u8()? != FORMAT_VERSION {
+            return Err(RequestError::InvalidData("unsupported scan format version"));
+        }
+
+        let taken_at = read_time(r)?;
+
+        let count = r.read_u32::<BigEndian>()?;
+        let mut masters = Vec::new();
+        for _ in 0..count {
+            let master = read_str(r)?;
+            let count = r.read_u32::<BigEndian>()?;
+            let mut servers = Vec::new();
+            for _ in 0..count {
+                servers.push(read_address(r)?);
+            }
+            masters.push(MasterList { master, servers });
+        }
+
+        let count = r.read_u32::<BigEndian>()?;
+        let mut servers = Vec::new();
+        for _ in 0..count {
+            servers.push(read_entry(r)?);
+        }
+
+        Ok(Scan {
+            taken_at,
+            masters,
+            servers,
+        })
+    }
+
+    /// Saves the scan to a file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write_to(&mut w)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Loads a scan from a file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Scan> {
+        Scan::read_from(&mut BufReader::new(File::open(path)?))
+    }
+
+    /// Compares this scan with a newer one.
+    pub fn diff(&self, newer: &Scan) -> ScanDiff {
+        let old: HashMap<SocketAddr, &ServerInfo> =
+            self.servers.iter().map(|x| (x.address, &x.info)).collect();
+        let new: HashMap<SocketAddr, &ServerInfo> =
+            newer.servers.iter().map(|x| (x.address, &x.info)).collect();
+
+        let mut diff = ScanDiff::default();
+
+        for entry in &newer.servers {
+            let after = &entry.info;
+            let before = match old.get(&entry.address) {
+                Some(before) => before,
+                None => {
+                    diff.added.push(entry.address);
+                    continue;
+                }
+            };
+
+            let names_before: HashSet<&str> = before.players.iter().map(|x| &*x.name).collect();
+            let names_after: HashSet<&str> = after.players.iter().map(|x| &*x.name).collect();
+
+            let joined: Vec<String> = after
+                .players
+                .iter()
+                .filter(|x| !names_before.contains(&*x.name))
+                .map(|x| x.name.to_string())
+                .collect();
+            let left: Vec<String> = before
+                .players
+                .iter()
+                .filter(|x| !names_after.contains(&*x.name))
+                .map(|x| x.name.to_string())
+                .collect();
+
+            if before.client_count != after.client_count
+                || before.player_count != after.player_count
+                || !joined.is_empty()
+                || !left.is_empty()
+            {
+                diff.changed.push(PopulationChange {
+                    address: entry.address,
+                    clients_before: before.client_count,
+                    clients_after: after.client_count,
+                    players_before: before.player_count,
+                    players_after: after.player_count,
+                    joined,
+                    left,
+                });
+            }
+        }
+
+        diff.removed = self
+            .servers
+            .iter()
+            .map(|x| x.address)
+            .filter(|x| !new.contains_key(x))
+            .collect();
+
+        diff
+    }
+}
+
+impl Default for Scan {
+    fn default() -> Scan {
+        Scan::new()
+    }
+}
+
+fn write_str<W: Write>(w: &mut W, s: &str) -> Result<()> {
+    if s.len() > u16::MAX as usize {
+        return Err(RequestError::InvalidData("string too long"));
+    }
+    w.write_u16::<BigEndian>(s.len() as u16)?;
+    w.write_all(s.as_bytes())?;
+    Ok(())
+}
+
+fn read_str<R: Read>(r: &mut R) -> Result<String> {
+    let len = r.read_u16::<BigEndian>()?;
+    let mut buf = vec![0; len as usize];
+    r.read_exact(&mut buf)?;
+    Ok(std::str::from_utf8(&buf)?.to_owned())
+}
+
+fn write_time<W: Write>(w: &mut W, time: SystemTime) -> Result<()> {
+    let millis = time
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_millis() as u64)
+        .unwrap_or(0);
+    w.write_u64::<BigEndian>(millis)?;
+    Ok(())
+}
+
+fn read_time<R: Read>(r: &mut R) -> Result<SystemTime> {
+    Ok(UNIX_EPOCH + Duration::from_millis(r.read_u64::<BigEndian>()?))
+}
+
+fn write_address<W: Write>(w: &mut W, address: &SocketAddr) -> Result<()> {
+    match address.ip() {
+        IpAddr::V4(ip) => {
+            w.write_u8(4)?;
+            w.write_all(&ip.octets())?;
+        }
+        IpAddr::V6(ip) => {
+            w.write_u8(6)?;
+            w.write_all(&ip.octets())?;
+        }
+    }
+    w.write_u16::<BigEndian>(address.port())?;
+    Ok(())
+}
+
+fn read_address<R: Read>(r: &mut R) -> Result<SocketAddr> {
+    let ip = match r.read_u8()? {
+        4 => {
+            let mut octets = [0; 4];
+            r.read_exact(&mut octets)?;
+            IpAddr::V4(Ipv4Addr::from(octets))
+        }
+        6 => {
+            let mut octets = [0; 16];
+            r.read_exact(&mut octets)?;
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return Err(RequestError::InvalidData("unknown address family")),
+    };
+    Ok(SocketAddr::new(ip, r.read_u16::<BigEndian>()?))
+}
+
+fn write_opt_int<W: Write>(w: &mut W, value: Option<i32>) -> Result<()> {
+    match value {
+        Some(value) => {
+            w.write_u8(1)?;
+            w.write_i32::<BigEndian>(value)?;
+        }
+        None => w.write_u8(0)?,
+    }
+    Ok(())
+}
+
+fn read_opt_int<R: Read>(r: &mut R) -> Result<Option<i32>> {
+    Ok(match r.read_u8()? {
+        0 => None,
+        _ => Some(r.read_i32::<BigEndian>()?),
+    })
+}
+
+fn write_entry<W: Write>(w: &mut W, entry: &ServerEntry) -> Result<()> {
+    let info = &entry.info;
+
+    write_address(w, &entry.address)?;
+    w.write_u32::<BigEndian>(entry.ping.as_micros().min(u32::MAX as u128) as u32)?;
+    write_time(w, entry.queried_at)?;
+
+    write_str(w, &info.version)?;
+    w.write_i32::<BigEndian>(info.token)?;
+    write_str(w, &info.name)?;
+    write_str(w, &info.map)?;
+    w.write_i32::<BigEndian>(info.flags.bits())?;
+    write_str(w, &info.game_type)?;
+    w.write_i32::<BigEndian>(info.player_count)?;
+    w.write_i32::<BigEndian>(info.max_player_count)?;
+    w.write_i32::<BigEndian>(info.client_count)?;
+    w.write_i32::<BigEndian>(info.max_client_count)?;
+    write_opt_int(w, info.map_crc)?;
+    write_opt_int(w, info.map_size)?;
+
+    w.write_u16::<BigEndian>(info.players.len() as u16)?;
+    for player in &info.players {
+        write_str(w, &player.name)?;
+        write_str(w, &player.clan)?;
+        w.write_i32::<BigEndian>(player.country)?;
+        w.write_i32::<BigEndian>(player.score)?;
+        w.write_u8(player.is_spectator as u8)?;
+        write_str(w, &player.reserved)?;
+    }
+
+    Ok(())
+}
+
+fn read_entry<R: Read>(r: &mut R) -> Result<ServerEntry<'static>> {
+    let address = read_address(r)?;
+    let ping = Duration::from_micros(r.read_u32::<BigEndian>()?.into());
+    let queried_at = read_time(r)?;
+
+    let version = read_str(r)?;
+    let token = r.read_i32::<BigEndian>()?;
+    let name = read_str(r)?;
+    let map = read_str(r)?;
+    let flags = ServerFlags(r.read_i32::<BigEndian>()?);
+    let game_type = read_str(r)?;
+    let player_count = r.read_i32::<BigEndian>()?;
+    let max_player_count = r.read_i32::<BigEndian>()?;
+    let client_count = r.read_i32::<BigEndian>()?;
+    let max_client_count = r.read_i32::<BigEndian>()?;
+    let map_crc = read_opt_int(r)?;
+    let map_size = read_opt_int(r)?;
+
+    let count = r.read_u16::<BigEndian>()?;
+    let mut players = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        players.push(Player {
+            name: Cow::Owned(read_str(r)?),
+            clan: Cow::Owned(read_str(r)?),
+            country: r.read_i32::<BigEndian>()?,
+            score: r.read_i32::<BigEndian>()?,
+            is_spectator: r.read_u8()? != 0,
+            reserved: Cow::Owned(read_str(r)?),
+        });
+    }
+
+    Ok(ServerEntry {
+        address,
+        ping,
+        queried_at,
+        info: ServerInfo {
+            version: Cow::Owned(version),
+            token,
+            name: Cow::Owned(name),
+            map: Cow::Owned(map),
+            password: flags.contains(ServerFlags::PASSWORD),
+            flags,
+            game_type: Cow::Owned(game_type),
+            player_count,
+            max_player_count,
+            client_count,
+            max_client_count,
+            map_crc,
+            map_size,
+            players,
+            buffers: Vec::new(),
+            extensions: InfoExtensions::default(),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn sample_scan() -> Scan {
+        let data = include_bytes!("samples/server_info.data");
+        let data_more = include_bytes!("samples/server_info_more.data");
+        let mut info = ServerInfo::parse_main(data).unwrap();
+        info.parse_more(data_more).unwrap();
+
+        Scan {
+            taken_at: UNIX_EPOCH + Duration::from_secs(1_600_000_000),
+            masters: vec![MasterList {
+                master: "49.12.97.180:8300".to_owned(),
+                servers: vec![
+                    "127.0.0.1:8303".parse().unwrap(),
+                    "[2001:db8::1]:8303".parse().unwrap(),
+                ],
+            }],
+            servers: vec![ServerEntry {
+                address: "127.0.0.1:8303".parse().unwrap(),
+                ping: Duration::from_micros(25_300),
+                queried_at: UNIX_EPOCH + Duration::from_secs(1_600_000_001),
+                info: info.into_owned(),
+            }],
+        }
+    }
+
+    #[test]
+    fn roundtrips() {
+        let scan = sample_scan();
+        let mut buf = Vec::new();
+        scan.write_to(&mut buf).unwrap();
+
+        let loaded = Scan::read_from(&mut &buf[..]).unwrap();
+        assert_eq!(loaded.taken_at, scan.taken_at);
+        assert_eq!(loaded.masters, scan.masters);
+
+        let (a, b) = (&scan.servers[0], &loaded.servers[0]);
+        assert_eq!(a.address, b.address);
+        assert_eq!(a.ping, b.ping);
+        assert_eq!(a.queried_at, b.queried_at);
+        assert_eq!(a.info.version, b.info.version);
+        assert_eq!(a.info.token, b.info.token);
+        assert_eq!(a.info.name, b.info.name);
+        assert_eq!(a.info.map, b.info.map);
+        assert_eq!(a.info.password, b.info.password);
+        assert_eq!(a.info.flags, b.info.flags);
+        assert_eq!(a.info.game_type, b.info.game_type);
+        assert_eq!(
+            (a.info.player_count, a.info.max_player_count),
+            (b.info.player_count, b.info.max_player_count)
+        );
+        assert_eq!(
+            (a.info.client_count, a.info.max_client_count),
+            (b.info.client_count, b.info.max_client_count)
+        );
+        assert_eq!(
+            (a.info.map_crc, a.info.map_size),
+            (b.info.map_crc, b.info.map_size)
+        );
+        assert_eq!(a.info.players, b.info.players);
+        assert_eq!(scan.diff(&loaded), ScanDiff::default());
+
+        buf[0] = b'X';
+        assert!(matches!(
+            Scan::read_from(&mut &buf[..]),
+            Err(RequestError::InvalidData(_))
+        ));
+    }
+
+    #[test]
+    fn diffs_scans() {
+        let old = sample_scan();
+        let mut new = sample_scan();
+
+        let mut added = new.servers[0].clone();
+        added.address = "127.0.0.1:8304".parse().unwrap();
+        new.servers.push(added);
+
+        let server = &mut new.servers[0];
+        server.address = "127.0.0.1:8305".parse().unwrap();
+        let mut changed = old.servers[0].clone();
+        let left = changed.info.players.remove(0);
+        changed.info.client_count -= 1;
+        changed.info.player_count -= 1;
+        new.servers.push(changed);
+
+        let diff = old.diff(&new);
+        assert_eq!(
+            diff.added,
+            vec![
+                "127.0.0.1:8305".parse().unwrap(),
+                "127.0.0.1:8304".parse().unwrap()
+            ]
+        );
+        assert_eq!(diff.removed, Vec::<SocketAddr>::new());
+        assert_eq!(
+            diff.changed,
+            vec![PopulationChange {
+                address: "127.0.0.1:8303".parse().unwrap(),
+                clients_before: 63,
+                clients_after: 62,
+                players_before: 62,
+                players_after: 61,
+                joined: vec![],
+                left: vec![left.name.into_owned()],
+            }]
+        );
+
+        let diff = new.diff(&old);
+        assert_eq!(diff.removed.len(), 2);
+        assert!(diff.added.is_empty());
+    }
+}
@@ -0,0 +1,103 @@
+use std::time::Duration;
+
+use crate::server::*;
+
+/// Score value sent by race servers for players without a finish time.
+const NO_TIME_SCORE: i32 = -9999;
+
+/// A player score, interpreted according to the server gametype.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Score {
+    /// Regular points, as in vanilla gametypes.
+    Points(i32),
+    /// The best finish time on race servers.
+    Time(Duration),
+    /// The player has no score, e.g no finish time yet.
+    None,
+}
+
+impl Score {
+    /// Interprets a raw score as sent by a race server.
+    pub fn from_time_score(score: i32) -> Score {
+        // Race servers send the negated time in seconds.
+        if score == NO_TIME_SCORE || score == i32::MIN || score > 0 {
+            Score::None
+        } else {
+            Score::Time(Duration::from_secs(u64::from(score.unsigned_abs())))
+        }
+    }
+}
+
+impl<'a> ServerInfo<'a> {
+    /// Whether the player scores hold finish times instead of points.
+    ///
+    /// Uses the [ServerFlags::TIMESCORE] flag when the server sets it,
+    /// otherwise guesses from the gametype.
+    pub fn uses_time_score(&self) -> bool {
+        if self.flags.contains(ServerFlags::TIMESCORE) {
+            return true;
+        }
+
+        let game_type = self.game_type.to_lowercase();
+        game_type.contains("race") || game_type.contains("gores")
+    }
+
+    /// Interprets the score of the given player.
+    pub fn score(&self, player: &Player) -> Score {
+        interpret(self.uses_time_score(), player.score)
+    }
+
+    /// Returns each player along with its interpreted score.
+    pub fn scores(&self) -> impl Iterator<Item = (&Player<'a>, Score)> + '_ {
+        let time_score = self.uses_time_score();
+        self.players
+            .iter()
+            .map(move |player| (player, interpret(time_score, player.score)))
+    }
+}
+
+fn interpret(time_score: bool, score: i32) -> Score {
+    if time_score {
+        Score::from_time_score(score)
+    } else {
+        Score::Points(score)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn decodes_time_scores() {
+        assert_eq!(Score::from_time_score(-9999), Score::None);
+        assert_eq!(Score::from_time_score(i32::MIN), Score::None);
+        assert_eq!(
+            Score::from_time_score(-437),
+            Score::Time(Duration::from_secs(437))
+        );
+        assert_eq!(
+            Score::from_time_score(0),
+            Score::Time(Duration::from_secs(0))
+        );
+    }
+
+    #[test]
+    fn uses_gametype_and_flags() {
+        let data = include_bytes!("samples/server_info.data");
+        let mut info = ServerInfo::parse_main(data).unwrap();
+
+        assert!(info.uses_time_score());
+        let scores: Vec<_> = info.scores().map(|(_, score)| score).collect();
+        assert_eq!(scores[0], Score::None);
+        assert_eq!(scores[1], Score::Time(Duration::from_secs(437)));
+
+        info.game_type = "CTF".into();
+        assert!(!info.uses_time_score());
+        assert_eq!(info.score(&info.players[1]), Score::Points(-437));
+
+        info.flags = ServerFlags::TIMESCORE;
+        assert!(info.uses_time_score());
+    }
+}
@@ -1,34 +1,80 @@
 use log::debug;
 use nom::sequence::tuple;
 use nom::IResult;
-use nom::{
-    char, cond, do_parse, many_till, map_res, named, tag, take, take_str, take_until,
-    terminated,
-};
+use nom::{char, cond, do_parse, map_res, named, take, take_str, take_until, terminated};
+use std::any::Any;
+use std::borrow::Cow;
 use std::net::UdpSocket;
 
+use crate::decoder::*;
 use crate::errors::*;
+use crate::query::*;
 use crate::util::*;
+use crate::version::ServerVersion;
 
 /// Player info.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Player<'a> {
-    pub name: &'a str,
-    pub clan: &'a str,
+    pub name: Cow<'a, str>,
+    pub clan: Cow<'a, str>,
     pub country: i32,
     pub score: i32,
     pub is_spectator: bool,
-    pub reserved: &'a str,
+    pub reserved: Cow<'a, str>,
 }
 
-#[derive(Debug)]
+impl<'a> Player<'a> {
+    /// Copies the borrowed data, detaching the player from the receive buffers.
+    pub fn into_owned(self) -> Player<'static> {
+        Player {
+            name: Cow::Owned(self.name.into_owned()),
+            clan: Cow::Owned(self.clan.into_owned()),
+            country: self.country,
+            score: self.score,
+            is_spectator: self.is_spectator,
+            reserved: Cow::Owned(self.reserved.into_owned()),
+        }
+    }
+}
+
+/// Flags sent by the server in the info packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct ServerFlags(pub i32);
+
+impl ServerFlags {
+    /// The server requires a password to join.
+    pub const PASSWORD: ServerFlags = ServerFlags(1);
+    /// The player scores are finish times (DDNet).
+    pub const TIMESCORE: ServerFlags = ServerFlags(1 << 1);
+
+    /// The raw flag bits.
+    pub fn bits(self) -> i32 {
+        self.0
+    }
+
+    /// Whether all the flags in `other` are set.
+    pub fn contains(self, other: ServerFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl std::ops::BitOr for ServerFlags {
+    type Output = ServerFlags;
+
+    fn bitor(self, rhs: ServerFlags) -> ServerFlags {
+        ServerFlags(self.0 | rhs.0)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct ServerInfo<'a> {
-    pub version: &'a str,
+    pub version: Cow<'a, str>,
     pub token: i32,
-    pub name: &'a str,
-    pub map: &'a str,
+    pub name: Cow<'a, str>,
+    pub map: Cow<'a, str>,
     pub password: bool,
-    pub game_type: &'a str,
+    pub flags: ServerFlags,
+    pub game_type: Cow<'a, str>,
     pub player_count: i32,
     pub max_player_count: i32,
     pub client_count: i32,
@@ -37,6 +83,8 @@ pub struct ServerInfo<'a> {
     pub map_size: Option<i32>,
     pub players: Vec<Player<'a>>,
     pub buffers: Vec<Vec<u8>>,
+    /// The data decoded by the registered [InfoDecoder]s.
+    pub extensions: InfoExtensions,
 }
 
 named!(padding, take!(10));
@@ -74,61 +122,229 @@ named!(
             >> _reserved: next_str
             >> (ServerInfo {
                 token,
-                version,
-                name,
-                map,
+                version: Cow::Borrowed(version),
+                name: Cow::Borrowed(name),
+                map: Cow::Borrowed(map),
                 map_crc,
                 map_size,
                 player_count: num_players,
                 max_player_count: max_players,
                 client_count: num_clients,
                 max_client_count: max_clients,
-                password: (flags & 1) == 1,
-                game_type,
+                password: ServerFlags(flags).contains(ServerFlags::PASSWORD),
+                flags: ServerFlags(flags),
+                game_type: Cow::Borrowed(game_type),
                 players: Vec::new(),
-                buffers: Vec::new()
+                buffers: Vec::new(),
+                extensions: InfoExtensions::default()
             })
     )
 );
 
-named!(read_players<&[u8], (Vec<Player>, &[u8])>, many_till!(get_player, tag!("\0\0")));
+/// The number of clients a vanilla info packet lists.
+pub const VANILLA_MAX_CLIENTS: usize = 16;
+
+fn info_header(packet_type: PacketType, token: i32) -> Vec<u8> {
+    let mut packet = vec![0xff; 10];
+    packet.extend_from_slice(packet_type.value());
+    add_int(&mut packet, token);
+    packet
+}
+
+fn add_str(packet: &mut Vec<u8>, value: &str) {
+    packet.extend_from_slice(value.as_bytes());
+    packet.push(0);
+}
 
-fn get_player(i: &[u8]) -> IResult<&[u8], Player> {
+fn add_int(packet: &mut Vec<u8>, value: i32) {
+    add_str(packet, &value.to_string());
+}
+
+fn add_player(packet: &mut Vec<u8>, player: &Player<'_>) {
+    add_str(packet, &player.name);
+    add_str(packet, &player.clan);
+    add_int(packet, player.country);
+    add_int(packet, player.score);
+    add_int(packet, !player.is_spectator as i32);
+}
+
+/// Reads players until only padding is left.
+fn read_players(mut input: &[u8]) -> Result<Vec<Player<'_>>> {
+    let mut players = Vec::new();
+
+    while input.iter().any(|&x| x != 0) {
+        let (rest, player) =
+            get_player(input).map_err(|_| RequestError::InvalidData("malformed player info"))?;
+        players.push(player);
+        input = rest;
+    }
+
+    Ok(players)
+}
+
+fn get_player(i: &[u8]) -> IResult<&[u8], Player<'_>> {
     let (input, (name, clan, country, score, is_player, reserved)) =
         tuple((next_str, next_str, next_int, next_int, next_int, next_str))(i)?;
     IResult::Ok((
         input,
         Player {
-            name,
-            clan,
+            name: Cow::Borrowed(name),
+            clan: Cow::Borrowed(clan),
             country,
             score,
             is_spectator: is_player != 1,
-            reserved,
+            reserved: Cow::Borrowed(reserved),
         },
     ))
 }
 
+/// Parses the players of a more packet.
+pub(crate) fn parse_more_players(data: &[u8]) -> Result<Vec<Player<'_>>> {
+    let (input, _) = tuple((padding, response_type, next_int, next_int, next_str))(data)
+        .map_err(|_| RequestError::InvalidData("malformed info more packet"))?;
+
+    read_players(input)
+}
+
+/// Reads the token and packet number of a more packet.
+pub(crate) fn parse_more_header(data: &[u8]) -> Result<(i32, i32)> {
+    let (_, (_, _, token, number)) = tuple((padding, response_type, next_int, next_int))(data)
+        .map_err(|_| RequestError::InvalidData("malformed info more packet"))?;
+
+    Ok((token, number))
+}
+
 impl<'a> ServerInfo<'a> {
     /// Parses the main packet.
-    fn parse_main(data: &'a [u8]) -> Result<ServerInfo<'a>> {
-        let (input, mut server_info) = server_info(data).unwrap();
+    pub(crate) fn parse_main(data: &'a [u8]) -> Result<ServerInfo<'a>> {
+        let (input, mut server_info) =
+            server_info(data).map_err(|_| RequestError::InvalidData("malformed info packet"))?;
 
         if server_info.client_count > 0 {
-            let (_input, (ps, _)) = read_players(input).unwrap();
-            server_info.players.extend(ps);
+            server_info.players.extend(read_players(input)?);
         }
 
         Ok(server_info)
     }
 
     /// Parses the more packet.
-    fn parse_more(&mut self, data: &'a [u8]) {
-        let (input, _) =
-            tuple((padding, response_type, next_int, next_int, next_str))(data).unwrap();
+    pub(crate) fn parse_more(&mut self, data: &'a [u8]) -> Result<()> {
+        self.players.extend(parse_more_players(data)?);
+        Ok(())
+    }
+
+    /// Parses the version string into its components.
+    ///
+    /// Returns `None` if the server sent a version we can't make sense of.
+    pub fn parse_version(&self) -> Option<ServerVersion<'_>> {
+        ServerVersion::parse(&self.version)
+    }
 
-        let (_, (more_players, _)) = read_players(input).unwrap();
-        self.players.extend(more_players);
+    /// The format of the response the info was parsed from.
+    pub fn format(&self) -> InfoFormat {
+        // Only the extended format carries the map checksum.
+        if self.map_crc.is_some() {
+            InfoFormat::Extended
+        } else {
+            InfoFormat::Vanilla
+        }
+    }
+
+    /// The data of the given type decoded by an [InfoDecoder].
+    pub fn extension<T: Any>(&self) -> Option<&T> {
+        self.extensions.get()
+    }
+
+    /// Runs the decoders, replacing the extensions.
+    ///
+    /// Queries given decoders already run them, this is for infos built or changed
+    /// otherwise.
+    pub fn decode_extensions(&mut self, decoders: &InfoDecoders) {
+        self.extensions = decoders.decode(self);
+    }
+
+    /// Copies the borrowed data, detaching the info from the receive buffers.
+    pub fn into_owned(self) -> ServerInfo<'static> {
+        ServerInfo {
+            version: Cow::Owned(self.version.into_owned()),
+            token: self.token,
+            name: Cow::Owned(self.name.into_owned()),
+            map: Cow::Owned(self.map.into_owned()),
+            password: self.password,
+            flags: self.flags,
+            game_type: Cow::Owned(self.game_type.into_owned()),
+            player_count: self.player_count,
+            max_player_count: self.max_player_count,
+            client_count: self.client_count,
+            max_client_count: self.max_client_count,
+            map_crc: self.map_crc,
+            map_size: self.map_size,
+            players: self.players.into_iter().map(Player::into_owned).collect(),
+            buffers: self.buffers,
+            extensions: self.extensions,
+        }
+    }
+
+    /// Encodes the info the way a DDNet server answers a request with the given token,
+    /// an `iext` packet followed by as many `iex+` packets as the players need.
+    pub fn to_packets(&self, token: i32) -> Vec<Vec<u8>> {
+        let mut main = info_header(PacketType::InfoExtended, token);
+        add_str(&mut main, &self.version);
+        add_str(&mut main, &self.name);
+        add_str(&mut main, &self.map);
+        add_int(&mut main, self.map_crc.unwrap_or(0));
+        add_int(&mut main, self.map_size.unwrap_or(0));
+        self.add_counts(&mut main, self.client_count, self.max_client_count);
+        add_str(&mut main, "");
+
+        let mut packets = vec![main];
+        for player in &self.players {
+            let mut data = Vec::new();
+            add_player(&mut data, player);
+            add_str(&mut data, &player.reserved);
+
+            let last = packets.last_mut().expect("there is always a main packet");
+            if last.len() + data.len() <= MAX_DATAGRAM_SIZE {
+                last.extend(data);
+            } else {
+                let mut more = info_header(PacketType::InfoExtendedMore, token);
+                add_int(&mut more, packets.len() as i32);
+                add_str(&mut more, "");
+                more.extend(data);
+                packets.push(more);
+            }
+        }
+
+        packets
+    }
+
+    /// Encodes the info the way a vanilla server answers a request with the given token,
+    /// a single `inf3` packet listing up to [VANILLA_MAX_CLIENTS] clients.
+    pub fn to_legacy_packet(&self, token: i32) -> Vec<u8> {
+        let mut packet = info_header(PacketType::Info, token);
+        add_str(&mut packet, &self.version);
+        add_str(&mut packet, &self.name);
+        add_str(&mut packet, &self.map);
+        self.add_counts(
+            &mut packet,
+            self.client_count.min(VANILLA_MAX_CLIENTS as i32),
+            self.max_client_count.min(VANILLA_MAX_CLIENTS as i32),
+        );
+
+        for player in self.players.iter().take(VANILLA_MAX_CLIENTS) {
+            add_player(&mut packet, player);
+        }
+
+        packet
+    }
+
+    fn add_counts(&self, packet: &mut Vec<u8>, clients: i32, max_clients: i32) {
+        add_str(packet, &self.game_type);
+        add_int(packet, self.flags.bits());
+        add_int(packet, self.player_count.min(clients));
+        add_int(packet, self.max_player_count.min(max_clients));
+        add_int(packet, clients);
+        add_int(packet, max_clients);
     }
 
     /// Creates the necessary buffers that you need to hold and use to get the server info.
@@ -148,58 +364,17 @@ impl<'a> ServerInfo<'a> {
     /// Using the provided buffers to hold the response,
     /// this function parses the data received doing zero copy into a [ServerInfo].
     ///
-    /// See also [ServerInfo::create_buffers()]
+    /// Waits for the socket read timeout, or [DEFAULT_TIMEOUT] if it has none.
+    ///
+    /// See also [ServerInfo::create_buffers()] and [InfoQuery] for a socket-free version.
     pub fn new(sock: &UdpSocket, buffers: &'a mut [Vec<u8>]) -> Result<ServerInfo<'a>> {
-        let (buf, extra_token, token) = create_packet(PacketType::GetInfo, Some(b"xe"), true);
-        let token = token.expect("token should always have value here.");
-
-        log::debug!("generated extra_token={}, token={}", extra_token, token);
+        let timeout = sock.read_timeout()?.unwrap_or(DEFAULT_TIMEOUT);
+        let mut query = InfoQuery::new(sock.peer_addr()?, timeout);
+        drive(sock, &mut query)?;
 
-        // TODO: Use a single buffer with split_mut_at and use the recv value.
-
-        let sent = sock.send(&buf)?;
-
-        log::debug!("sent {} bytes", sent);
-        if sent != buf.len() {
-            log::warn!(
-                "bytes sent ({}) not equal to buffer size ({})!",
-                sent,
-                buf.len()
-            );
-        }
-
-        let iter = &mut buffers.iter_mut();
-
-        if let Some(data) = iter.next() {
-            let res = sock.recv(data)?;
-
-            log::debug!("received {} packets", res);
-            let mut info = ServerInfo::parse_main(data).unwrap();
-
-            debug!(
-                "Players parsed={} total_players={}",
-                info.players.len(),
-                info.max_client_count
-            );
-
-            if info.players.len() < info.client_count as usize {
-                for more_data in iter {
-                    let res = sock.recv(more_data)?;
-                    if res > 0 {
-                        info.parse_more(more_data);
-                    }
-                    debug!(
-                        "Players parsed={} total_players={}",
-                        info.players.len(),
-                        info.client_count
-                    );
-                }
-            }
+        debug!("received {} packets", query.received());
 
-            Ok(info)
-        } else {
-            unimplemented!()
-        }
+        query.into_info(buffers)
     }
 }
 
@@ -213,7 +388,7 @@ mod tests {
         let data = include_bytes!("samples/server_info.data");
         let data_more = include_bytes!("samples/server_info_more.data");
         let mut info = ServerInfo::parse_main(data).unwrap();
-        info.parse_more(data_more);
+        info.parse_more(data_more).unwrap();
 
         assert_eq!(info.client_count, 63);
         assert_eq!(info.game_type, "DDraceNetwork");
@@ -227,6 +402,42 @@ mod tests {
         );
     }
 
+    #[test]
+    fn encodes_info() {
+        let mut info = ServerInfo::parse_main(include_bytes!("samples/server_info.data")).unwrap();
+        info.parse_more(include_bytes!("samples/server_info_more.data"))
+            .unwrap();
+
+        let packets = info.to_packets(1234);
+        assert!(packets.len() > 1);
+        assert!(packets.iter().all(|x| x.len() <= MAX_DATAGRAM_SIZE));
+        assert_eq!(parse_more_header(&packets[1]).unwrap(), (1234, 1));
+
+        let mut decoded = ServerInfo::parse_main(&packets[0]).unwrap();
+        for more in &packets[1..] {
+            decoded.parse_more(more).unwrap();
+        }
+        assert_eq!(decoded.token, 1234);
+        assert_eq!(decoded.name, info.name);
+        assert_eq!(decoded.map_crc, info.map_crc);
+        assert_eq!(decoded.flags, info.flags);
+        assert_eq!(decoded.players.len(), 63);
+        assert!(decoded
+            .players
+            .iter()
+            .zip(&info.players)
+            .all(|(a, b)| a.name == b.name
+                && a.score == b.score
+                && a.is_spectator == b.is_spectator));
+
+        let legacy = info.to_legacy_packet(12);
+        assert_eq!(PacketType::Info, legacy[10..14]);
+        assert!(legacy.starts_with(b"\xff\xff\xff\xff\xff\xff\xff\xff\xff\xffinf312\0"));
+        // No reserved strings, one more zero per player would be there otherwise.
+        let zeros = legacy.iter().filter(|&&x| x == 0).count();
+        assert_eq!(zeros, 1 + 3 + 6 + VANILLA_MAX_CLIENTS * 5);
+    }
+
     /*
     #[test]
     fn it_works_2() {
@@ -0,0 +1,843 @@
+use std::collections::VecDeque;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::errors::*;
+use crate::protocol::*;
+use crate::query::*;
+
+/// How often the connect message is repeated until the server answers.
+pub const CONNECT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long the connection stays silent before a keepalive is sent.
+pub const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long unacknowledged vital chunks wait before being resent.
+pub const RESEND_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long the server can stay silent, or leave vital chunks unacknowledged,
+/// before the connection is dropped.
+pub const SESSION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The state of a [ClientSession].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionState {
+    Connecting,
+    Online,
+    Closed { reason: Option<String> },
+}
+
+/// What happened on a [ClientSession].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionEvent {
+    /// The server accepted the connection.
+    Connected,
+    /// A chunk of data arrived, vital chunks in the order they were sent.
+    Chunk { vital: bool, data: Vec<u8> },
+    /// The connection was closed by either side or timed out.
+    Closed { reason: Option<String> },
+}
+
+#[derive(Debug)]
+struct ResendChunk {
+    sequence: u16,
+    data: Vec<u8>,
+    first_sent: Instant,
+    last_sent: Instant,
+}
+
+/// A client connection to a game server, using the 0.6 protocol.
+///
+/// Like the queries, the session doesn't do any IO by itself: datagrams to send are
+/// taken with [ClientSession::poll_transmit] and received ones are fed with
+/// [ClientSession::handle_datagram]. [ClientSession::connect] and
+/// [ClientSession::pump] run it on a blocking socket.
+///
+/// Servers supporting the DDNet security token get it appended to every packet.
+#[derive(Debug)]
+pub struct ClientSession {
+    address: SocketAddr,
+    state: SessionState,
+    token: Option<SecurityToken>,
+    /// Last vital sequence received.
+    ack: u16,
+    /// Last vital sequence sent.
+    sequence: u16,
+    /// Vital chunks sent but not acknowledged yet.
+    resend: VecDeque<ResendChunk>,
+    /// Chunks waiting to be sent, with their header.
+    queued: Vec<(ChunkHeader, Vec<u8>)>,
+    /// Control packets waiting to be sent.
+    control: VecDeque<Vec<u8>>,
+    events: VecDeque<SessionEvent>,
+    resend_requested: bool,
+    ack_pending: bool,
+    started: Instant,
+    last_connect: Option<Instant>,
+    last_sent: Instant,
+    last_received: Instant,
+}
+
+impl ClientSession {
+    /// Starts connecting to the server at the given address.
+    pub fn new(address: SocketAddr, now: Instant) -> ClientSession {
+        ClientSession {
+            address,
+            state: SessionState::Connecting,
+            token: None,
+            ack: 0,
+            sequence: 0,
+            resend: VecDeque::new(),
+            queued: Vec::new(),
+            control: VecDeque::new(),
+            events: VecDeque::new(),
+            resend_requested: false,
+            ack_pending: false,
+            started: now,
+            last_connect: None,
+            last_sent: now,
+            last_received: now,
+        }
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn state(&self) -> &SessionState {
+        &self.state
+    }
+
+    /// The DDNet security token given by the server, if it supports it.
+    pub fn security_token(&self) -> Option<SecurityToken> {
+        self.token
+    }
+
+    /// Queues a chunk, vital chunks are resent until the server acknowledges them.
+    ///
+    /// Chunks are sent on the next [ClientSession::poll_transmit].
+    pub fn send(&mut self, data: &[u8], vital: bool) -> Result<()> {
+        if matches!(self.state, SessionState::Closed { .. }) {
+            return Err(RequestError::Disconnected);
+        }
+
+        // Room for the header and the chunk header.
+        if data.len() > MAX_PAYLOAD - 3 {
+            return Err(RequestError::InvalidData("chunk too big"));
+        }
+
+        let mut header = ChunkHeader {
+            vital,
+            resend: false,
+            size: data.len() as u16,
+            sequence: 0,
+        };
+
+        if vital {
+            self.sequence = (self.sequence + 1) % MAX_SEQUENCE;
+            header.sequence = self.sequence;
+        }
+
+        self.queued.push((header, data.to_vec()));
+        Ok(())
+    }
+
+    /// Closes the connection, telling the server why.
+    pub fn close(&mut self, reason: Option<&str>) {
+        if matches!(self.state, SessionState::Closed { .. }) {
+            return;
+        }
+
+        let reason = reason.map(str::to_owned);
+        let close = ControlMessage::Close {
+            reason: reason.clone(),
+        };
+        self.control.push_back(close.pack(self.ack, self.token));
+        self.set_closed(reason);
+    }
+
+    /// Returns the next event, if any.
+    pub fn poll_event(&mut self) -> Option<SessionEvent> {
+        self.events.pop_front()
+    }
+
+    fn set_closed(&mut self, reason: Option<String>) {
+        log::debug!("session closed: {:?}", reason);
+        self.state = SessionState::Closed {
+            reason: reason.clone(),
+        };
+        self.queued.clear();
+        self.resend.clear();
+        self.events.push_back(SessionEvent::Closed { reason });
+    }
+
+    fn transmit(&mut self, contents: Vec<u8>, now: Instant) -> Transmit {
+        self.last_sent = now;
+        Transmit {
+            destination: self.address,
+            contents,
+        }
+    }
+
+    /// Packs as many queued chunks as fit in one packet.
+    fn pack_chunks(&mut self, now: Instant) -> Vec<u8> {
+        let mut payload = Vec::new();
+        let mut num_chunks = 0;
+        let mut taken = 0;
+
+        for (header, data) in &self.queued {
+            if payload.len() + 3 + data.len() > MAX_PAYLOAD || num_chunks == u8::MAX {
+                break;
+            }
+
+            header.pack(&mut payload);
+            payload.extend_from_slice(data);
+            num_chunks += 1;
+            taken += 1;
+
+            if header.vital && !header.resend {
+                self.resend.push_back(ResendChunk {
+                    sequence: header.sequence,
+                    data: data.clone(),
+                    first_sent: now,
+                    last_sent: now,
+                });
+            }
+        }
+        self.queued.drain(..taken);
+
+        let mut flags = PacketFlags::default();
+        if self.resend_requested {
+            flags = flags | PacketFlags::RESEND;
+            self.resend_requested = false;
+        }
+
+        self.ack_pending = false;
+        Packet {
+            header: PacketHeader {
+                flags,
+                ack: self.ack,
+                num_chunks,
+            },
+            payload,
+        }
+        .pack(self.token)
+    }
+
+    /// Queues again every vital chunk not acknowledged yet, ahead of the new ones
+    /// so the server gets them in order.
+    fn queue_resends(&mut self, now: Instant) {
+        self.queued.retain(|(x, _)| !x.resend);
+
+        let resends: Vec<_> = self
+            .resend
+            .iter_mut()
+            .map(|chunk| {
+                chunk.last_sent = now;
+                let header = ChunkHeader {
+                    vital: true,
+                    resend: true,
+                    size: chunk.data.len() as u16,
+                    sequence: chunk.sequence,
+                };
+                (header, chunk.data.clone())
+            })
+            .collect();
+        self.queued.splice(..0, resends);
+    }
+
+    /// Returns the next datagram to send, if any.
+    pub fn poll_transmit(&mut self, now: Instant) -> Option<Transmit> {
+        if let Some(control) = self.control.pop_front() {
+            return Some(self.transmit(control, now));
+        }
+
+        match self.state {
+            SessionState::Closed { .. } => None,
+            SessionState::Connecting => {
+                let due = match self.last_connect {
+                    Some(x) => now.saturating_duration_since(x) >= CONNECT_INTERVAL,
+                    None => true,
+                };
+                if !due {
+                    return None;
+                }
+
+                self.last_connect = Some(now);
+                let connect = ControlMessage::Connect {
+                    security_token: true,
+                };
+                Some(self.transmit(connect.pack(0, None), now))
+            }
+            SessionState::Online => {
+                let resend_due = matches!(self.resend.front(),
+                    Some(x) if now.saturating_duration_since(x.last_sent) >= RESEND_INTERVAL);
+                if resend_due {
+                    log::debug!("resending {} vital chunks", self.resend.len());
+                    self.queue_resends(now);
+                }
+
+                if !self.queued.is_empty() || self.ack_pending || self.resend_requested {
+                    let packet = self.pack_chunks(now);
+                    return Some(self.transmit(packet, now));
+                }
+
+                if now.saturating_duration_since(self.last_sent) >= KEEPALIVE_INTERVAL {
+                    let keepalive = ControlMessage::KeepAlive.pack(self.ack, self.token);
+                    return Some(self.transmit(keepalive, now));
+                }
+
+                None
+            }
+        }
+    }
+
+    /// Feeds a datagram received from the server.
+    pub fn handle_datagram(&mut self, from: SocketAddr, data: &[u8], now: Instant) -> Result<()> {
+        if from != self.address {
+            return Err(RequestError::InvalidData("datagram from another address"));
+        }
+
+        if matches!(self.state, SessionState::Closed { .. }) {
+            return Err(RequestError::Disconnected);
+        }
+
+        let packet = Packet::unpack(data)?;
+        self.last_received = now;
+
+        if packet.header.flags.contains(PacketFlags::CONTROL) {
+            return self.handle_control(ControlMessage::unpack(&packet.payload)?, now);
+        }
+
+        if self.state == SessionState::Connecting {
+            return Err(RequestError::InvalidData(
+                "chunks before the connection is accepted",
+            ));
+        }
+
+        // Drop what the server acknowledged.
+        while matches!(self.resend.front(), Some(x) if is_sequence_acked(x.sequence, packet.header.ack))
+        {
+            self.resend.pop_front();
+        }
+
+        if packet.header.flags.contains(PacketFlags::RESEND) {
+            self.queue_resends(now);
+        }
+
+        for (header, chunk) in unpack_chunks(&packet.payload, packet.header.num_chunks)? {
+            if header.vital {
+                if header.sequence == (self.ack + 1) % MAX_SEQUENCE {
+                    self.ack = header.sequence;
+                    self.ack_pending = true;
+                } else if is_sequence_acked(header.sequence, self.ack) {
+                    // Already got it, the ack was probably lost.
+                    self.ack_pending = true;
+                    continue;
+                } else {
+                    log::debug!(
+                        "vital chunk {} out of order, expected {}",
+                        header.sequence,
+                        (self.ack + 1) % MAX_SEQUENCE
+                    );
+                    self.resend_requested = true;
+                    continue;
+                }
+            }
+
+            self.events.push_back(SessionEvent::Chunk {
+                vital: header.vital,
+                data: chunk.to_vec(),
+            });
+        }
+
+        Ok(())
+    }
+
+    fn handle_control(&mut self, message: ControlMessage, now: Instant) -> Result<()> {
+        match message {
+            ControlMessage::ConnectAccept { token } if self.state == SessionState::Connecting => {
+                log::debug!("connection accepted, security token: {:?}", token);
+                self.token = token;
+                self.state = SessionState::Online;
+                self.last_sent = now;
+                self.control
+                    .push_back(ControlMessage::Accept.pack(self.ack, self.token));
+                self.events.push_back(SessionEvent::Connected);
+            }
+            ControlMessage::Close { reason } => {
+                self.set_closed(reason);
+            }
+            // Keepalives only refresh the timeout, accepts may be repeated.
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Checks the timeouts, closing the connection if the server is gone.
+    pub fn poll(&mut self, now: Instant) -> &SessionState {
+        let timed_out = match self.state {
+            SessionState::Connecting => {
+                now.saturating_duration_since(self.started) >= SESSION_TIMEOUT
+            }
+            SessionState::Online => {
+                now.saturating_duration_since(self.last_received) >= SESSION_TIMEOUT
+                    || matches!(self.resend.front(),
+                        Some(x) if now.saturating_duration_since(x.first_sent) >= SESSION_TIMEOUT)
+            }
+            SessionState::Closed { .. } => false,
+        };
+
+        if timed_out {
+            self.set_closed(Some("timeout".to_owned()));
+        }
+
+        &self.state
+    }
+
+    /// When something has to be sent or checked next.
+    pub fn poll_timeout(&self) -> Option<Instant> {
+        match self.state {
+            SessionState::Closed { .. } => None,
+            SessionState::Connecting => {
+                let connect = self.last_connect.map(|x| x + CONNECT_INTERVAL);
+                let timeout = self.started + SESSION_TIMEOUT;
+                Some(connect.map_or(timeout, |x| x.min(timeout)))
+            }
+            SessionState::Online => {
+                let mut next =
+                    (self.last_sent + KEEPALIVE_INTERVAL).min(self.last_received + SESSION_TIMEOUT);
+                if let Some(x) = self.resend.front() {
+                    next = next.min(x.last_sent + RESEND_INTERVAL);
+                }
+                Some(next)
+            }
+        }
+    }
+
+    /// Connects to the server on a blocking socket.
+    ///
+    /// Waits for the socket read timeout, or [DEFAULT_TIMEOUT] if it has none.
+    pub fn connect(sock: &UdpSocket, address: SocketAddr) -> Result<ClientSession> {
+        let timeout = sock.read_timeout()?.unwrap_or(DEFAULT_TIMEOUT);
+        let deadline = Instant::now() + timeout;
+        let mut session = ClientSession::new(address, Instant::now());
+
+        while session.state == SessionState::Connecting {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RequestError::Timeout);
+            }
+            session.pump(sock, deadline - now)?;
+        }
+
+        match session.state.clone() {
+            SessionState::Closed { reason } => Err(RequestError::Refused(
+                reason.unwrap_or_else(|| "no reason given".to_owned()),
+            )),
+            _ => Ok(session),
+        }
+    }
+
+    /// Sends what's due and handles the datagrams received for up to `wait`,
+    /// returning early once an event is available.
+    ///
+    /// The socket read timeout is restored afterwards.
+    pub fn pump(&mut self, sock: &UdpSocket, wait: Duration) -> Result<()> {
+        let read_timeout = sock.read_timeout()?;
+        let result = self.pump_inner(sock, wait);
+        sock.set_read_timeout(read_timeout)?;
+        result
+    }
+
+    fn pump_inner(&mut self, sock: &UdpSocket, wait: Duration) -> Result<()> {
+        let deadline = Instant::now() + wait;
+        let mut buf = [0; MAX_PACKET_SIZE];
+
+        loop {
+            let now = Instant::now();
+            self.poll(now);
+            while let Some(transmit) = self.poll_transmit(now) {
+                sock.send_to(&transmit.contents, transmit.destination)?;
+            }
+
+            if !self.events.is_empty() || now >= deadline {
+                return Ok(());
+            }
+
+            let until = self.poll_timeout().map_or(deadline, |x| x.min(deadline));
+            let timeout = until.saturating_duration_since(now);
+            sock.set_read_timeout(Some(timeout.max(Duration::from_millis(1))))?;
+
+            match sock.recv_from(&mut buf) {
+                Ok((len, from)) => {
+                    if let Err(e) = self.handle_datagram(from, &buf[..len], Instant::now()) {
+                        log::debug!("ignoring datagram from {}: {}", from, e);
+                    }
+                }
+                Err(e)
+                    if e.kind() == std::io::ErrorKind::WouldBlock
+                        || e.kind() == std::io::ErrorKind::TimedOut => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const TOKEN: SecurityToken = [0xde, 0xad, 0xbe, 0xef];
+
+    fn chunk_packet(ack: u16, chunks: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut payload = Vec::new();
+        for (sequence, data) in chunks {
+            ChunkHeader {
+                vital: true,
+                resend: false,
+                size: data.len() as u16,
+                sequence: *sequence,
+            }
+            .pack(&mut payload);
+            payload.extend_from_slice(data);
+        }
+
+        Packet {
+            header: PacketHeader {
+                flags: PacketFlags::default(),
+                ack,
+                num_chunks: chunks.len() as u8,
+            },
+            payload,
+        }
+        .pack(Some(TOKEN))
+    }
+
+    fn online_session(now: Instant) -> ClientSession {
+        let address = "127.0.0.1:8303".parse().unwrap();
+        let mut session = ClientSession::new(address, now);
+        session.poll_transmit(now).unwrap();
+
+        let accept = ControlMessage::ConnectAccept { token: Some(TOKEN) }.pack(0, None);
+        session.handle_datagram(address, &accept, now).unwrap();
+        assert_eq!(session.poll_event(), Some(SessionEvent::Connected));
+
+        let accept = session.poll_transmit(now).unwrap();
+        assert_eq!(accept.contents, b"\x10\x00\x00\x03\xde\xad\xbe\xef");
+        session
+    }
+
+    #[test]
+    fn connects_and_retries() {
+        let now = Instant::now();
+        let address = "127.0.0.1:8303".parse().unwrap();
+        let mut session = ClientSession::new(address, now);
+
+        let connect = session.poll_transmit(now).unwrap();
+        assert_eq!(connect.contents, b"\x10\x00\x00\x01TKEN");
+        assert!(session.poll_transmit(now).is_none());
+
+        let later = now + CONNECT_INTERVAL;
+        assert!(session.poll_transmit(later).is_some());
+
+        assert_eq!(
+            session.poll(now + SESSION_TIMEOUT),
+            &SessionState::Closed {
+                reason: Some("timeout".to_owned())
+            }
+        );
+    }
+
+    #[test]
+    fn acks_and_orders_vital_chunks() {
+        let now = Instant::now();
+        let mut session = online_session(now);
+        let address = session.address();
+
+        // Chunk 2 before 1 asks for a resend.
+        session
+            .handle_datagram(address, &chunk_packet(0, &[(2, b"b")]), now)
+            .unwrap();
+        assert_eq!(session.poll_event(), None);
+        let resend = session.poll_transmit(now).unwrap();
+        let header = PacketHeader::unpack(&resend.contents).unwrap();
+        assert!(header.flags.contains(PacketFlags::RESEND));
+        assert_eq!(header.ack, 0);
+        assert!(resend.contents.ends_with(&TOKEN));
+
+        session
+            .handle_datagram(address, &chunk_packet(0, &[(1, b"a"), (2, b"b")]), now)
+            .unwrap();
+        assert_eq!(
+            session.poll_event(),
+            Some(SessionEvent::Chunk {
+                vital: true,
+                data: b"a".to_vec()
+            })
+        );
+        assert_eq!(
+            session.poll_event(),
+            Some(SessionEvent::Chunk {
+                vital: true,
+                data: b"b".to_vec()
+            })
+        );
+
+        // The ack goes out right away.
+        let ack = session.poll_transmit(now).unwrap();
+        assert_eq!(PacketHeader::unpack(&ack.contents).unwrap().ack, 2);
+        assert!(session.poll_transmit(now).is_none());
+
+        // Then keepalives.
+        let later = now + KEEPALIVE_INTERVAL;
+        let keepalive = session.poll_transmit(later).unwrap();
+        assert_eq!(keepalive.contents, b"\x10\x02\x00\x00\xde\xad\xbe\xef");
+    }
+
+    #[test]
+    fn resends_until_acked() {
+        let now = Instant::now();
+        let mut session = online_session(now);
+        let address = session.address();
+
+        session.send(b"\x03hi", true).unwrap();
+        session.send(b"\x05", false).unwrap();
+        let sent = session.poll_transmit(now).unwrap();
+        let packet = Packet::unpack(&sent.contents).unwrap();
+        let chunks = unpack_chunks(&packet.payload, packet.header.num_chunks).unwrap();
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].0.sequence, 1);
+        assert!(chunks[0].0.vital);
+        assert!(!chunks[1].0.vital);
+
+        // Not acked in time.
+        let later = now + RESEND_INTERVAL;
+        let resent = session.poll_transmit(later).unwrap();
+        let packet = Packet::unpack(&resent.contents).unwrap();
+        let chunks = unpack_chunks(&packet.payload, packet.header.num_chunks).unwrap();
+        assert_eq!(chunks.len(), 1);
+        assert!(chunks[0].0.resend);
+        assert_eq!(chunks[0].1, b"\x03hi");
+
+        // Acked.
+        session
+            .handle_datagram(address, &chunk_packet(1, &[]), later)
+            .unwrap();
+        let much_later = later + RESEND_INTERVAL;
+        let keepalive = session.poll_transmit(much_later).unwrap();
+        assert!(PacketHeader::unpack(&keepalive.contents)
+            .unwrap()
+            .flags
+            .contains(PacketFlags::CONTROL));
+        assert_eq!(session.poll(much_later), &SessionState::Online);
+
+        session.close(Some("bye"));
+        assert_eq!(
+            session.poll_transmit(much_later).unwrap().contents,
+            b"\x10\x00\x00\x04bye\x00\xde\xad\xbe\xef"
+        );
+        assert!(session.send(b"x", true).is_err());
+    }
+
+    #[test]
+    fn talks_to_local_server() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap();
+
+        let stand_in = std::thread::spawn(move || {
+            let mut buf = [0; MAX_PACKET_SIZE];
+
+            let (len, client) = server.recv_from(&mut buf).unwrap();
+            let packet = Packet::unpack(&buf[..len]).unwrap();
+            assert_eq!(
+                ControlMessage::unpack(&packet.payload).unwrap(),
+                ControlMessage::Connect {
+                    security_token: true
+                }
+            );
+
+            let accept = ControlMessage::ConnectAccept { token: Some(TOKEN) }.pack(0, None);
+            server.send_to(&accept, client).unwrap();
+
+            let (len, _) = server.recv_from(&mut buf).unwrap();
+            assert_eq!(&buf[..len], b"\x10\x00\x00\x03\xde\xad\xbe\xef");
+
+            // The client sends its info.
+            let (len, _) = server.recv_from(&mut buf).unwrap();
+            let packet = Packet::unpack(&buf[..len]).unwrap();
+            let chunks = unpack_chunks(&packet.payload, packet.header.num_chunks).unwrap();
+            assert_eq!(chunks[0].1, b"info");
+
+            server
+                .send_to(&chunk_packet(1, &[(1, b"welcome")]), client)
+                .unwrap();
+
+            let close = ControlMessage::Close {
+                reason: Some("shutdown".to_owned()),
+            };
+            server.send_to(&close.pack(1, Some(TOKEN)), client).unwrap();
+        });
+
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sock.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let mut session = ClientSession::connect(&sock, address).unwrap();
+        assert_eq!(session.security_token(), Some(TOKEN));
+        assert_eq!(session.poll_event(), Some(SessionEvent::Connected));
+
+        session.send(b"info", true).unwrap();
+
+        let mut events = Vec::new();
+        while !matches!(session.state(), SessionState::Closed { .. }) {
+            session.pump(&sock, Duration::from_secs(2)).unwrap();
+            events.extend(std::iter::from_fn(|| session.poll_event()));
+        }
+        stand_in.join().unwrap();
+
+        assert_eq!(
+            events,
+            vec![
+                SessionEvent::Chunk {
+                    vital: true,
+                    data: b"welcome".to_vec()
+                },
+                SessionEvent::Closed {
+                    reason: Some("shutdown".to_owned())
+                }
+            ]
+        );
+        assert_eq!(sock.read_timeout().unwrap(), Some(Duration::from_secs(2)));
+    }
+}
+
+/// A stand-in server, just enough to test the clients built on sessions.
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    pub const STAND_IN_TOKEN: SecurityToken = [1, 2, 3, 4];
+
+    pub struct StandIn {
+        pub sock: UdpSocket,
+        pub client: SocketAddr,
+        sequence: u16,
+        ack: u16,
+        received: VecDeque<(i32, bool, Vec<u8>)>,
+    }
+
+    impl StandIn {
+        /// Waits for a client to connect and accepts it.
+        pub fn accept(sock: UdpSocket) -> StandIn {
+            let mut buf = [0; MAX_PACKET_SIZE];
+            let (_, client) = sock.recv_from(&mut buf).unwrap();
+            let accept = ControlMessage::ConnectAccept {
+                token: Some(STAND_IN_TOKEN),
+            };
+            sock.send_to(&accept.pack(0, None), client).unwrap();
+
+            StandIn {
+                sock,
+                client,
+                sequence: 0,
+                ack: 0,
+                received: VecDeque::new(),
+            }
+        }
+
+        /// Sends a message in a vital chunk.
+        pub fn send(&mut self, packer: &Packer) {
+            self.sequence += 1;
+            let mut payload = Vec::new();
+            ChunkHeader {
+                vital: true,
+                resend: false,
+                size: packer.len() as u16,
+                sequence: self.sequence,
+            }
+            .pack(&mut payload);
+            payload.extend_from_slice(packer.as_bytes());
+
+            let packet = Packet {
+                header: PacketHeader {
+                    flags: PacketFlags::default(),
+                    ack: self.ack,
+                    num_chunks: 1,
+                },
+                payload,
+            };
+            self.sock
+                .send_to(&packet.pack(Some(STAND_IN_TOKEN)), self.client)
+                .unwrap();
+        }
+
+        pub fn close(&self, reason: &str) {
+            let close = ControlMessage::Close {
+                reason: Some(reason.to_owned()),
+            };
+            self.sock
+                .send_to(&close.pack(self.ack, Some(STAND_IN_TOKEN)), self.client)
+                .unwrap();
+        }
+
+        /// Returns the next message received in a vital chunk, with its id and
+        /// whether it's a system message.
+        pub fn recv(&mut self) -> (i32, bool, Vec<u8>) {
+            let mut buf = [0; MAX_PACKET_SIZE];
+            while self.received.is_empty() {
+                let (len, _) = self.sock.recv_from(&mut buf).unwrap();
+                let packet = match Packet::unpack(&buf[..len]) {
+                    Ok(packet) => packet,
+                    Err(_) => continue,
+                };
+                if packet.header.flags.contains(PacketFlags::CONTROL) {
+                    continue;
+                }
+
+                for (header, data) in
+                    unpack_chunks(&packet.payload, packet.header.num_chunks).unwrap()
+                {
+                    if header.vital && header.sequence == self.ack + 1 {
+                        self.ack = header.sequence;
+                        let (id, system, unpacker) = unpack_message(data).unwrap();
+                        self.received
+                            .push_back((id, system, unpacker.remaining().to_vec()));
+                    }
+                }
+            }
+            self.received.pop_front().unwrap()
+        }
+
+        pub fn expect_system(&mut self, message: SystemMessage) -> Vec<u8> {
+            let (id, system, data) = self.recv();
+            assert_eq!((SystemMessage::from_id(id), system), (Some(message), true));
+            data
+        }
+
+        pub fn expect_game(&mut self, message: GameMessage) -> Vec<u8> {
+            let (id, system, data) = self.recv();
+            assert_eq!((GameMessage::from_id(id), system), (Some(message), false));
+            data
+        }
+
+        /// Answers the info, sends the map and waits for the client to be ready.
+        pub fn get_ready(&mut self, map: &str) {
+            let info = self.expect_system(SystemMessage::Info);
+            let mut unpacker = Unpacker::new(&info);
+            assert_eq!(unpacker.get_string().unwrap(), GAME_VERSION);
+
+            self.change_map(map);
+        }
+
+        pub fn change_map(&mut self, map: &str) {
+            let mut map_change = pack_system_message(SystemMessage::MapChange);
+            map_change.add_string(map).add_int(0x1234).add_int(5805);
+            self.send(&map_change);
+
+            self.expect_system(SystemMessage::Ready);
+            self.send(&pack_system_message(SystemMessage::ConReady));
+        }
+    }
+}
@@ -51,7 +51,7 @@ pub fn create_packet(packet: PacketType, magic_bytes: Option<&[u8]>, add_token:
     let mut rng = rand::thread_rng();
     let extra_token = rng.gen::<u16>();
     if let Some(magic_bytes) = magic_bytes {
-        buf.put(&magic_bytes[..]);
+        buf.put(magic_bytes);
     }
     buf.put_u16(extra_token); // extra token
     // reserved
@@ -62,7 +62,7 @@ pub fn create_packet(packet: PacketType, magic_bytes: Option<&[u8]>, add_token:
     buf.put_u8(0xff);
     buf.put_u8(0xff);
     buf.put_u8(0xff);
-    buf.put(&packet.value()[..]); // vanilla request
+    buf.put(packet.value()); // vanilla request
     let mut token = None;
     if add_token {
         let val = rng.gen::<u8>();
@@ -72,6 +72,129 @@ pub fn create_packet(packet: PacketType, magic_bytes: Option<&[u8]>, add_token:
     (buf, extra_token, token)
 }
 
+/// Splits a line into whitespace separated arguments.
+///
+/// Double quotes group characters, inside them a backslash escapes the next character.
+/// Returns `None` if a quote is left open.
+pub(crate) fn split_args(line: &str) -> Option<Vec<String>> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_arg = false;
+    let mut in_quotes = false;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                in_arg = true;
+            }
+            '\\' if in_quotes => current.push(chars.next()?),
+            c if c.is_whitespace() && !in_quotes => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            c => {
+                current.push(c);
+                in_arg = true;
+            }
+        }
+    }
+
+    if in_quotes {
+        return None;
+    }
+
+    if in_arg {
+        args.push(current);
+    }
+
+    Some(args)
+}
+
+/// Splits a console line into its commands, separated by `;` outside quotes.
+///
+/// Comments starting with `#` are dropped.
+pub(crate) fn split_commands(line: &str) -> Vec<&str> {
+    let mut commands = Vec::new();
+    let mut start = 0;
+    let mut in_quotes = false;
+    let mut escaped = false;
+
+    for (i, c) in line.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '\\' if in_quotes => escaped = true,
+            ';' if !in_quotes => {
+                commands.push(&line[start..i]);
+                start = i + 1;
+            }
+            '#' if !in_quotes => {
+                commands.push(&line[start..i]);
+                start = line.len();
+                break;
+            }
+            _ => {}
+        }
+    }
+
+    commands.push(&line[start..]);
+    commands.retain(|x| !x.trim().is_empty());
+    commands
+}
+
+/// Quotes an argument so [split_args] reads it back unchanged.
+pub(crate) fn quote_arg(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\') {
+        return arg.to_owned();
+    }
+
+    let mut quoted = String::with_capacity(arg.len() + 2);
+    quoted.push('"');
+    for c in arg.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Case insensitive glob matching supporting `*` and `?`.
+pub(crate) fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((bp, bt)) = backtrack {
+            p = bp + 1;
+            t = bt + 1;
+            backtrack = Some((bp, bt + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -132,4 +255,38 @@ mod tests {
 
         assert_eq!(PacketType::GetInfo, buf[8..12]);
     }
+
+    #[test]
+    fn splits_args() {
+        assert_eq!(
+            split_args(r#"add_friend "nameless tee" "" map:"a \"b\"""#).unwrap(),
+            vec!["add_friend", "nameless tee", "", r#"map:a "b""#]
+        );
+        assert_eq!(split_args("  a   b ").unwrap(), vec!["a", "b"]);
+        assert!(split_args(r#"a "b"#).is_none());
+
+        for arg in &["", "a b", r#"q"uo\te"#, "plain"] {
+            assert_eq!(split_args(&quote_arg(arg)).unwrap(), vec![*arg]);
+        }
+    }
+
+    #[test]
+    fn splits_commands() {
+        assert_eq!(
+            split_commands(r#"echo "a;b # c"; add_friend x;  # comment"#),
+            vec![r#"echo "a;b # c""#, " add_friend x"]
+        );
+        assert_eq!(split_commands(r#"say "\";" ; b"#), vec![r#"say "\";" "#, " b"]);
+        assert!(split_commands("# only a comment").is_empty());
+    }
+
+    #[test]
+    fn globs_match() {
+        assert!(glob_match("ctf_*", "ctf_5"));
+        assert!(glob_match("CTF?", "ctf5"));
+        assert!(glob_match("*easy*", "Multeasymap"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("ctf_*", "dm1"));
+        assert!(!glob_match("dm?", "dm"));
+    }
 }
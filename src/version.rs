@@ -0,0 +1,191 @@
+use std::fmt;
+
+/// A dotted version number, like `0.6.4` or `16.3`.
+///
+/// Missing components are treated as zero, so `16.3` equals `16.3.0`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl Version {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Version {
+        Version {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Parses a version at the start of the string, returning it and the remaining input.
+    fn parse_prefix(s: &str) -> Option<(Version, &str)> {
+        let mut parts = [0u32; 3];
+        let mut rest = s;
+
+        for (i, part) in parts.iter_mut().enumerate() {
+            if i > 0 {
+                match rest.strip_prefix('.') {
+                    Some(r) if r.starts_with(|c: char| c.is_ascii_digit()) => rest = r,
+                    _ => break,
+                }
+            }
+
+            let end = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            if end == 0 {
+                return None;
+            }
+            *part = rest[..end].parse().ok()?;
+            rest = &rest[end..];
+        }
+
+        Some((Version::new(parts[0], parts[1], parts[2]), rest))
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// The kind of client trying to reach a server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientKind {
+    /// A vanilla teeworlds 0.6 client.
+    Vanilla06,
+    /// A vanilla teeworlds 0.7 client.
+    Vanilla07,
+    /// A DDNet client of the given version.
+    DDNet(Version),
+}
+
+/// A server version string split in its components.
+///
+/// Handles the formats seen in the wild:
+/// - `0.6.4` or `0.7.5` for vanilla servers.
+/// - `0.6.4, 16.3` for DDNet servers.
+/// - `0.6.4 [iDDRace]` or `0.6.4, 15.5.4 mymod` for mods.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerVersion<'a> {
+    /// The base network version, e.g `0.6.4`.
+    pub net_version: Version,
+    /// The DDNet release version, if any.
+    pub ddnet_version: Option<Version>,
+    /// Anything after the known parts, usually a mod name.
+    pub suffix: Option<&'a str>,
+}
+
+impl<'a> ServerVersion<'a> {
+    /// Parses a version string as sent in the server info.
+    ///
+    /// Returns `None` if the string doesn't start with a version number.
+    pub fn parse(s: &'a str) -> Option<ServerVersion<'a>> {
+        let (net_version, rest) = Version::parse_prefix(s.trim_start())?;
+
+        let (ddnet_version, rest) = match rest.strip_prefix(',') {
+            Some(r) => match Version::parse_prefix(r.trim_start()) {
+                Some((version, r)) => (Some(version), r),
+                None => (None, r),
+            },
+            None => (None, rest),
+        };
+
+        let suffix = rest.trim_matches(|c: char| c.is_whitespace() || c == ',' || c == '-');
+
+        Some(ServerVersion {
+            net_version,
+            ddnet_version,
+            suffix: if suffix.is_empty() {
+                None
+            } else {
+                Some(suffix)
+            },
+        })
+    }
+
+    /// Whether the server speaks the 0.6 protocol.
+    pub fn is_06(&self) -> bool {
+        self.net_version.major == 0 && self.net_version.minor == 6
+    }
+
+    /// Whether the server speaks the 0.7 protocol.
+    pub fn is_07(&self) -> bool {
+        self.net_version.major == 0 && self.net_version.minor == 7
+    }
+
+    /// Whether the server reports a DDNet version.
+    pub fn is_ddnet(&self) -> bool {
+        self.ddnet_version.is_some()
+    }
+
+    /// Whether the server runs DDNet with at least the given version.
+    pub fn is_ddnet_at_least(&self, version: Version) -> bool {
+        matches!(self.ddnet_version, Some(v) if v >= version)
+    }
+
+    /// Whether the given client can connect to this server.
+    ///
+    /// DDNet clients speak the 0.6 protocol, so they can reach any 0.6 server, and
+    /// since 18.0 they also connect to 0.7 servers.
+    /// To require a minimum DDNet version on the server side use [ServerVersion::is_ddnet_at_least].
+    pub fn is_reachable_by(&self, client: ClientKind) -> bool {
+        match client {
+            ClientKind::Vanilla06 => self.is_06(),
+            ClientKind::Vanilla07 => self.is_07(),
+            ClientKind::DDNet(version) => {
+                self.is_06() || (self.is_07() && version >= Version::new(18, 0, 0))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn parses_versions() {
+        let v = ServerVersion::parse("0.6.4, 16.3").unwrap();
+        assert_eq!(v.net_version, Version::new(0, 6, 4));
+        assert_eq!(v.ddnet_version, Some(Version::new(16, 3, 0)));
+        assert_eq!(v.suffix, None);
+
+        let v = ServerVersion::parse("0.7.5").unwrap();
+        assert_eq!(v.net_version, Version::new(0, 7, 5));
+        assert_eq!(v.ddnet_version, None);
+        assert!(v.is_07());
+
+        let v = ServerVersion::parse("0.6.4 [iDDRace]").unwrap();
+        assert_eq!(v.ddnet_version, None);
+        assert_eq!(v.suffix, Some("[iDDRace]"));
+
+        let v = ServerVersion::parse("0.6.4, 15.5.4 - mymod 1.0").unwrap();
+        assert_eq!(v.ddnet_version, Some(Version::new(15, 5, 4)));
+        assert_eq!(v.suffix, Some("mymod 1.0"));
+
+        assert!(ServerVersion::parse("zCatch").is_none());
+        assert!(ServerVersion::parse("").is_none());
+    }
+
+    #[test]
+    fn checks_compatibility() {
+        let ddnet = ServerVersion::parse("0.6.4, 16.3").unwrap();
+        assert!(ddnet.is_reachable_by(ClientKind::Vanilla06));
+        assert!(ddnet.is_reachable_by(ClientKind::DDNet(Version::new(16, 3, 0))));
+        assert!(!ddnet.is_reachable_by(ClientKind::Vanilla07));
+        assert!(ddnet.is_ddnet_at_least(Version::new(15, 0, 0)));
+        assert!(!ddnet.is_ddnet_at_least(Version::new(16, 4, 0)));
+
+        let vanilla = ServerVersion::parse("0.7.5").unwrap();
+        assert!(vanilla.is_reachable_by(ClientKind::Vanilla07));
+        assert!(!vanilla.is_reachable_by(ClientKind::Vanilla06));
+        assert!(!vanilla.is_reachable_by(ClientKind::DDNet(Version::new(17, 4, 0))));
+        assert!(vanilla.is_reachable_by(ClientKind::DDNet(Version::new(18, 0, 0))));
+        assert!(!vanilla.is_ddnet_at_least(Version::new(15, 0, 0)));
+    }
+}